@@ -74,6 +74,9 @@ class BoardInfo:
     loader_link_address: int
     kernel_options: KERNEL_OPTIONS
     examples: Dict[str, Path]
+    # (base, size) of physical memory that is owned by firmware but that
+    # the kernel considers available
+    reserved_memory: Tuple[Tuple[int, int], ...] = ()
 
 
 @dataclass
@@ -437,6 +440,13 @@ def build_sel4(
     copy(invocations_all, dest)
     dest.chmod(0o744)
 
+    dest = (root_dir / "board" / board.name / config.name / "reserved_memory.json")
+    dest.unlink(missing_ok=True)
+    with open(dest, "w") as f:
+        reserved_memory = [{"base": str(base), "size": str(size)} for base, size in board.reserved_memory]
+        json.dump(reserved_memory, f)
+    dest.chmod(0o744)
+
     include_dir = root_dir / "board" / board.name / config.name / "include"
     for source in ("kernel_Config", "libsel4", "libsel4/sel4_Config", "libsel4/autoconf"):
         source_dir = sel4_install_dir / source / "include"
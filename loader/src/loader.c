@@ -120,6 +120,14 @@ static void memcpy(void *dst, const void *src, size_t sz)
     }
 }
 
+static void memzero(void *dst, size_t sz)
+{
+    char *dst_ = dst;
+    while (sz-- > 0) {
+        *dst_++ = 0;
+    }
+}
+
 #if defined(BOARD_tqma8xqp1gb)
 #define UART_BASE 0x5a070000
 #define STAT 0x14
@@ -497,6 +505,13 @@ static void copy_data(void)
     const void *base = &loader_data->regions[loader_data->num_regions];
     for (uint32_t i = 0; i < loader_data->num_regions; i++) {
         const struct region *r = &loader_data->regions[i];
+        if (r->type == REGION_TYPE_ZERO) {
+            puts("LDR|INFO: zeroing region ");
+            puthex32(i);
+            puts("\n");
+            memzero((void *)(uintptr_t)r->load_addr, r->size);
+            continue;
+        }
         puts("LDR|INFO: copying region ");
         puthex32(i);
         puts("\n");
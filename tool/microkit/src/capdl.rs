@@ -0,0 +1,495 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+// Export of the built system as a capDL specification.
+//
+// The state of each object is taken from the simulator once it has performed
+// all of the invocations, so the specification describes the system as the
+// monitor leaves it just before the protection domains start running.
+
+use crate::sel4::{Arch, ArmVmAttributes, Config, IrqTrigger, Object, ObjectType, Rights};
+use crate::sim::{self, Cap, CapType, Mapping, Simulator};
+use crate::util::mask;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+
+/// Turn the name of an object into a capDL identifier.
+fn identifier(name: &str) -> String {
+    let mut identifier = String::new();
+    for c in name.chars() {
+        if c.is_ascii_alphanumeric() {
+            identifier.push(c.to_ascii_lowercase());
+        } else if !identifier.is_empty() && !identifier.ends_with('_') {
+            identifier.push('_');
+        }
+    }
+    let identifier = identifier.trim_end_matches('_');
+
+    match identifier.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => identifier.to_string(),
+        _ => format!("obj_{}", identifier),
+    }
+}
+
+fn rights(rights: u64, object_type: Option<ObjectType>) -> String {
+    let mut letters = vec![(Rights::Read, 'R'), (Rights::Write, 'W')];
+    // Frames only have read and write rights
+    if !matches!(
+        object_type,
+        Some(ObjectType::SmallPage | ObjectType::LargePage | ObjectType::HugePage)
+    ) {
+        letters.push((Rights::Grant, 'G'));
+        letters.push((Rights::GrantReply, 'P'));
+    }
+
+    letters
+        .into_iter()
+        .filter(|(right, _)| rights & *right as u64 != 0)
+        .map(|(_, letter)| letter)
+        .collect()
+}
+
+fn frame_size(object_type: ObjectType) -> &'static str {
+    match object_type {
+        ObjectType::SmallPage => "4k",
+        ObjectType::LargePage => "2M",
+        ObjectType::HugePage => "1G",
+        _ => unreachable!(),
+    }
+}
+
+/// The capDL type of a paging structure whose entries each cover 'bits'
+/// bits of virtual address.
+fn paging_type(config: &Config, bits: u64) -> &'static str {
+    match (config.arch, bits) {
+        (Arch::Aarch64, 39) => "pgd",
+        (Arch::Aarch64, 30) => "pud",
+        (Arch::Aarch64, 21) => "pd",
+        _ => "pt",
+    }
+}
+
+struct Spec<'a> {
+    config: &'a Config,
+    names: HashMap<usize, String>,
+}
+
+impl Spec<'_> {
+    fn cap(&self, cap: &Cap, cap_rights: Option<u64>) -> Option<String> {
+        let name = self.names.get(&cap.object)?;
+        let object_type = match cap.cap_type {
+            CapType::Object(object_type) => Some(object_type),
+            _ => None,
+        };
+
+        let mut params = Vec::new();
+        if let Some(cap_rights) = cap_rights {
+            params.push(rights(cap_rights, object_type));
+        }
+        if cap.badge != 0 {
+            params.push(format!("badge: 0x{:x}", cap.badge));
+        }
+        if cap.guard_size != 0 {
+            params.push(format!("guard: 0x{:x}", cap.guard));
+            params.push(format!("guard_size: {}", cap.guard_size));
+        }
+
+        match params.is_empty() {
+            true => Some(name.clone()),
+            false => Some(format!("{} ({})", name, params.join(", "))),
+        }
+    }
+
+    /// A cap in a CNode, with rights for the objects where capDL expects them.
+    fn slot_cap(&self, cap: &Cap) -> Option<String> {
+        let has_rights = matches!(
+            cap.cap_type,
+            CapType::Object(
+                ObjectType::Endpoint
+                    | ObjectType::Notification
+                    | ObjectType::Reply
+                    | ObjectType::SmallPage
+                    | ObjectType::LargePage
+                    | ObjectType::HugePage
+            )
+        );
+        self.cap(cap, has_rights.then_some(cap.rights))
+    }
+
+    fn mapping(&self, mapping: &Mapping) -> Option<String> {
+        let cap = self.cap(&mapping.frame, Some(mapping.rights))?;
+        let cacheable = mapping.attr & ArmVmAttributes::Cacheable as u64 != 0;
+        match (self.config.arch, cacheable) {
+            (Arch::Aarch64, false) => Some(format!("{}, uncached)", cap.trim_end_matches(')'))),
+            _ => Some(cap),
+        }
+    }
+}
+
+/// Generate a capDL specification of 'objects' and the caps between them.
+/// Objects are named after the names of their cap addresses in 'cap_lookup'.
+pub fn spec(
+    config: &Config,
+    simulator: &Simulator,
+    objects: &[Object],
+    cap_lookup: &HashMap<u64, String>,
+) -> String {
+    let mut spec = Spec {
+        config,
+        names: HashMap::new(),
+    };
+    let mut used_names = HashSet::new();
+    let mut unique_name = |name: String| {
+        let mut unique = name.clone();
+        let mut n = 1;
+        while !used_names.insert(unique.clone()) {
+            unique = format!("{}_{}", name, n);
+            n += 1;
+        }
+        unique
+    };
+
+    let mut declared = Vec::new();
+    for object in objects {
+        if let Some(index) = simulator.object_at(object.cap_addr) {
+            let name = match cap_lookup.get(&object.cap_addr) {
+                Some(name) => identifier(name),
+                None => format!("obj_{:x}", object.cap_addr),
+            };
+            spec.names.insert(index, unique_name(name));
+            declared.push((index, object));
+        }
+    }
+    let mut irqs = BTreeMap::new();
+    for (index, object) in simulator.objects.iter().enumerate() {
+        if let sim::Object::IrqHandler { irq, trigger, .. } = object {
+            spec.names
+                .insert(index, unique_name(format!("irq_{}", irq)));
+            irqs.insert(*irq, (index, *trigger));
+        }
+    }
+
+    // Work out which level each page table has been mapped at, along with
+    // the entries of every paging structure.
+    let mut levels: HashMap<usize, u64> = HashMap::new();
+    let mut entries: HashMap<usize, BTreeMap<u64, String>> = HashMap::new();
+    let root_bits = simulator.root_bits();
+    for (index, _) in &declared {
+        let sim::Object::Paging { page_tables, pages } = &simulator.objects[*index] else {
+            continue;
+        };
+        levels.insert(*index, root_bits);
+        let parent = |bits: u64, entry: u64| match bits == root_bits {
+            true => (*index, entry),
+            false => (
+                page_tables[&(bits + sim::PT_INDEX_BITS, entry >> sim::PT_INDEX_BITS)],
+                entry & mask(sim::PT_INDEX_BITS),
+            ),
+        };
+        for (&(bits, entry), &page_table) in page_tables {
+            levels.insert(page_table, bits - sim::PT_INDEX_BITS);
+            if let Some(name) = spec.names.get(&page_table) {
+                let (table, slot) = parent(bits, entry);
+                entries.entry(table).or_default().insert(slot, name.clone());
+            }
+        }
+        for (&(bits, entry), mapping) in pages {
+            if let Some(cap) = spec.mapping(mapping) {
+                let (table, slot) = parent(bits, entry);
+                entries.entry(table).or_default().insert(slot, cap);
+            }
+        }
+    }
+
+    let mut out = String::new();
+    let arch = match config.arch {
+        Arch::Aarch64 => "aarch64",
+        Arch::Riscv64 => "riscv64",
+    };
+    writeln!(out, "arch {}\n", arch).unwrap();
+
+    writeln!(out, "objects {{").unwrap();
+    for (index, object) in &declared {
+        let name = &spec.names[index];
+        let paddr = object.phys_addr;
+        let declaration = match (object.object_type, &simulator.objects[*index]) {
+            (ObjectType::Tcb, sim::Object::Tcb(tcb)) => {
+                let register = |name: &str| {
+                    tcb.registers
+                        .iter()
+                        .find(|(reg, _)| *reg == name)
+                        .map_or(0, |(_, value)| *value)
+                };
+                let ipc_buffer = tcb.ipc_buffer.map_or(0, |(vaddr, _)| vaddr);
+                format!(
+                    "tcb (addr: 0x{:x}, ip: 0x{:x}, sp: 0x{:x}, prio: {}, max_prio: {}, affinity: 0)",
+                    ipc_buffer,
+                    register("pc"),
+                    register("sp"),
+                    tcb.priority,
+                    tcb.mcp
+                )
+            }
+            (
+                ObjectType::SchedContext,
+                sim::Object::SchedContext {
+                    budget,
+                    period,
+                    badge,
+                    ..
+                },
+            ) => format!(
+                "sc (period: {}, budget: {}, data: 0x{:x})",
+                period, budget, badge
+            ),
+            (ObjectType::CNode, sim::Object::CNode { size_bits, .. }) => {
+                format!("cnode ({} bits)", size_bits)
+            }
+            (object_type @ (ObjectType::VSpace | ObjectType::PageTable), _) => {
+                match levels.get(index) {
+                    Some(bits) => paging_type(config, *bits).to_string(),
+                    None if object_type == ObjectType::VSpace => {
+                        paging_type(config, root_bits).to_string()
+                    }
+                    None => "pt".to_string(),
+                }
+            }
+            (
+                object_type
+                @ (ObjectType::SmallPage | ObjectType::LargePage | ObjectType::HugePage),
+                _,
+            ) => format!("frame ({}, paddr: 0x{:x})", frame_size(object_type), paddr),
+            (ObjectType::Untyped, sim::Object::Untyped { region, .. }) => {
+                format!("ut ({} bits, paddr: 0x{:x})", region.size().ilog2(), paddr)
+            }
+            (ObjectType::Endpoint, _) => "ep".to_string(),
+            (ObjectType::Notification, _) => "notification".to_string(),
+            (ObjectType::Reply, _) => "rtreply".to_string(),
+            (ObjectType::Vcpu, _) => "vcpu".to_string(),
+            (object_type, _) => unreachable!("unexpected {:?} object", object_type),
+        };
+        // capDL only has physical addresses for frames and untypeds
+        match object.object_type {
+            ObjectType::Untyped
+            | ObjectType::SmallPage
+            | ObjectType::LargePage
+            | ObjectType::HugePage => writeln!(out, "    {} = {}", name, declaration),
+            _ => writeln!(
+                out,
+                "    {} = {} -- paddr: 0x{:x}",
+                name, declaration, paddr
+            ),
+        }
+        .unwrap();
+    }
+    for (irq, (index, trigger)) in &irqs {
+        let declaration = match config.arch {
+            Arch::Aarch64 => {
+                let trigger = match trigger {
+                    IrqTrigger::Level => "level",
+                    IrqTrigger::Edge => "edge",
+                };
+                format!("arm_irq (trigger: {}, target: 0)", trigger)
+            }
+            Arch::Riscv64 => "irq".to_string(),
+        };
+        writeln!(
+            out,
+            "    {} = {} -- irq: {}",
+            spec.names[index], declaration, irq
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "caps {{").unwrap();
+    for (index, _) in &declared {
+        let mut slots: Vec<(String, String)> = Vec::new();
+        match &simulator.objects[*index] {
+            sim::Object::CNode { slots: cnode, .. } => {
+                let mut cnode: Vec<(&u64, &Cap)> = cnode.iter().collect();
+                cnode.sort_by_key(|(slot, _)| **slot);
+                for (slot, cap) in cnode {
+                    if let Some(cap) = spec.slot_cap(cap) {
+                        slots.push((format!("0x{:x}", slot), cap));
+                    }
+                }
+            }
+            sim::Object::Tcb(tcb) => {
+                let tcb_slots = [
+                    ("cspace", tcb.cspace.and_then(|cap| spec.cap(&cap, None))),
+                    ("vspace", tcb.vspace.and_then(|cap| spec.cap(&cap, None))),
+                    (
+                        "ipc_buffer_slot",
+                        tcb.ipc_buffer
+                            .and_then(|(_, cap)| spec.cap(&cap, Some(cap.rights))),
+                    ),
+                    (
+                        "fault_ep_slot",
+                        tcb.fault_ep
+                            .and_then(|cap| spec.cap(&cap, Some(cap.rights))),
+                    ),
+                    (
+                        "sc_slot",
+                        tcb.sched_context
+                            .and_then(|sc| spec.names.get(&sc).cloned()),
+                    ),
+                    (
+                        "bound_notification",
+                        tcb.bound_notification
+                            .and_then(|ntfn| spec.names.get(&ntfn).cloned()),
+                    ),
+                ];
+                for (slot, cap) in tcb_slots {
+                    if let Some(cap) = cap {
+                        slots.push((slot.to_string(), cap));
+                    }
+                }
+            }
+            _ => {}
+        }
+        if let Some(table) = entries.get(index) {
+            for (slot, cap) in table {
+                slots.push((format!("0x{:x}", slot), cap.clone()));
+            }
+        }
+
+        if !slots.is_empty() {
+            writeln!(out, "    {} {{", spec.names[index]).unwrap();
+            for (slot, cap) in slots {
+                writeln!(out, "        {}: {}", slot, cap).unwrap();
+            }
+            writeln!(out, "    }}").unwrap();
+        }
+    }
+    for (index, _) in irqs.values() {
+        if let sim::Object::IrqHandler {
+            notification: Some(ntfn),
+            ..
+        } = &simulator.objects[*index]
+        {
+            if let Some(cap) = spec.slot_cap(ntfn) {
+                writeln!(
+                    out,
+                    "    {} {{\n        0: {}\n    }}",
+                    spec.names[index], cap
+                )
+                .unwrap();
+            }
+        }
+    }
+    writeln!(out, "}}\n").unwrap();
+
+    writeln!(out, "irq maps {{").unwrap();
+    for (irq, (index, _)) in &irqs {
+        writeln!(out, "    {}: {}", irq, spec.names[index]).unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sel4::test_util::config;
+    use crate::sim::tests::{page_map, retype, simulator};
+
+    #[test]
+    fn test_identifier() {
+        assert_eq!(identifier("TCB: PD=a"), "tcb_pd_a");
+        assert_eq!(
+            identifier("Page(4 KiB): MR=shared #0"),
+            "page_4_kib_mr_shared_0"
+        );
+        assert_eq!(identifier("0 start"), "obj_0_start");
+    }
+
+    #[test]
+    fn test_spec() {
+        let config = config();
+        let mut simulator = simulator(&config);
+        let objects = [
+            (25, ObjectType::VSpace, 0x4000_0000),
+            (26, ObjectType::PageTable, 0x4000_2000),
+            (27, ObjectType::PageTable, 0x4000_3000),
+            (28, ObjectType::SmallPage, 0x4000_4000),
+            (29, ObjectType::Notification, 0x4000_5000),
+        ];
+        for (slot, object_type, _) in objects {
+            simulator
+                .execute(&retype(23, object_type, 0, slot))
+                .unwrap();
+        }
+        let invocations = [
+            crate::sel4::InvocationArgs::AsidPoolAssign {
+                asid_pool: 6,
+                vspace: 25,
+            },
+            crate::sel4::InvocationArgs::PageTableMap {
+                page_table: 26,
+                vspace: 25,
+                vaddr: 0x4020_0000,
+                attr: 0,
+            },
+            crate::sel4::InvocationArgs::PageTableMap {
+                page_table: 27,
+                vspace: 25,
+                vaddr: 0x4020_0000,
+                attr: 0,
+            },
+            page_map(28, 25, 0x4020_3000),
+            crate::sel4::InvocationArgs::IrqControlGetTrigger {
+                irq_control: 4,
+                irq: 33,
+                trigger: IrqTrigger::Edge,
+                dest_root: 2,
+                dest_index: 30,
+                dest_depth: 64,
+            },
+            crate::sel4::InvocationArgs::IrqHandlerSetNotification {
+                irq_handler: 30,
+                notification: 29,
+            },
+        ];
+        for invocation in &invocations {
+            simulator.execute(invocation).unwrap();
+        }
+
+        let objects: Vec<Object> = objects
+            .iter()
+            .map(|(cap_addr, object_type, phys_addr)| Object {
+                object_type: *object_type,
+                cap_addr: *cap_addr,
+                phys_addr: *phys_addr,
+            })
+            .collect();
+        let cap_lookup = HashMap::from([
+            (25, "VSpace: PD=a".to_string()),
+            (26, "PageTable: PD=a".to_string()),
+            (27, "PageTable: PD=a".to_string()),
+            (28, "Page(4 KiB): MR=shared #0".to_string()),
+            (29, "Notification: PD=a".to_string()),
+        ]);
+        let spec = spec(&config, &simulator, &objects, &cap_lookup);
+
+        for line in [
+            "arch aarch64",
+            "    vspace_pd_a = pud -- paddr: 0x40000000",
+            "    pagetable_pd_a = pd -- paddr: 0x40002000",
+            "    pagetable_pd_a_1 = pt -- paddr: 0x40003000",
+            "    page_4_kib_mr_shared_0 = frame (4k, paddr: 0x40004000)",
+            "    irq_33 = arm_irq (trigger: edge, target: 0) -- irq: 33",
+            "    vspace_pd_a {\n        0x1: pagetable_pd_a\n    }",
+            "    pagetable_pd_a {\n        0x1: pagetable_pd_a_1\n    }",
+            "    pagetable_pd_a_1 {\n        0x3: page_4_kib_mr_shared_0 (R, uncached)\n    }",
+            "    irq_33 {\n        0: notification_pd_a (RWGP)\n    }",
+            "    33: irq_33",
+        ] {
+            assert!(spec.contains(line), "'{}' not in:\n{}", line, spec);
+        }
+    }
+}
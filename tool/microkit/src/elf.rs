@@ -105,6 +105,18 @@ pub struct ElfSegment {
 }
 
 impl ElfSegment {
+    /// Segment at 'virt_addr' with the PF_* flags given in 'attrs'.
+    #[cfg(test)]
+    pub(crate) fn new(virt_addr: u64, data: Vec<u8>, attrs: u32) -> ElfSegment {
+        ElfSegment {
+            data,
+            phys_addr: virt_addr,
+            virt_addr,
+            loadable: true,
+            attrs,
+        }
+    }
+
     pub fn mem_size(&self) -> u64 {
         self.data.len() as u64
     }
@@ -139,6 +151,17 @@ pub struct ElfFile {
 }
 
 impl ElfFile {
+    /// ELF file with the given segments and no symbols.
+    #[cfg(test)]
+    pub(crate) fn from_segments(entry: u64, segments: Vec<ElfSegment>) -> ElfFile {
+        ElfFile {
+            word_size: 64,
+            entry,
+            segments,
+            symbols: HashMap::new(),
+        }
+    }
+
     pub fn from_path(path: &Path) -> Result<ElfFile, String> {
         let bytes = match fs::read(path) {
             Ok(bytes) => bytes,
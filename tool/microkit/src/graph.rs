@@ -0,0 +1,344 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+// Rendering of the topology of a system description as a graph, for use in
+// design reviews and documentation.
+//
+// Protection domains are nested inside the PD that is their parent, along with
+// any virtual machine that they manage. Channels become directed edges for
+// each direction that can notify or make a PPC. Memory regions that are mapped
+// by more than one PD or VM are drawn as nodes with an edge to each mapping, as
+// neither DOT nor Mermaid has a notion of a hyperedge.
+
+use crate::sdf::{ProtectionDomain, SysMap, SysMapPerms, SystemDescription};
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum GraphFormat {
+    Dot,
+    Mermaid,
+}
+
+impl GraphFormat {
+    pub fn parse(s: &str) -> Option<GraphFormat> {
+        match s {
+            "dot" => Some(GraphFormat::Dot),
+            "mermaid" => Some(GraphFormat::Mermaid),
+            _ => None,
+        }
+    }
+
+    pub fn valid_formats() -> Vec<&'static str> {
+        vec!["dot", "mermaid"]
+    }
+}
+
+#[derive(Copy, Clone)]
+enum NodeShape {
+    Pd,
+    Vm,
+    MemoryRegion,
+}
+
+#[derive(Copy, Clone)]
+enum EdgeKind {
+    Notify,
+    Ppc,
+    Map,
+}
+
+struct Renderer {
+    format: GraphFormat,
+    out: String,
+}
+
+impl Renderer {
+    fn escape(&self, s: &str) -> String {
+        match self.format {
+            GraphFormat::Dot => s.replace('\\', "\\\\").replace('"', "\\\""),
+            GraphFormat::Mermaid => s.replace('"', "#quot;"),
+        }
+    }
+
+    fn node(&mut self, depth: usize, id: &str, shape: NodeShape, lines: &[String]) {
+        let indent = "    ".repeat(depth);
+        let lines: Vec<String> = lines.iter().map(|line| self.escape(line)).collect();
+        match self.format {
+            GraphFormat::Dot => {
+                let shape = match shape {
+                    NodeShape::Pd => "box",
+                    NodeShape::Vm => "component",
+                    NodeShape::MemoryRegion => "note",
+                };
+                writeln!(
+                    self.out,
+                    "{}{} [label=\"{}\", shape={}];",
+                    indent,
+                    id,
+                    lines.join("\\n"),
+                    shape
+                )
+            }
+            GraphFormat::Mermaid => {
+                let (open, close) = match shape {
+                    NodeShape::Pd => ("[", "]"),
+                    NodeShape::Vm => ("[[", "]]"),
+                    NodeShape::MemoryRegion => ("[(", ")]"),
+                };
+                writeln!(
+                    self.out,
+                    "{}{}{}\"{}\"{}",
+                    indent,
+                    id,
+                    open,
+                    lines.join("<br/>"),
+                    close
+                )
+            }
+        }
+        .unwrap();
+    }
+
+    fn begin_cluster(&mut self, depth: usize, id: &str, title: &str) {
+        let indent = "    ".repeat(depth);
+        let title = self.escape(title);
+        match self.format {
+            GraphFormat::Dot => writeln!(
+                self.out,
+                "{}subgraph {} {{\n{}    label=\"{}\";",
+                indent, id, indent, title
+            ),
+            GraphFormat::Mermaid => writeln!(self.out, "{}subgraph {} [\"{}\"]", indent, id, title),
+        }
+        .unwrap();
+    }
+
+    fn end_cluster(&mut self, depth: usize) {
+        let indent = "    ".repeat(depth);
+        match self.format {
+            GraphFormat::Dot => writeln!(self.out, "{}}}", indent),
+            GraphFormat::Mermaid => writeln!(self.out, "{}end", indent),
+        }
+        .unwrap();
+    }
+
+    fn edge(&mut self, from: &str, to: &str, kind: EdgeKind, label: &str) {
+        let label = self.escape(label);
+        match self.format {
+            GraphFormat::Dot => {
+                let style = match kind {
+                    EdgeKind::Notify => "style=dashed",
+                    EdgeKind::Ppc => "style=bold",
+                    EdgeKind::Map => "dir=none",
+                };
+                writeln!(
+                    self.out,
+                    "    {} -> {} [label=\"{}\", {}];",
+                    from, to, label, style
+                )
+            }
+            GraphFormat::Mermaid => {
+                let arrow = match kind {
+                    EdgeKind::Notify => "-.->",
+                    EdgeKind::Ppc => "==>",
+                    EdgeKind::Map => "---",
+                };
+                writeln!(self.out, "    {} {}|\"{}\"| {}", from, arrow, label, to)
+            }
+        }
+        .unwrap();
+    }
+}
+
+fn pd_node_id(pd_idx: usize) -> String {
+    format!("pd_{}", pd_idx)
+}
+
+fn vm_node_id(pd_idx: usize) -> String {
+    format!("vm_{}", pd_idx)
+}
+
+fn perms_string(perms: u8) -> String {
+    [
+        (SysMapPerms::Read as u8, 'r'),
+        (SysMapPerms::Write as u8, 'w'),
+        (SysMapPerms::Execute as u8, 'x'),
+    ]
+    .into_iter()
+    .filter(|(perm, _)| perms & perm != 0)
+    .map(|(_, c)| c)
+    .collect()
+}
+
+fn write_pd(renderer: &mut Renderer, system: &SystemDescription, pd_idx: usize, depth: usize) {
+    let pd = &system.protection_domains[pd_idx];
+    let children: Vec<usize> = system
+        .protection_domains
+        .iter()
+        .enumerate()
+        .filter(|(_, child)| child.parent == Some(pd_idx))
+        .map(|(child_idx, _)| child_idx)
+        .collect();
+
+    let mut lines = vec![pd.name.clone(), format!("priority {}", pd.priority)];
+    if let Some(id) = pd.id {
+        lines.push(format!("child id {}", id));
+    }
+    if pd.passive {
+        lines.push("passive".to_string());
+    }
+    for irq in &pd.irqs {
+        lines.push(format!("IRQ {} on channel {}", irq.irq, irq.id));
+    }
+
+    let is_cluster = !children.is_empty() || pd.virtual_machine.is_some();
+    let node_depth = if is_cluster { depth + 1 } else { depth };
+    if is_cluster {
+        renderer.begin_cluster(depth, &format!("cluster_pd_{}", pd_idx), &pd.name);
+    }
+    renderer.node(node_depth, &pd_node_id(pd_idx), NodeShape::Pd, &lines);
+    if let Some(vm) = &pd.virtual_machine {
+        let lines = vec![
+            vm.name.clone(),
+            format!("priority {}", vm.priority),
+            format!("{} vCPU(s)", vm.vcpus.len()),
+        ];
+        renderer.node(node_depth, &vm_node_id(pd_idx), NodeShape::Vm, &lines);
+    }
+    for child_idx in children {
+        write_pd(renderer, system, child_idx, node_depth);
+    }
+    if is_cluster {
+        renderer.end_cluster(depth);
+    }
+}
+
+/// Render the topology of 'system' as a graph in the given format.
+pub fn render(system: &SystemDescription, format: GraphFormat) -> String {
+    let mut renderer = Renderer {
+        format,
+        out: String::new(),
+    };
+
+    match format {
+        GraphFormat::Dot => writeln!(renderer.out, "digraph system {{\n    compound=true;"),
+        GraphFormat::Mermaid => writeln!(renderer.out, "flowchart LR"),
+    }
+    .unwrap();
+
+    for (pd_idx, pd) in system.protection_domains.iter().enumerate() {
+        if pd.parent.is_none() {
+            write_pd(&mut renderer, system, pd_idx, 1);
+        }
+    }
+
+    // Each memory region shared between PDs or VMs, with the node of everything mapping it
+    for (mr_idx, mr) in system.memory_regions.iter().enumerate() {
+        let mappings: Vec<(String, &SysMap)> = system
+            .protection_domains
+            .iter()
+            .enumerate()
+            .flat_map(|(pd_idx, pd): (usize, &ProtectionDomain)| {
+                let pd_maps = pd.maps.iter().map(move |map| (pd_node_id(pd_idx), map));
+                let vm_maps = pd
+                    .virtual_machine
+                    .iter()
+                    .flat_map(|vm| vm.maps.iter())
+                    .map(move |map| (vm_node_id(pd_idx), map));
+                pd_maps.chain(vm_maps)
+            })
+            .filter(|(_, map)| map.mr == mr.name)
+            .collect();
+        if mappings.len() < 2 {
+            continue;
+        }
+
+        let mr_node = format!("mr_{}", mr_idx);
+        let lines = vec![mr.name.clone(), format!("0x{:x} bytes", mr.size)];
+        renderer.node(1, &mr_node, NodeShape::MemoryRegion, &lines);
+        for (node, map) in mappings {
+            renderer.edge(&mr_node, &node, EdgeKind::Map, &perms_string(map.perms));
+        }
+    }
+
+    for cc in &system.channels {
+        for (send, recv) in [(&cc.end_a, &cc.end_b), (&cc.end_b, &cc.end_a)] {
+            let (from, to) = (pd_node_id(send.pd), pd_node_id(recv.pd));
+            if send.notify {
+                let label = format!("notify {} -> {}", send.id, recv.id);
+                renderer.edge(&from, &to, EdgeKind::Notify, &label);
+            }
+            if send.pp {
+                let label = format!("ppc {} -> {}", send.id, recv.id);
+                renderer.edge(&from, &to, EdgeKind::Ppc, &label);
+            }
+        }
+    }
+
+    if format == GraphFormat::Dot {
+        writeln!(renderer.out, "}}").unwrap();
+    }
+
+    renderer.out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf;
+    use crate::sel4::test_util::config;
+
+    const SYSTEM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<system>
+    <memory_region name="shared" size="0x1000" />
+    <protection_domain name="server" priority="2">
+        <program_image path="server.elf" />
+        <map mr="shared" vaddr="0x4000000" perms="rw" />
+        <irq irq="33" id="5" />
+        <protection_domain name="worker" priority="1" id="0">
+            <program_image path="worker.elf" />
+            <map mr="shared" vaddr="0x4000000" perms="r" />
+        </protection_domain>
+    </protection_domain>
+    <protection_domain name="client" priority="1">
+        <program_image path="client.elf" />
+    </protection_domain>
+    <channel>
+        <end pd="client" id="3" pp="true" notify="false" />
+        <end pd="server" id="1" />
+    </channel>
+</system>
+"#;
+
+    #[test]
+    fn test_render() {
+        let system = sdf::parse("test.system", SYSTEM, &config()).unwrap();
+
+        let dot = render(&system, GraphFormat::Dot);
+        for line in [
+            "    subgraph cluster_pd_0 {\n        label=\"server\";",
+            "        pd_0 [label=\"server\\npriority 2\\nIRQ 33 on channel 5\", shape=box];",
+            "        pd_1 [label=\"worker\\npriority 1\\nchild id 0\", shape=box];",
+            "    mr_0 -> pd_1 [label=\"r\", dir=none];",
+            "    pd_2 -> pd_0 [label=\"ppc 3 -> 1\", style=bold];",
+            "    pd_0 -> pd_2 [label=\"notify 1 -> 3\", style=dashed];",
+        ] {
+            assert!(dot.contains(line), "'{}' not in:\n{}", line, dot);
+        }
+        assert!(!dot.contains("pd_2 -> pd_0 [label=\"notify"));
+
+        let mermaid = render(&system, GraphFormat::Mermaid);
+        for line in [
+            "    subgraph cluster_pd_0 [\"server\"]",
+            "    mr_0[(\"shared<br/>0x1000 bytes\")]",
+            "    mr_0 ---|\"rw\"| pd_0",
+            "    pd_2 ==>|\"ppc 3 -> 1\"| pd_0",
+            "    pd_0 -.->|\"notify 1 -> 3\"| pd_2",
+        ] {
+            assert!(mermaid.contains(line), "'{}' not in:\n{}", line, mermaid);
+        }
+    }
+}
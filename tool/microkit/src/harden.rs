@@ -0,0 +1,177 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+// Hardening checks on the mappings of a system, for when the tool is asked to
+// enforce a stricter policy than the one required for a system to be valid.
+//
+// Every violation is collected, rather than stopping at the first, so that
+// they can all be fixed at once.
+
+use crate::elf::ElfFile;
+use crate::sdf::{SysMap, SysMapPerms, SystemDescription};
+
+fn is_writable(perms: u8) -> bool {
+    perms & SysMapPerms::Write as u8 != 0
+}
+
+fn is_executable(perms: u8) -> bool {
+    perms & SysMapPerms::Execute as u8 != 0
+}
+
+fn map_loc(filename: &str, map: &SysMap) -> String {
+    match map.text_pos {
+        Some(pos) => format!("{}:{}:{}", filename, pos.row, pos.col),
+        None => filename.to_string(),
+    }
+}
+
+/// Check the maps in the system description, 'filename' is the path of the
+/// SDF and is only used for the location of each violation.
+pub fn check_maps(filename: &str, system: &SystemDescription) -> Vec<String> {
+    let mut maps: Vec<(&String, &SysMap)> = Vec::new();
+    for pd in &system.protection_domains {
+        maps.extend(pd.maps.iter().map(|map| (&pd.name, map)));
+        if let Some(vm) = &pd.virtual_machine {
+            maps.extend(vm.maps.iter().map(|map| (&vm.name, map)));
+        }
+    }
+
+    let mut violations = Vec::new();
+    for (name, map) in &maps {
+        // Only maps of memory regions that exist reach this point
+        let mr = system
+            .memory_regions
+            .iter()
+            .find(|mr| mr.name == map.mr)
+            .unwrap();
+        let loc = map_loc(filename, map);
+
+        if is_writable(map.perms) && is_executable(map.perms) {
+            violations.push(format!(
+                "map of memory region '{}' in '{}' is writable and executable @ {}",
+                mr.name, name, loc
+            ));
+        }
+
+        let mut mapped_by: Vec<&String> = maps
+            .iter()
+            .filter(|(_, other)| other.mr == mr.name)
+            .map(|(other_name, _)| *other_name)
+            .collect();
+        mapped_by.sort();
+        mapped_by.dedup();
+        if is_executable(map.perms) && mapped_by.len() > 1 {
+            violations.push(format!(
+                "map of memory region '{}' in '{}' is executable but the memory region is shared @ {}",
+                mr.name, name, loc
+            ));
+        }
+
+        if mr.phys_addr.is_some() {
+            if is_executable(map.perms) {
+                violations.push(format!(
+                    "map of device memory region '{}' in '{}' is executable @ {}",
+                    mr.name, name, loc
+                ));
+            }
+            if map.cached {
+                violations.push(format!(
+                    "map of device memory region '{}' in '{}' must have cached=\"false\" @ {}",
+                    mr.name, name, loc
+                ));
+            }
+        }
+    }
+
+    violations
+}
+
+/// Check the loadable segments of the program image of each protection domain.
+pub fn check_elfs(system: &SystemDescription, pd_elf_files: &[ElfFile]) -> Vec<String> {
+    let mut violations = Vec::new();
+    for (pd, elf) in system.protection_domains.iter().zip(pd_elf_files) {
+        for (seg_idx, segment) in elf.segments.iter().enumerate() {
+            if segment.loadable && segment.is_writable() && segment.is_executable() {
+                violations.push(format!(
+                    "segment {} at 0x{:x} of program image '{}' for protection domain '{}' is writable and executable",
+                    seg_idx,
+                    segment.virt_addr,
+                    pd.program_image.display(),
+                    pd.name
+                ));
+            }
+        }
+    }
+
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::elf::ElfSegment;
+    use crate::sdf;
+    use crate::sel4::test_util::config;
+
+    const SYSTEM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<system>
+    <memory_region name="uart" size="0x1000" phys_addr="0x9000000" />
+    <memory_region name="shared" size="0x1000" />
+    <memory_region name="private" size="0x1000" />
+    <protection_domain name="driver" priority="2">
+        <program_image path="driver.elf" />
+        <map mr="uart" vaddr="0x4000000" perms="rwx" />
+        <map mr="shared" vaddr="0x5000000" perms="rx" />
+        <map mr="private" vaddr="0x6000000" perms="rx" />
+    </protection_domain>
+    <protection_domain name="client" priority="1">
+        <program_image path="client.elf" />
+        <map mr="shared" vaddr="0x5000000" perms="r" />
+        <map mr="uart" vaddr="0x4000000" perms="rw" cached="false" />
+    </protection_domain>
+</system>
+"#;
+
+    #[test]
+    fn test_check_maps() {
+        let system = sdf::parse("test.system", SYSTEM, &config()).unwrap();
+        assert_eq!(
+            check_maps("test.system", &system),
+            vec![
+                "map of memory region 'uart' in 'driver' is writable and executable @ test.system:8:9",
+                "map of memory region 'uart' in 'driver' is executable but the memory region is shared @ test.system:8:9",
+                "map of device memory region 'uart' in 'driver' is executable @ test.system:8:9",
+                "map of device memory region 'uart' in 'driver' must have cached=\"false\" @ test.system:8:9",
+                "map of memory region 'shared' in 'driver' is executable but the memory region is shared @ test.system:9:9",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_elfs() {
+        let system = sdf::parse("test.system", SYSTEM, &config()).unwrap();
+        let driver = ElfFile::from_segments(
+            0x200000,
+            vec![
+                ElfSegment::new(0x200000, vec![0; 0x1000], 0x5),
+                ElfSegment::new(0x201000, vec![0; 0x1000], 0x7),
+            ],
+        );
+        let client = ElfFile::from_segments(
+            0x200000,
+            vec![
+                ElfSegment::new(0x200000, vec![0; 0x1000], 0x5),
+                ElfSegment::new(0x201000, vec![0; 0x1000], 0x6),
+            ],
+        );
+        assert_eq!(
+            check_elfs(&system, &[driver, client]),
+            vec![
+                "segment 1 at 0x201000 of program image 'driver.elf' for protection domain 'driver' is writable and executable",
+            ]
+        );
+    }
+}
@@ -4,14 +4,21 @@
 // SPDX-License-Identifier: BSD-2-Clause
 //
 
+pub mod capdl;
 pub mod elf;
+pub mod graph;
+pub mod harden;
+pub mod lint;
 pub mod loader;
+pub mod sched;
 pub mod sdf;
 pub mod sel4;
+pub mod sim;
 pub mod util;
 
 use sel4::BootInfo;
-use std::cmp::min;
+use std::cmp::{max, min};
+use std::collections::BTreeMap;
 use std::fmt;
 
 // Note that this value is used in the monitor so should also be changed there
@@ -57,22 +64,32 @@ pub struct Region {
     pub size: u64,
     // In order to avoid some expensive copies to put the data
     // into this struct, we instead store the index of the segment
-    // of the ELF this region is associated with.
+    // of the ELF this region is associated with, and the offset of
+    // the region's data within the segment.
     segment_idx: usize,
+    segment_offset: u64,
 }
 
 impl Region {
-    pub fn new(name: String, addr: u64, size: u64, segment_idx: usize) -> Region {
+    pub fn new(
+        name: String,
+        addr: u64,
+        size: u64,
+        segment_idx: usize,
+        segment_offset: u64,
+    ) -> Region {
         Region {
             name,
             addr,
             size,
             segment_idx,
+            segment_offset,
         }
     }
 
-    pub fn data<'a>(&self, elf: &'a elf::ElfFile) -> &'a Vec<u8> {
-        &elf.segments[self.segment_idx].data
+    pub fn data<'a>(&self, elf: &'a elf::ElfFile) -> &'a [u8] {
+        let start = self.segment_offset as usize;
+        &elf.segments[self.segment_idx].data[start..start + self.size as usize]
     }
 }
 
@@ -139,76 +156,185 @@ impl MemoryRegion {
     }
 }
 
+/// Policy used to choose where an allocation is placed when there are
+/// multiple free regions (or untyped objects) that could satisfy it.
+///
+/// All policies are alignment-aware: the candidates are compared based
+/// on the placement after aligning the allocation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AllocationPolicy {
+    /// Use the first (lowest address) candidate with sufficient space.
+    FirstFit,
+    /// Use the candidate that leaves the least free space (including any
+    /// padding needed for alignment) after the allocation.
+    BestFit,
+    /// Use the candidate that needs the least padding for alignment,
+    /// preferring smaller regions, so that allocations of the same size
+    /// are grouped together.
+    SizeClass,
+}
+
+impl AllocationPolicy {
+    pub fn parse(s: &str) -> Option<AllocationPolicy> {
+        match s {
+            "first-fit" => Some(AllocationPolicy::FirstFit),
+            "best-fit" => Some(AllocationPolicy::BestFit),
+            "size-class" => Some(AllocationPolicy::SizeClass),
+            _ => None,
+        }
+    }
+
+    pub fn valid_policies() -> &'static [&'static str] {
+        &["first-fit", "best-fit", "size-class"]
+    }
+
+    /// Select one of the candidates (in address order) according to the policy.
+    fn select<T>(self, mut candidates: impl Iterator<Item = (T, Placement)>) -> Option<T> {
+        let selected = match self {
+            AllocationPolicy::FirstFit => candidates.next(),
+            AllocationPolicy::BestFit => {
+                candidates.min_by_key(|(_, p)| (p.padding + p.leftover, p.padding))
+            }
+            AllocationPolicy::SizeClass => {
+                candidates.min_by_key(|(_, p)| (p.padding, util::msb(p.region_size)))
+            }
+        };
+
+        selected.map(|(candidate, _)| candidate)
+    }
+}
+
+/// Describes how an allocation would be placed within a free region.
+#[derive(Copy, Clone)]
+struct Placement {
+    /// Bytes skipped before the allocation in order to align it
+    padding: u64,
+    /// Bytes remaining free after the allocation
+    leftover: u64,
+    /// Size of the region being allocated from
+    region_size: u64,
+}
+
+/// A set of disjoint memory regions, kept sorted by address.
+///
+/// Adjacent regions are merged when inserted, so each region is maximal,
+/// unless created with 'without_merging'.
 #[derive(Default)]
 pub struct DisjointMemoryRegion {
-    pub regions: Vec<MemoryRegion>,
+    /// Maps the base of each region to its (exclusive) end.
+    regions: BTreeMap<u64, u64>,
+    /// Keep adjacent regions separate when inserted
+    keep_adjacent: bool,
 }
 
 impl DisjointMemoryRegion {
-    fn check(&self) {
-        // Ensure that regions are sorted and non-overlapping
-        let mut last_end: Option<u64> = None;
-        for region in &self.regions {
-            if last_end.is_some() {
-                assert!(region.base >= last_end.unwrap());
-            }
-            last_end = Some(region.end)
+    /// Adjacent regions are kept separate when inserted, as the kernel does
+    /// with the available physical memory regions during boot.
+    pub fn without_merging() -> DisjointMemoryRegion {
+        DisjointMemoryRegion {
+            regions: BTreeMap::new(),
+            keep_adjacent: true,
         }
     }
 
-    pub fn insert_region(&mut self, base: u64, end: u64) {
-        let mut insert_idx = self.regions.len();
-        for (idx, region) in self.regions.iter().enumerate() {
-            if end <= region.base {
-                insert_idx = idx;
-                break;
-            }
-        }
-        // FIXME: Should extend here if adjacent rather than
-        // inserting now
+    /// Iterate over the regions in ascending address order.
+    pub fn regions(&self) -> impl DoubleEndedIterator<Item = MemoryRegion> + '_ {
         self.regions
-            .insert(insert_idx, MemoryRegion::new(base, end));
-        self.check();
+            .iter()
+            .map(|(&base, &end)| MemoryRegion::new(base, end))
     }
 
-    pub fn remove_region(&mut self, base: u64, end: u64) {
-        let mut maybe_idx = None;
-        for (i, r) in self.regions.iter().enumerate() {
-            if base >= r.base && end <= r.end {
-                maybe_idx = Some(i);
-                break;
+    pub fn insert_region(&mut self, base: u64, end: u64) -> Result<(), String> {
+        if base > end {
+            return Err(format!("region [0x{:x}..0x{:x}) is invalid", base, end));
+        }
+        if base == end {
+            return Ok(());
+        }
+
+        // The closest region starting at or below base must end before it,
+        // and the closest region starting above base must start after end.
+        let before = self.regions.range(..=base).next_back();
+        let after = self.regions.range(base..).next();
+        let overlapping = match (before, after) {
+            (Some((&b, &e)), _) if e > base => Some((b, e)),
+            (_, Some((&b, &e))) if b < end => Some((b, e)),
+            _ => None,
+        };
+        if let Some((b, e)) = overlapping {
+            return Err(format!(
+                "region [0x{:x}..0x{:x}) overlaps free region [0x{:x}..0x{:x})",
+                base, end, b, e
+            ));
+        }
+
+        let mut new_base = base;
+        let mut new_end = end;
+        if !self.keep_adjacent {
+            if let Some((&b, &e)) = before {
+                if e == base {
+                    new_base = b;
+                }
+            }
+            if let Some(e) = self.regions.remove(&end) {
+                new_end = e;
             }
         }
-        if maybe_idx.is_none() {
-            panic!("Internal error: attempting to remove region [0x{:x}-0x{:x}) that is not currently covered", base, end);
+        self.regions.insert(new_base, new_end);
+
+        Ok(())
+    }
+
+    pub fn remove_region(&mut self, base: u64, end: u64) -> Result<(), String> {
+        if base > end {
+            return Err(format!("region [0x{:x}..0x{:x}) is invalid", base, end));
+        }
+        if base == end {
+            return Ok(());
         }
 
-        let idx = maybe_idx.unwrap();
-
-        let region = self.regions[idx];
-
-        if region.base == base && region.end == end {
-            // Covers exactly, so just remove
-            self.regions.remove(idx);
-        } else if region.base == base {
-            // Trim the start of the region
-            self.regions[idx] = MemoryRegion::new(end, region.end);
-        } else if region.end == end {
-            // Trim end of the region
-            self.regions[idx] = MemoryRegion::new(region.base, base);
-        } else {
-            // Splitting
-            self.regions[idx] = MemoryRegion::new(region.base, base);
-            self.regions
-                .insert(idx + 1, MemoryRegion::new(end, region.end));
+        let (region_base, region_end) = match self.regions.range(..=base).next_back() {
+            Some((&b, &e)) if end <= e => (b, e),
+            _ => return Err(format!("region [0x{:x}..0x{:x}) is not free", base, end)),
+        };
+
+        self.regions.remove(&region_base);
+        if region_base < base {
+            self.regions.insert(region_base, base);
         }
+        if end < region_end {
+            self.regions.insert(end, region_end);
+        }
+
+        Ok(())
+    }
 
-        self.check();
+    /// Remove any free memory within [base..end), unlike 'remove_region'
+    /// the whole range does not need to be free.
+    pub fn exclude_region(&mut self, base: u64, end: u64) {
+        let overlapping: Vec<MemoryRegion> = self
+            .regions()
+            .filter(|r| r.base < end && base < r.end)
+            .collect();
+        for r in overlapping {
+            self.remove_region(max(r.base, base), min(r.end, end))
+                .unwrap();
+        }
+    }
+
+    /// Size of the largest free region
+    pub fn largest_region_size(&self) -> u64 {
+        self.regions().map(|r| r.size()).max().unwrap_or(0)
+    }
+
+    /// Total size of all free regions
+    pub fn total_size(&self) -> u64 {
+        self.regions().map(|r| r.size()).sum()
     }
 
     pub fn aligned_power_of_two_regions(&self, max_bits: u64) -> Vec<MemoryRegion> {
         let mut aligned_regions = Vec::new();
-        for region in &self.regions {
+        for region in self.regions() {
             aligned_regions.extend(region.aligned_power_of_two_regions(max_bits));
         }
 
@@ -217,46 +343,49 @@ impl DisjointMemoryRegion {
 
     /// Allocate region of 'size' bytes, returning the base address.
     /// The allocated region is removed from the disjoint memory region.
-    /// Allocation policy is simple first fit.
-    /// Possibly a 'best fit' policy would be better.
-    /// 'best' may be something that best matches a power-of-two
-    /// allocation
-    pub fn allocate(&mut self, size: u64) -> u64 {
-        let mut region_to_remove: Option<MemoryRegion> = None;
-        for region in &self.regions {
-            if size <= region.size() {
-                region_to_remove = Some(*region);
-                break;
-            }
-        }
-
-        match region_to_remove {
-            Some(region) => {
-                self.remove_region(region.base, region.base + size);
-                region.base
-            }
-            None => panic!("Unable to allocate {} bytes", size),
-        }
+    pub fn allocate(&mut self, size: u64, policy: AllocationPolicy) -> Result<u64, String> {
+        self.allocate_from(size, 0, 1, policy)
     }
 
-    pub fn allocate_from(&mut self, size: u64, lower_bound: u64) -> u64 {
-        let mut region_to_remove = None;
-        for region in &self.regions {
-            if size <= region.size() && region.base >= lower_bound {
-                region_to_remove = Some(*region);
-                break;
+    /// Allocate region of 'size' bytes with a base address aligned to
+    /// 'alignment', from a region starting at or above 'lower_bound'.
+    /// The region is chosen according to 'policy'.
+    pub fn allocate_from(
+        &mut self,
+        size: u64,
+        lower_bound: u64,
+        alignment: u64,
+        policy: AllocationPolicy,
+    ) -> Result<u64, String> {
+        assert!(util::is_power_of_two(alignment));
+        let candidates = self.regions().filter_map(|region| {
+            let base = util::round_up(region.base, alignment);
+            if region.base >= lower_bound && base + size <= region.end {
+                let placement = Placement {
+                    padding: base - region.base,
+                    leftover: region.end - (base + size),
+                    region_size: region.size(),
+                };
+                Some((base, placement))
+            } else {
+                None
             }
-        }
+        });
+        let base_to_remove = policy.select(candidates);
 
-        match region_to_remove {
-            Some(region) => {
-                self.remove_region(region.base, region.base + size);
-                region.base
+        match base_to_remove {
+            Some(base) => {
+                self.remove_region(base, base + size)?;
+                Ok(base)
             }
-            None => panic!(
-                "Unable to allocate {} bytes from lower_bound 0x{:x}",
-                size, lower_bound
-            ),
+            None => Err(format!(
+                "no free region of {} bytes (aligned to 0x{:x}) at or above 0x{:x}; the largest free region is {} bytes and {} bytes are free in total",
+                size,
+                alignment,
+                lower_bound,
+                self.largest_region_size(),
+                self.total_size()
+            )),
         }
     }
 }
@@ -271,6 +400,11 @@ pub struct UntypedAllocator {
     untyped_object: UntypedObject,
     allocation_point: u64,
     allocations: Vec<KernelAllocation>,
+    /// Bytes skipped in order to satisfy alignment requirements
+    wasted: u64,
+    /// Address that allocations must not go past, this is before the end of
+    /// the untyped if it contains reserved memory
+    limit: u64,
 }
 
 impl UntypedAllocator {
@@ -283,9 +417,15 @@ impl UntypedAllocator {
             untyped_object,
             allocation_point,
             allocations,
+            wasted: 0,
+            limit: untyped_object.region.end,
         }
     }
 
+    pub fn untyped(&self) -> &UntypedObject {
+        &self.untyped_object
+    }
+
     pub fn base(&self) -> u64 {
         self.untyped_object.region.base
     }
@@ -293,6 +433,24 @@ impl UntypedAllocator {
     pub fn end(&self) -> u64 {
         self.untyped_object.region.end
     }
+
+    pub fn limit(&self) -> u64 {
+        self.limit
+    }
+
+    /// Bytes allocated, including any wasted for alignment
+    pub fn used(&self) -> u64 {
+        self.allocation_point
+    }
+
+    pub fn wasted(&self) -> u64 {
+        self.wasted
+    }
+
+    /// Bytes that can still be allocated
+    pub fn free(&self) -> u64 {
+        (self.limit - self.base()).saturating_sub(self.allocation_point)
+    }
 }
 
 /// Allocator for kernel objects.
@@ -305,8 +463,7 @@ impl UntypedAllocator {
 /// policy (basically a bump allocator with alignment).
 ///
 /// The only 'choice' this allocator has is which untyped object
-/// to use. The current algorithm is simply first fit: the first
-/// untyped that has sufficient space. This is not optimal.
+/// to use, which is decided by the allocation policy.
 ///
 /// Note: The allocator does not generate the Retype invocations;
 /// this must be done with more knowledge (specifically the destination
@@ -317,10 +474,20 @@ impl UntypedAllocator {
 pub struct ObjectAllocator {
     allocation_idx: u64,
     untyped: Vec<UntypedAllocator>,
+    policy: AllocationPolicy,
+    /// Total bytes requested, including any requests that failed
+    demand: u64,
 }
 
 impl ObjectAllocator {
-    pub fn new(kernel_boot_info: &BootInfo) -> ObjectAllocator {
+    /// Objects are never allocated in 'reserved_memory'. As the kernel
+    /// allocates from the start of an untyped, only the memory of each untyped
+    /// before any reserved memory within it is used.
+    pub fn new(
+        kernel_boot_info: &BootInfo,
+        policy: AllocationPolicy,
+        reserved_memory: &[MemoryRegion],
+    ) -> ObjectAllocator {
         let mut untyped = Vec::new();
         for ut in kernel_boot_info.untyped_objects.iter() {
             if ut.is_device {
@@ -328,37 +495,438 @@ impl ObjectAllocator {
                 // device memory can't be used for kernel objects
                 continue;
             }
-            untyped.push(UntypedAllocator::new(*ut, 0, vec![]));
+            let mut ut_allocator = UntypedAllocator::new(*ut, 0, vec![]);
+            for r in reserved_memory {
+                if r.base < ut.end() && ut.base() < r.end {
+                    ut_allocator.limit = min(ut_allocator.limit, max(r.base, ut.base()));
+                }
+            }
+            untyped.push(ut_allocator);
         }
 
         ObjectAllocator {
             allocation_idx: 0,
             untyped,
+            policy,
+            demand: 0,
         }
     }
 
-    pub fn alloc(&mut self, size: u64) -> KernelAllocation {
+    pub fn untyped(&self) -> &[UntypedAllocator] {
+        &self.untyped
+    }
+
+    /// The address the untyped with the cap 'untyped_cap' is free from, objects
+    /// at fixed addresses can only be allocated from it past this point.
+    pub fn free_start(&self, untyped_cap: u64) -> u64 {
+        let ut = self
+            .untyped
+            .iter()
+            .find(|ut| ut.untyped_object.cap == untyped_cap)
+            .unwrap();
+        ut.base() + ut.allocation_point
+    }
+
+    /// Record that objects at fixed addresses have been allocated from the
+    /// untyped with the cap 'untyped_cap' up to 'watermark', as the kernel
+    /// continues allocating from there.
+    pub fn advance(&mut self, untyped_cap: u64, watermark: u64) {
+        let ut = self
+            .untyped
+            .iter_mut()
+            .find(|ut| ut.untyped_object.cap == untyped_cap)
+            .unwrap();
+        ut.allocation_point = max(ut.allocation_point, watermark - ut.base());
+    }
+
+    pub fn alloc(&mut self, size: u64) -> Result<KernelAllocation, String> {
         self.alloc_n(size, 1)
     }
 
-    pub fn alloc_n(&mut self, size: u64, count: u64) -> KernelAllocation {
+    /// Allocate 'count' contiguous objects of 'size' bytes, starting at an
+    /// address aligned to 'alignment' and within [lower, upper). Unlike alloc_n,
+    /// the allocation may start past where the kernel would place the next
+    /// object, so the address the untyped is free from is also returned and the
+    /// memory in between must be consumed by the caller (e.g with padding objects).
+    pub fn alloc_n_within(
+        &mut self,
+        size: u64,
+        count: u64,
+        alignment: u64,
+        lower: u64,
+        upper: u64,
+    ) -> Result<(KernelAllocation, u64), String> {
         assert!(util::is_power_of_two(size));
+        assert!(util::is_power_of_two(alignment));
         assert!(count > 0);
-        for ut in &mut self.untyped {
+        self.demand += count * size;
+        let alignment = max(size, alignment);
+        let placement = |ut: &UntypedAllocator| {
+            let free_start = ut.base() + ut.allocation_point;
+            let start = util::round_up(max(free_start, lower), alignment);
+            (free_start, start)
+        };
+        let candidates = self.untyped.iter().enumerate().filter_map(|(idx, ut)| {
+            let (free_start, start) = placement(ut);
+            let end = start + count * size;
+            if end <= ut.limit && end <= upper {
+                let placement = Placement {
+                    padding: start - free_start,
+                    leftover: ut.limit - end,
+                    region_size: ut.untyped_object.region.size(),
+                };
+                Some((idx, placement))
+            } else {
+                None
+            }
+        });
+
+        if let Some(idx) = self.policy.select(candidates) {
+            let ut = &mut self.untyped[idx];
+            let (free_start, start) = placement(ut);
+            ut.wasted += start - free_start;
+            ut.allocation_point = (start - ut.base()) + (count * size);
+            self.allocation_idx += 1;
+            let allocation = KernelAllocation {
+                untyped_cap_address: ut.untyped_object.cap,
+                phys_addr: start,
+            };
+            ut.allocations.push(allocation);
+            return Ok((allocation, free_start));
+        }
+
+        Err(format!(
+            "need {} contiguous bytes aligned to 0x{:x} within [0x{:x}..0x{:x}) but no free normal memory untyped has room in that range",
+            count * size,
+            alignment,
+            lower,
+            upper
+        ))
+    }
+
+    pub fn alloc_n(&mut self, size: u64, count: u64) -> Result<KernelAllocation, String> {
+        assert!(util::is_power_of_two(size));
+        assert!(count > 0);
+        self.demand += count * size;
+        let candidates = self.untyped.iter().enumerate().filter_map(|(idx, ut)| {
             // See if this fits
-            let start = util::round_up(ut.base() + ut.allocation_point, size);
-            if start + (count * size) <= ut.end() {
-                ut.allocation_point = (start - ut.base()) + (count * size);
-                self.allocation_idx += 1;
-                let allocation = KernelAllocation {
-                    untyped_cap_address: ut.untyped_object.cap,
-                    phys_addr: start,
+            let free_start = ut.base() + ut.allocation_point;
+            let start = util::round_up(free_start, size);
+            if start + (count * size) <= ut.limit {
+                let placement = Placement {
+                    padding: start - free_start,
+                    leftover: ut.limit - (start + count * size),
+                    region_size: ut.untyped_object.region.size(),
                 };
-                ut.allocations.push(allocation);
-                return allocation;
+                Some((idx, placement))
+            } else {
+                None
+            }
+        });
+
+        if let Some(idx) = self.policy.select(candidates) {
+            let ut = &mut self.untyped[idx];
+            let free_start = ut.base() + ut.allocation_point;
+            let start = util::round_up(free_start, size);
+            ut.wasted += start - free_start;
+            ut.allocation_point = (start - ut.base()) + (count * size);
+            self.allocation_idx += 1;
+            let allocation = KernelAllocation {
+                untyped_cap_address: ut.untyped_object.cap,
+                phys_addr: start,
+            };
+            ut.allocations.push(allocation);
+            return Ok(allocation);
+        }
+
+        let largest_free = self.untyped.iter().map(|ut| ut.free()).max().unwrap_or(0);
+        let available: u64 = self.untyped.iter().map(|ut| ut.limit - ut.base()).sum();
+        Err(format!(
+            "need {} bytes ({} x {} bytes) but the largest free normal memory untyped only has {} bytes free; \
+             at least {} bytes of normal memory are needed in total, {} bytes are available",
+            count * size,
+            count,
+            size,
+            largest_free,
+            self.demand,
+            available
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: u64 = 0x1000;
+    const PAGES: usize = 64;
+
+    /// Deterministic xorshift64 so failures can be reproduced.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: u64) -> u64 {
+            self.next() % n
+        }
+    }
+
+    /// Reference model: one flag per page recording whether it is free.
+    fn model_regions(model: &[bool]) -> Vec<MemoryRegion> {
+        let mut regions: Vec<MemoryRegion> = Vec::new();
+        for (i, &free) in model.iter().enumerate() {
+            if !free {
+                continue;
+            }
+            let base = i as u64 * PAGE;
+            match regions.last_mut() {
+                Some(last) if last.end == base => last.end = base + PAGE,
+                _ => regions.push(MemoryRegion::new(base, base + PAGE)),
             }
         }
+        regions
+    }
+
+    fn random_range(rng: &mut Rng) -> (usize, usize) {
+        let start = rng.below(PAGES as u64) as usize;
+        let len = 1 + rng.below(8) as usize;
+        (start, min(start + len, PAGES))
+    }
+
+    #[test]
+    fn test_disjoint_memory_region_merges_adjacent() {
+        let mut dmr = DisjointMemoryRegion::default();
+        dmr.insert_region(0x3000, 0x4000).unwrap();
+        dmr.insert_region(0x1000, 0x2000).unwrap();
+        dmr.insert_region(0x2000, 0x3000).unwrap();
+        assert_eq!(
+            dmr.regions().collect::<Vec<_>>(),
+            vec![MemoryRegion::new(0x1000, 0x4000)]
+        );
+        assert!(dmr.insert_region(0x3000, 0x5000).is_err());
+        assert!(dmr.insert_region(0x0, 0x2000).is_err());
+        assert!(dmr.remove_region(0x3000, 0x5000).is_err());
+        dmr.remove_region(0x2000, 0x3000).unwrap();
+        assert_eq!(
+            dmr.regions().collect::<Vec<_>>(),
+            vec![
+                MemoryRegion::new(0x1000, 0x2000),
+                MemoryRegion::new(0x3000, 0x4000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_disjoint_memory_region_against_model() {
+        for seed in 1..=32u64 {
+            let mut rng = Rng(seed.wrapping_mul(0x9e3779b97f4a7c15));
+            let mut dmr = DisjointMemoryRegion::default();
+            let mut model = [false; PAGES];
+
+            for _ in 0..500 {
+                let (start, end) = random_range(&mut rng);
+                let (base, limit) = (start as u64 * PAGE, end as u64 * PAGE);
+                match rng.below(3) {
+                    0 => {
+                        let expected = model[start..end].iter().all(|free| !free);
+                        assert_eq!(dmr.insert_region(base, limit).is_ok(), expected);
+                        if expected {
+                            model[start..end].fill(true);
+                        }
+                    }
+                    1 => {
+                        let expected = model[start..end].iter().all(|free| *free);
+                        assert_eq!(dmr.remove_region(base, limit).is_ok(), expected);
+                        if expected {
+                            model[start..end].fill(false);
+                        }
+                    }
+                    _ => {
+                        let size = (end - start) as u64 * PAGE;
+                        let expected = model_regions(&model)
+                            .into_iter()
+                            .find(|r| r.size() >= size)
+                            .map(|r| r.base);
+                        let result = dmr.allocate(size, AllocationPolicy::FirstFit);
+                        assert_eq!(result.ok(), expected);
+                        if let Some(base) = expected {
+                            let first = (base / PAGE) as usize;
+                            model[first..first + (end - start)].fill(false);
+                        }
+                    }
+                }
+
+                let regions = model_regions(&model);
+                assert_eq!(dmr.regions().collect::<Vec<_>>(), regions);
+                assert_eq!(
+                    dmr.total_size(),
+                    regions.iter().map(|r| r.size()).sum::<u64>()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_object_allocator_alloc_n_within() {
+        let boot_info = BootInfo {
+            fixed_cap_count: 0,
+            sched_control_cap: 0,
+            paging_cap_count: 0,
+            page_cap_count: 0,
+            untyped_objects: vec![
+                UntypedObject::new(1, MemoryRegion::new(0x10000, 0x20000), false),
+                UntypedObject::new(2, MemoryRegion::new(0x40000, 0x80000), false),
+            ],
+            first_available_cap: 0,
+        };
+        let mut kao = ObjectAllocator::new(&boot_info, AllocationPolicy::FirstFit, &[]);
+        kao.alloc(PAGE).unwrap();
+
+        // Skips the first untyped as it cannot satisfy the lower bound
+        let (allocation, watermark) = kao
+            .alloc_n_within(PAGE, 2, 0x10000, 0x41000, u64::MAX)
+            .unwrap();
+        assert_eq!(allocation.untyped_cap_address, 2);
+        assert_eq!(allocation.phys_addr, 0x50000);
+        assert_eq!(watermark, 0x40000);
+        assert_eq!(kao.untyped()[1].wasted(), 0x10000);
+
+        // The next allocation in the untyped follows on from the last
+        let (allocation, watermark) = kao.alloc_n_within(PAGE, 1, PAGE, 0, 0x60000).unwrap();
+        assert_eq!(allocation.phys_addr, 0x11000);
+        assert_eq!(watermark, 0x11000);
+
+        assert!(kao.alloc_n_within(PAGE, 1, PAGE, 0x20000, 0x40000).is_err());
+    }
+
+    #[test]
+    fn test_disjoint_memory_region_exclude() {
+        let mut dmr = DisjointMemoryRegion::default();
+        dmr.insert_region(0x1000, 0x3000).unwrap();
+        dmr.insert_region(0x4000, 0x6000).unwrap();
+        dmr.exclude_region(0x2000, 0x5000);
+        dmr.exclude_region(0x8000, 0x9000);
+        assert_eq!(
+            dmr.regions().collect::<Vec<_>>(),
+            vec![
+                MemoryRegion::new(0x1000, 0x2000),
+                MemoryRegion::new(0x5000, 0x6000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_object_allocator_reserved_memory() {
+        let boot_info = BootInfo {
+            fixed_cap_count: 0,
+            sched_control_cap: 0,
+            paging_cap_count: 0,
+            page_cap_count: 0,
+            untyped_objects: vec![
+                UntypedObject::new(1, MemoryRegion::new(0x10000, 0x20000), false),
+                UntypedObject::new(2, MemoryRegion::new(0x20000, 0x30000), false),
+            ],
+            first_available_cap: 0,
+        };
+        let reserved = [MemoryRegion::new(0x14000, 0x24000)];
+        let mut kao = ObjectAllocator::new(&boot_info, AllocationPolicy::FirstFit, &reserved);
+        assert_eq!(kao.untyped()[0].free(), 0x4000);
+        assert_eq!(kao.untyped()[1].free(), 0);
+
+        assert_eq!(kao.alloc_n(PAGE, 4).unwrap().phys_addr, 0x10000);
+        assert!(kao.alloc(PAGE).is_err());
+
+        let mut kao = ObjectAllocator::new(&boot_info, AllocationPolicy::FirstFit, &reserved);
+        kao.alloc(PAGE).unwrap();
+        assert_eq!(kao.free_start(1), 0x11000);
+        kao.advance(1, 0x13000);
+        assert_eq!(kao.alloc(PAGE).unwrap().phys_addr, 0x13000);
+        assert!(kao.alloc(PAGE).is_err());
+    }
+
+    #[test]
+    fn test_object_allocator_policies() {
+        // For an allocation of 0x2000 bytes:
+        //  - 1 is the lowest, but leaves the most free space
+        //  - 2 needs padding for alignment, but is then filled exactly
+        //  - 3 needs no padding and is smaller than 1
+        let boot_info = BootInfo {
+            fixed_cap_count: 0,
+            sched_control_cap: 0,
+            paging_cap_count: 0,
+            page_cap_count: 0,
+            untyped_objects: vec![
+                UntypedObject::new(1, MemoryRegion::new(0x10000, 0x20000), false),
+                UntypedObject::new(2, MemoryRegion::new(0x31000, 0x34000), false),
+                UntypedObject::new(3, MemoryRegion::new(0x40000, 0x44000), false),
+            ],
+            first_available_cap: 0,
+        };
+        for (policy, cap, phys_addr) in [
+            (AllocationPolicy::FirstFit, 1, 0x10000),
+            (AllocationPolicy::BestFit, 2, 0x32000),
+            (AllocationPolicy::SizeClass, 3, 0x40000),
+        ] {
+            let mut kao = ObjectAllocator::new(&boot_info, policy, &[]);
+            let allocation = kao.alloc(0x2000).unwrap();
+            assert_eq!(allocation.untyped_cap_address, cap, "{:?}", policy);
+            assert_eq!(allocation.phys_addr, phys_addr, "{:?}", policy);
+        }
+    }
+
+    #[test]
+    fn test_disjoint_memory_region_policies() {
+        for (policy, base) in [
+            (AllocationPolicy::FirstFit, 0x1000),
+            (AllocationPolicy::BestFit, 0x11000),
+            (AllocationPolicy::SizeClass, 0x20000),
+        ] {
+            let mut dmr = DisjointMemoryRegion::default();
+            dmr.insert_region(0x1000, 0x5000).unwrap();
+            dmr.insert_region(0x10800, 0x12000).unwrap();
+            dmr.insert_region(0x20000, 0x22000).unwrap();
+            assert_eq!(
+                dmr.allocate_from(PAGE, 0, PAGE, policy),
+                Ok(base),
+                "{:?}",
+                policy
+            );
+        }
+    }
 
-        panic!("Can't alloc of size {}, count: {} - no space", size, count);
+    #[test]
+    fn test_disjoint_memory_region_without_merging() {
+        let mut dmr = DisjointMemoryRegion::without_merging();
+        dmr.insert_region(0x2000, 0x4000).unwrap();
+        dmr.insert_region(0x1000, 0x2000).unwrap();
+        dmr.insert_region(0x4000, 0x8000).unwrap();
+        assert!(dmr.insert_region(0x3000, 0x5000).is_err());
+        assert_eq!(
+            dmr.regions().collect::<Vec<_>>(),
+            vec![
+                MemoryRegion::new(0x1000, 0x2000),
+                MemoryRegion::new(0x2000, 0x4000),
+                MemoryRegion::new(0x4000, 0x8000)
+            ]
+        );
+        // The untypeds are created for each region separately
+        assert_eq!(
+            dmr.aligned_power_of_two_regions(47),
+            vec![
+                MemoryRegion::new(0x1000, 0x2000),
+                MemoryRegion::new(0x2000, 0x4000),
+                MemoryRegion::new(0x4000, 0x8000)
+            ]
+        );
+        // Removing the end of one region leaves the one after it alone
+        dmr.remove_region(0x3000, 0x4000).unwrap();
+        assert!(dmr.remove_region(0x1000, 0x3000).is_err());
+        assert_eq!(dmr.total_size(), 0x6000);
     }
 }
@@ -0,0 +1,402 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+// Lints for system descriptions that are valid, but are likely to be a mistake.
+//
+// Each lint has a stable code and name that can be used to silence it, or to
+// promote it to an error. Codes are never re-used, so new lints must be added
+// to the end of the list.
+
+use crate::sdf::{ProtectionDomain, SysMap, SystemDescription};
+
+/// The kernel rejects scheduling contexts with a budget below twice its
+/// worst-case execution time, which is 10us with the default WCET scale.
+const MIN_BUDGET_US: u64 = 20;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Lint {
+    UnusedMemoryRegion,
+    IsolatedPd,
+    UnusableChannel,
+    CachedDeviceMemory,
+    MixedCacheAttributes,
+    UncalledPassivePd,
+    LowBudget,
+}
+
+impl Lint {
+    pub fn all() -> [Lint; 7] {
+        [
+            Lint::UnusedMemoryRegion,
+            Lint::IsolatedPd,
+            Lint::UnusableChannel,
+            Lint::CachedDeviceMemory,
+            Lint::MixedCacheAttributes,
+            Lint::UncalledPassivePd,
+            Lint::LowBudget,
+        ]
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            Lint::UnusedMemoryRegion => "W001",
+            Lint::IsolatedPd => "W002",
+            Lint::UnusableChannel => "W003",
+            Lint::CachedDeviceMemory => "W004",
+            Lint::MixedCacheAttributes => "W005",
+            Lint::UncalledPassivePd => "W006",
+            Lint::LowBudget => "W007",
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedMemoryRegion => "unused-memory-region",
+            Lint::IsolatedPd => "isolated-pd",
+            Lint::UnusableChannel => "unusable-channel",
+            Lint::CachedDeviceMemory => "cached-device-memory",
+            Lint::MixedCacheAttributes => "mixed-cache-attributes",
+            Lint::UncalledPassivePd => "uncalled-passive-pd",
+            Lint::LowBudget => "low-budget",
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LintLevel {
+    Allow,
+    Warn,
+    Deny,
+}
+
+/// The level of each lint, as configured by the user.
+#[derive(Debug, Clone)]
+pub struct LintLevels {
+    levels: Vec<(Lint, LintLevel)>,
+}
+
+impl Default for LintLevels {
+    fn default() -> Self {
+        LintLevels {
+            levels: Lint::all()
+                .into_iter()
+                .map(|lint| (lint, LintLevel::Warn))
+                .collect(),
+        }
+    }
+}
+
+impl LintLevels {
+    /// Set the level of the lints matching 'spec', which is either a lint
+    /// code, a lint name or 'all'.
+    pub fn set(&mut self, spec: &str, level: LintLevel) -> Result<(), String> {
+        let mut found = false;
+        for (lint, lint_level) in &mut self.levels {
+            if spec == "all" || spec == lint.code() || spec == lint.name() {
+                *lint_level = level;
+                found = true;
+            }
+        }
+
+        match found {
+            true => Ok(()),
+            false => Err(format!("unknown lint '{}'", spec)),
+        }
+    }
+
+    pub fn level(&self, lint: Lint) -> LintLevel {
+        self.levels
+            .iter()
+            .find(|(l, _)| *l == lint)
+            .map(|(_, level)| *level)
+            .unwrap()
+    }
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub lint: Lint,
+    pub message: String,
+    /// Location in the parsed SDF file of the element the warning is about
+    pub text_pos: Option<roxmltree::TextPos>,
+}
+
+impl Warning {
+    fn new(lint: Lint, message: String, text_pos: Option<roxmltree::TextPos>) -> Warning {
+        Warning {
+            lint,
+            message,
+            text_pos,
+        }
+    }
+}
+
+/// Describe a budget that is too small for the kernel, if it is
+fn low_budget(budget: u64) -> Option<String> {
+    match budget {
+        0 => Some("a budget of zero and will never run".to_string()),
+        budget if budget < MIN_BUDGET_US => Some(format!(
+            "a budget of {} us, which is below the kernel's minimum of {} us",
+            budget, MIN_BUDGET_US
+        )),
+        _ => None,
+    }
+}
+
+/// Every map of a protection domain, including those of its virtual machine,
+/// along with the name of the PD or VM.
+fn all_maps(pd: &ProtectionDomain) -> Vec<(&String, &SysMap)> {
+    let mut maps: Vec<(&String, &SysMap)> = pd.maps.iter().map(|map| (&pd.name, map)).collect();
+    if let Some(vm) = &pd.virtual_machine {
+        maps.extend(vm.maps.iter().map(|map| (&vm.name, map)));
+    }
+    maps
+}
+
+/// Run every lint on 'system', regardless of its level.
+pub fn check(system: &SystemDescription) -> Vec<Warning> {
+    let pds = &system.protection_domains;
+    let maps: Vec<(&String, &SysMap)> = pds.iter().flat_map(all_maps).collect();
+    let mut warnings = Vec::new();
+
+    for mr in &system.memory_regions {
+        let mr_maps: Vec<&(&String, &SysMap)> =
+            maps.iter().filter(|(_, map)| map.mr == mr.name).collect();
+        if mr_maps.is_empty() {
+            warnings.push(Warning::new(
+                Lint::UnusedMemoryRegion,
+                format!("unused memory region '{}'", mr.name),
+                mr.text_pos,
+            ));
+        }
+
+        if mr.phys_addr.is_some() {
+            for (name, map) in mr_maps.iter().filter(|(_, map)| map.cached) {
+                warnings.push(Warning::new(
+                    Lint::CachedDeviceMemory,
+                    format!(
+                        "memory region '{}' has a physical address but is mapped cached in '{}'",
+                        mr.name, name
+                    ),
+                    map.text_pos,
+                ));
+            }
+        }
+
+        let cached: Vec<&String> = mr_maps
+            .iter()
+            .filter(|(_, map)| map.cached)
+            .map(|(name, _)| *name)
+            .collect();
+        let uncached: Vec<&String> = mr_maps
+            .iter()
+            .filter(|(_, map)| !map.cached)
+            .map(|(name, _)| *name)
+            .collect();
+        if !cached.is_empty() && !uncached.is_empty() {
+            warnings.push(Warning::new(
+                Lint::MixedCacheAttributes,
+                format!(
+                    "memory region '{}' is mapped cached in '{}' but uncached in '{}'",
+                    mr.name, cached[0], uncached[0]
+                ),
+                mr.text_pos,
+            ));
+        }
+    }
+
+    for (pd_idx, pd) in pds.iter().enumerate() {
+        let channels: Vec<_> = system
+            .channels
+            .iter()
+            .filter(|cc| cc.end_a.pd == pd_idx || cc.end_b.pd == pd_idx)
+            .collect();
+
+        if channels.is_empty() && pd.irqs.is_empty() && all_maps(pd).is_empty() {
+            warnings.push(Warning::new(
+                Lint::IsolatedPd,
+                format!(
+                    "protection domain '{}' has no channels, IRQs or maps",
+                    pd.name
+                ),
+                Some(pd.text_pos),
+            ));
+        }
+
+        let has_callers = channels.iter().any(|cc| {
+            (cc.end_a.pp && cc.end_b.pd == pd_idx) || (cc.end_b.pp && cc.end_a.pd == pd_idx)
+        });
+        if pd.passive && !has_callers {
+            warnings.push(Warning::new(
+                Lint::UncalledPassivePd,
+                format!(
+                    "passive protection domain '{}' has no protected procedure callers",
+                    pd.name
+                ),
+                Some(pd.text_pos),
+            ));
+        }
+
+        if let Some(budget) = low_budget(pd.budget).filter(|_| !pd.passive) {
+            warnings.push(Warning::new(
+                Lint::LowBudget,
+                format!("protection domain '{}' has {}", pd.name, budget),
+                Some(pd.text_pos),
+            ));
+        }
+        if let Some(vm) = &pd.virtual_machine {
+            if let Some(budget) = low_budget(vm.budget) {
+                warnings.push(Warning::new(
+                    Lint::LowBudget,
+                    format!("virtual machine '{}' has {}", vm.name, budget),
+                    Some(vm.text_pos),
+                ));
+            }
+        }
+    }
+
+    for cc in &system.channels {
+        if !cc.end_a.notify && !cc.end_b.notify && !cc.end_a.pp && !cc.end_b.pp {
+            warnings.push(Warning::new(
+                Lint::UnusableChannel,
+                format!(
+                    "channel between '{}' and '{}' cannot be used as neither end can notify or make a protected procedure call",
+                    pds[cc.end_a.pd].name, pds[cc.end_b.pd].name
+                ),
+                Some(cc.text_pos),
+            ));
+        }
+    }
+
+    warnings
+}
+
+/// Print each warning that is not allowed. Returns an error if any of the
+/// warnings have been denied. 'filename' is the path of the SDF and is only
+/// used for the location of each warning.
+pub fn report(filename: &str, warnings: &[Warning], levels: &LintLevels) -> Result<(), String> {
+    let mut denied = 0;
+    for warning in warnings {
+        let lint = warning.lint;
+        let loc = match warning.text_pos {
+            Some(pos) => format!("{}:{}:{}", filename, pos.row, pos.col),
+            None => filename.to_string(),
+        };
+        match levels.level(lint) {
+            LintLevel::Allow => {}
+            LintLevel::Warn => println!(
+                "WARNING: {} @ {} [{} {}]",
+                warning.message,
+                loc,
+                lint.code(),
+                lint.name()
+            ),
+            LintLevel::Deny => {
+                eprintln!(
+                    "Error: {} @ {} [{} {}]",
+                    warning.message,
+                    loc,
+                    lint.code(),
+                    lint.name()
+                );
+                denied += 1;
+            }
+        }
+    }
+
+    match denied {
+        0 => Ok(()),
+        _ => Err(format!(
+            "Error: {} denied lint warning(s) in the system description",
+            denied
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf;
+    use crate::sel4::test_util::config;
+
+    const SYSTEM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<system>
+    <memory_region name="unused" size="0x1000" />
+    <memory_region name="uart" size="0x1000" phys_addr="0x9000000" />
+    <memory_region name="shared" size="0x1000" />
+    <protection_domain name="driver" priority="2">
+        <program_image path="driver.elf" />
+        <map mr="uart" vaddr="0x4000000" perms="rw" />
+        <map mr="shared" vaddr="0x5000000" perms="rw" cached="false" />
+    </protection_domain>
+    <protection_domain name="client" priority="1" budget="0" period="100">
+        <program_image path="client.elf" />
+        <map mr="shared" vaddr="0x5000000" perms="rw" />
+    </protection_domain>
+    <protection_domain name="server" priority="3" passive="true">
+        <program_image path="server.elf" />
+    </protection_domain>
+    <protection_domain name="poller" priority="4" budget="10" period="1000">
+        <program_image path="poller.elf" />
+        <map mr="shared" vaddr="0x5000000" perms="r" cached="false" />
+    </protection_domain>
+    <channel>
+        <end pd="client" id="0" notify="false" />
+        <end pd="driver" id="0" notify="false" />
+    </channel>
+</system>
+"#;
+
+    #[test]
+    fn test_check() {
+        let system = sdf::parse("test.system", SYSTEM, &config()).unwrap();
+        let warnings: Vec<String> = check(&system)
+            .iter()
+            .map(|warning| {
+                let pos = warning.text_pos.unwrap();
+                format!(
+                    "{}:{}: {}: {}",
+                    pos.row,
+                    pos.col,
+                    warning.lint.code(),
+                    warning.message
+                )
+            })
+            .collect();
+
+        assert_eq!(
+            warnings,
+            vec![
+                "3:5: W001: unused memory region 'unused'",
+                "8:9: W004: memory region 'uart' has a physical address but is mapped cached in 'driver'",
+                "5:5: W005: memory region 'shared' is mapped cached in 'client' but uncached in 'driver'",
+                "11:5: W007: protection domain 'client' has a budget of zero and will never run",
+                "15:5: W002: protection domain 'server' has no channels, IRQs or maps",
+                "15:5: W006: passive protection domain 'server' has no protected procedure callers",
+                "18:5: W007: protection domain 'poller' has a budget of 10 us, which is below the kernel's minimum of 20 us",
+                "22:5: W003: channel between 'client' and 'driver' cannot be used as neither end can notify or make a protected procedure call",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_levels() {
+        let mut levels = LintLevels::default();
+        assert_eq!(levels.level(Lint::IsolatedPd), LintLevel::Warn);
+        levels.set("all", LintLevel::Deny).unwrap();
+        levels.set("W002", LintLevel::Allow).unwrap();
+        levels.set("low-budget", LintLevel::Allow).unwrap();
+        assert_eq!(levels.level(Lint::IsolatedPd), LintLevel::Allow);
+        assert_eq!(levels.level(Lint::LowBudget), LintLevel::Allow);
+        assert_eq!(levels.level(Lint::UnusableChannel), LintLevel::Deny);
+        assert!(levels.set("W999", LintLevel::Allow).is_err());
+
+        let warnings = [Warning::new(Lint::IsolatedPd, "isolated".to_string(), None)];
+        assert!(report("test.system", &warnings, &levels).is_ok());
+        levels.set("isolated-pd", LintLevel::Deny).unwrap();
+        assert!(report("test.system", &warnings, &levels).is_err());
+    }
+}
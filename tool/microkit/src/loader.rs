@@ -6,7 +6,7 @@
 
 use crate::elf::ElfFile;
 use crate::sel4::{Arch, Config};
-use crate::util::{kb, mask, mb, round_up, struct_to_bytes};
+use crate::util::{crc32, kb, mask, mb, round_up, struct_to_bytes};
 use crate::MemoryRegion;
 use std::fs::File;
 use std::io::{BufWriter, Write};
@@ -80,13 +80,12 @@ impl Riscv64 {
 
 /// Checks that each region in the given list does not overlap with any other region.
 /// Panics upon finding an overlapping region
-fn check_non_overlapping(regions: &Vec<(u64, &[u8])>) {
+fn check_non_overlapping(regions: &Vec<(u64, u64)>) {
     let mut checked: Vec<(u64, u64)> = Vec::new();
-    for (base, data) in regions {
-        let end = base + data.len() as u64;
+    for (base, end) in regions {
         // Check that this does not overlap with any checked regions
         for (b, e) in &checked {
-            if !(end <= *b || *base >= *e) {
+            if !(*end <= *b || *base >= *e) {
                 panic!(
                     "Overlapping regions: [{:x}..{:x}) overlaps [{:x}..{:x})",
                     base, end, b, e
@@ -94,10 +93,16 @@ fn check_non_overlapping(regions: &Vec<(u64, &[u8])>) {
             }
         }
 
-        checked.push((*base, end));
+        checked.push((*base, *end));
     }
 }
 
+const LOADER_MAGIC_64: u64 = 0x5e14dead14de5ead;
+
+/// Region types, these must match the loader
+const REGION_TYPE_DATA: u64 = 1;
+const REGION_TYPE_ZERO: u64 = 2;
+
 #[repr(C)]
 struct LoaderRegion64 {
     load_addr: u64,
@@ -120,7 +125,251 @@ struct LoaderHeader64 {
     num_regions: u64,
 }
 
+/// Format of the final image produced by the tool.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageType {
+    /// Raw binary, to be loaded at the loader's link address and jumped to.
+    Binary,
+    /// U-Boot legacy image (uImage), bootable with `bootm`.
+    UImage,
+    /// U-Boot Flattened Image Tree, bootable with `bootm`.
+    Fit,
+    /// Linux arm64 Image, bootable with `booti`.
+    LinuxArm64,
+}
+
+impl ImageType {
+    pub fn parse(s: &str) -> Option<ImageType> {
+        match s {
+            "binary" => Some(ImageType::Binary),
+            "uimage" => Some(ImageType::UImage),
+            "fit" => Some(ImageType::Fit),
+            "linux-arm64" => Some(ImageType::LinuxArm64),
+            _ => None,
+        }
+    }
+
+    pub fn valid_types() -> &'static [&'static str] {
+        &["binary", "uimage", "fit", "linux-arm64"]
+    }
+}
+
+const UIMAGE_MAGIC: u32 = 0x27051956;
+const UIMAGE_NAME_LEN: usize = 32;
+const UIMAGE_OS_LINUX: u8 = 5;
+const UIMAGE_ARCH_ARM64: u8 = 22;
+const UIMAGE_ARCH_RISCV: u8 = 26;
+const UIMAGE_TYPE_KERNEL: u8 = 2;
+const UIMAGE_COMP_NONE: u8 = 0;
+
+const ARM64_IMAGE_MAGIC: u32 = 0x644d5241;
+const ARM64_IMAGE_HEADER_SIZE: u64 = 64;
+/// Flags: little-endian, unspecified page size, image may be placed anywhere.
+const ARM64_IMAGE_FLAGS: u64 = 1 << 3;
+
+const FDT_MAGIC: u32 = 0xd00dfeed;
+const FDT_BEGIN_NODE: u32 = 0x1;
+const FDT_END_NODE: u32 = 0x2;
+const FDT_PROP: u32 = 0x3;
+const FDT_END: u32 = 0x9;
+
+/// Minimal flattened device tree writer, sufficient for producing
+/// FIT images. Nodes and properties are emitted in the order they
+/// are added.
+#[derive(Default)]
+struct FdtBuilder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+}
+
+impl FdtBuilder {
+    fn pad(&mut self) {
+        while self.structure.len() % 4 != 0 {
+            self.structure.push(0);
+        }
+    }
+
+    fn begin_node(&mut self, name: &str) {
+        self.structure.extend(FDT_BEGIN_NODE.to_be_bytes());
+        self.structure.extend(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+    }
+
+    fn end_node(&mut self) {
+        self.structure.extend(FDT_END_NODE.to_be_bytes());
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for s in self.strings.split(|b| *b == 0) {
+            if s == name.as_bytes() {
+                return offset as u32;
+            }
+            offset += s.len() + 1;
+        }
+        let offset = self.strings.len() as u32;
+        self.strings.extend(name.as_bytes());
+        self.strings.push(0);
+        offset
+    }
+
+    fn prop(&mut self, name: &str, value: &[u8]) {
+        let name_offset = self.string_offset(name);
+        self.structure.extend(FDT_PROP.to_be_bytes());
+        self.structure.extend((value.len() as u32).to_be_bytes());
+        self.structure.extend(name_offset.to_be_bytes());
+        self.structure.extend(value);
+        self.pad();
+    }
+
+    fn prop_str(&mut self, name: &str, value: &str) {
+        let mut bytes = value.as_bytes().to_vec();
+        bytes.push(0);
+        self.prop(name, &bytes);
+    }
+
+    fn prop_u32(&mut self, name: &str, value: u32) {
+        self.prop(name, &value.to_be_bytes());
+    }
+
+    fn prop_u64(&mut self, name: &str, value: u64) {
+        self.prop(name, &value.to_be_bytes());
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.structure.extend(FDT_END.to_be_bytes());
+
+        let header_size = 40;
+        // The memory reservation block is just the terminating entry.
+        let rsvmap_size = 16;
+        let off_mem_rsvmap = header_size;
+        let off_dt_struct = off_mem_rsvmap + rsvmap_size;
+        let off_dt_strings = off_dt_struct + self.structure.len();
+        let total_size = off_dt_strings + self.strings.len();
+
+        let mut fdt = Vec::with_capacity(total_size);
+        for field in [
+            FDT_MAGIC,
+            total_size as u32,
+            off_dt_struct as u32,
+            off_dt_strings as u32,
+            off_mem_rsvmap as u32,
+            17, // version
+            16, // last compatible version
+            0,  // boot_cpuid_phys
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            fdt.extend(field.to_be_bytes());
+        }
+        fdt.extend([0; 16]);
+        fdt.extend(self.structure);
+        fdt.extend(self.strings);
+
+        fdt
+    }
+}
+
+/// The loader header and regions found within an image written by
+/// 'Loader::write_image'.
+pub struct LoaderImage<'a> {
+    /// Physical address of the initial task
+    pub ui_p_reg_start: u64,
+    /// Offset to convert the initial task's virtual addresses to physical addresses
+    pub pv_offset: u64,
+    /// Base of the region reserved for the initial task
+    pub extra_device_addr_p: u64,
+    /// Size of the region reserved for the initial task
+    pub extra_device_size: u64,
+    /// Load address and data of each region
+    pub regions: Vec<(u64, &'a [u8])>,
+    /// Load address and size of each region that the loader zeroes
+    pub zero_regions: Vec<(u64, u64)>,
+}
+
+impl<'a> LoaderImage<'a> {
+    /// Find the loader header in an image of any of the supported image
+    /// types. The header comes straight after the loader itself, so we
+    /// search for the magic and check that the region metadata that follows
+    /// it is consistent with the rest of the image.
+    pub fn parse(image: &'a [u8]) -> Result<LoaderImage<'a>, String> {
+        let read = |offset: usize| -> Option<u64> {
+            let bytes = image.get(offset..offset + 8)?;
+            Some(u64::from_le_bytes(bytes.try_into().unwrap()))
+        };
+        let header_size = size_of::<LoaderHeader64>();
+        let region_size = size_of::<LoaderRegion64>();
+        let magic = LOADER_MAGIC_64.to_le_bytes();
+
+        for header_offset in 0..image.len().saturating_sub(header_size) {
+            if image[header_offset..header_offset + 8] != magic {
+                continue;
+            }
+
+            let header_word = |idx: usize| read(header_offset + idx * 8);
+            let num_regions = header_word(9).unwrap() as usize;
+            let metadata_offset = header_offset + header_size;
+            let data_offset = match num_regions
+                .checked_mul(region_size)
+                .and_then(|size| size.checked_add(metadata_offset))
+            {
+                Some(data_offset) if data_offset <= image.len() => data_offset,
+                _ => continue,
+            };
+
+            let mut regions = Vec::with_capacity(num_regions);
+            let mut zero_regions = Vec::new();
+            let mut expected_offset = 0;
+            for i in 0..num_regions {
+                let region_word = |idx: usize| read(metadata_offset + i * region_size + idx * 8);
+                let load_addr = region_word(0).unwrap();
+                let size = region_word(1).unwrap();
+                let offset = region_word(2).unwrap();
+                if region_word(3).unwrap() == REGION_TYPE_ZERO {
+                    zero_regions.push((load_addr, size));
+                    continue;
+                }
+                if offset != expected_offset {
+                    break;
+                }
+                let start = data_offset as u64 + offset;
+                let end = match start.checked_add(size) {
+                    Some(end) if end <= image.len() as u64 => end,
+                    _ => break,
+                };
+                regions.push((load_addr, &image[start as usize..end as usize]));
+                expected_offset += size;
+            }
+            if regions.len() + zero_regions.len() != num_regions {
+                continue;
+            }
+
+            return Ok(LoaderImage {
+                ui_p_reg_start: header_word(3).unwrap(),
+                pv_offset: header_word(5).unwrap(),
+                extra_device_addr_p: header_word(7).unwrap(),
+                extra_device_size: header_word(8).unwrap(),
+                regions,
+                zero_regions,
+            });
+        }
+
+        Err("could not find the loader header".to_string())
+    }
+
+    /// Data of the region loaded at 'load_addr'
+    pub fn region(&self, load_addr: u64) -> Option<&'a [u8]> {
+        self.regions
+            .iter()
+            .find(|(addr, _)| *addr == load_addr)
+            .map(|(_, data)| *data)
+    }
+}
+
 pub struct Loader<'a> {
+    arch: Arch,
+    image_vaddr: u64,
     image: Vec<u8>,
     header: LoaderHeader64,
     region_metadata: Vec<LoaderRegion64>,
@@ -128,6 +377,7 @@ pub struct Loader<'a> {
 }
 
 impl<'a> Loader<'a> {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         config: &Config,
         loader_elf_path: &Path,
@@ -136,6 +386,7 @@ impl<'a> Loader<'a> {
         initial_task_phys_base: Option<u64>,
         reserved_region: MemoryRegion,
         system_regions: Vec<(u64, &'a [u8])>,
+        zero_regions: Vec<MemoryRegion>,
     ) -> Loader<'a> {
         // Note: If initial_task_phys_base is not None, then it just this address
         // as the base physical address of the initial task, rather than the address
@@ -144,7 +395,7 @@ impl<'a> Loader<'a> {
         let sz = elf.word_size;
         let magic = match sz {
             32 => 0x5e14dead,
-            64 => 0x5e14dead14de5ead,
+            64 => LOADER_MAGIC_64,
             _ => panic!(
                 "Internal error: unexpected ELF word size: {} from '{}'",
                 sz,
@@ -176,10 +427,12 @@ impl<'a> Loader<'a> {
                     kernel_first_paddr = Some(segment.phys_addr);
                 }
 
-                if kernel_p_v_offset.is_none() {
-                    kernel_p_v_offset = Some(segment.virt_addr - segment.phys_addr);
-                } else if kernel_p_v_offset.unwrap() != segment.virt_addr - segment.phys_addr {
-                    panic!("Kernel does not have a consistent physical to virtual offset");
+                match kernel_p_v_offset {
+                    None => kernel_p_v_offset = Some(segment.virt_addr - segment.phys_addr),
+                    Some(offset) if offset != segment.virt_addr - segment.phys_addr => {
+                        panic!("Kernel does not have a consistent physical to virtual offset")
+                    }
+                    Some(_) => {}
                 }
 
                 regions.push((segment.phys_addr, segment.data.as_slice()));
@@ -270,8 +523,12 @@ impl<'a> Loader<'a> {
             }
         }
 
-        let mut all_regions_with_loader = all_regions.clone();
-        all_regions_with_loader.push((image_vaddr, &image));
+        let mut all_regions_with_loader: Vec<(u64, u64)> = all_regions
+            .iter()
+            .map(|(addr, data)| (*addr, addr + data.len() as u64))
+            .collect();
+        all_regions_with_loader.extend(zero_regions.iter().map(|r| (r.base, r.end)));
+        all_regions_with_loader.push((image_vaddr, image_vaddr + image.len() as u64));
         check_non_overlapping(&all_regions_with_loader);
 
         let flags = match config.hypervisor {
@@ -289,7 +546,7 @@ impl<'a> Loader<'a> {
             v_entry,
             extra_device_addr_p,
             extra_device_size,
-            num_regions: all_regions.len() as u64,
+            num_regions: (all_regions.len() + zero_regions.len()) as u64,
         };
 
         let mut region_metadata = Vec::new();
@@ -299,12 +556,23 @@ impl<'a> Loader<'a> {
                 load_addr: *addr,
                 size: data.len() as u64,
                 offset,
-                r#type: 1,
+                r#type: REGION_TYPE_DATA,
             });
             offset += data.len() as u64;
         }
+        // Zeroed regions have no data in the image
+        for r in &zero_regions {
+            region_metadata.push(LoaderRegion64 {
+                load_addr: r.base,
+                size: r.size(),
+                offset,
+                r#type: REGION_TYPE_ZERO,
+            });
+        }
 
         Loader {
+            arch: config.arch,
+            image_vaddr,
             image,
             header,
             region_metadata,
@@ -312,7 +580,7 @@ impl<'a> Loader<'a> {
         }
     }
 
-    pub fn write_image(&self, path: &Path) {
+    pub fn write_image(&self, path: &Path, image_type: ImageType) {
         let loader_file = match File::create(path) {
             Ok(file) => file,
             Err(e) => panic!("Could not create '{}': {}", path.display(), e),
@@ -320,6 +588,46 @@ impl<'a> Loader<'a> {
 
         let mut loader_buf = BufWriter::new(loader_file);
 
+        match image_type {
+            ImageType::Binary => self.write_payload(&mut loader_buf),
+            ImageType::UImage => {
+                let payload = self.payload();
+                loader_buf
+                    .write_all(&self.uimage_header(&payload))
+                    .expect("Failed to write uImage header to loader");
+                loader_buf
+                    .write_all(&payload)
+                    .expect("Failed to write payload to loader");
+            }
+            ImageType::Fit => {
+                let payload = self.payload();
+                loader_buf
+                    .write_all(&self.fit_image(&payload))
+                    .expect("Failed to write FIT image to loader");
+            }
+            ImageType::LinuxArm64 => {
+                let payload = self.payload();
+                loader_buf
+                    .write_all(&self.linux_arm64_header(&payload))
+                    .expect("Failed to write Image header to loader");
+                loader_buf
+                    .write_all(&payload)
+                    .expect("Failed to write payload to loader");
+            }
+        }
+
+        loader_buf.flush().unwrap();
+    }
+
+    /// The raw binary payload, i.e the loader image followed by the loader
+    /// metadata and all the region data.
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        self.write_payload(&mut payload);
+        payload
+    }
+
+    fn write_payload<W: Write>(&self, loader_buf: &mut W) {
         // First write out all the image data
         loader_buf
             .write_all(self.image.as_slice())
@@ -344,8 +652,121 @@ impl<'a> Loader<'a> {
                 .write_all(data)
                 .expect("Failed to write region data to loader");
         }
+    }
 
-        loader_buf.flush().unwrap();
+    /// The load address and entry point are the same since the
+    /// loader's entry point must be the first byte in the image.
+    /// Both are given to U-Boot as 32-bit values in a legacy image.
+    fn uimage_header(&self, payload: &[u8]) -> Vec<u8> {
+        let load_addr: u32 = self.image_vaddr.try_into().unwrap_or_else(|_| {
+            panic!(
+                "Loader address 0x{:x} does not fit in a uImage header",
+                self.image_vaddr
+            )
+        });
+        let size: u32 = payload
+            .len()
+            .try_into()
+            .expect("Loader image is too large for a uImage");
+        let arch = match self.arch {
+            Arch::Aarch64 => UIMAGE_ARCH_ARM64,
+            Arch::Riscv64 => UIMAGE_ARCH_RISCV,
+        };
+
+        let mut name = [0; UIMAGE_NAME_LEN];
+        let image_name = b"Microkit";
+        name[..image_name.len()].copy_from_slice(image_name);
+
+        let mut header = Vec::with_capacity(64);
+        header.extend(UIMAGE_MAGIC.to_be_bytes());
+        // The header checksum is computed with this field set to zero
+        header.extend(0_u32.to_be_bytes());
+        // Leave the timestamp as zero so that images are reproducible
+        header.extend(0_u32.to_be_bytes());
+        header.extend(size.to_be_bytes());
+        header.extend(load_addr.to_be_bytes());
+        header.extend(load_addr.to_be_bytes());
+        header.extend(crc32(payload).to_be_bytes());
+        header.extend([UIMAGE_OS_LINUX, arch, UIMAGE_TYPE_KERNEL, UIMAGE_COMP_NONE]);
+        header.extend(name);
+        assert!(header.len() == 64);
+
+        let header_crc = crc32(&header);
+        header[4..8].copy_from_slice(&header_crc.to_be_bytes());
+
+        header
+    }
+
+    /// The header is placed immediately before the loader image, so the image
+    /// must be loaded 64 bytes below the loader's link address. The first
+    /// instruction of the header branches over the rest of the header to the
+    /// loader's entry point.
+    ///
+    /// The 'placed anywhere' flag is set, `booti` then rounds the load address
+    /// minus text_offset up to 2MiB and adds text_offset back, so the image is
+    /// only left where the loader is linked if it is loaded there.
+    fn linux_arm64_header(&self, payload: &[u8]) -> Vec<u8> {
+        assert!(matches!(self.arch, Arch::Aarch64));
+
+        let image_start = self.image_vaddr - ARM64_IMAGE_HEADER_SIZE;
+        let text_offset = image_start % mb(2);
+        let image_size = ARM64_IMAGE_HEADER_SIZE + payload.len() as u64;
+        // 'b #64'
+        let code0: u32 = 0x14000000 | (ARM64_IMAGE_HEADER_SIZE / 4) as u32;
+
+        let mut header = Vec::with_capacity(ARM64_IMAGE_HEADER_SIZE as usize);
+        header.extend(code0.to_le_bytes());
+        header.extend(0_u32.to_le_bytes()); // code1
+        header.extend(text_offset.to_le_bytes());
+        header.extend(image_size.to_le_bytes());
+        header.extend(ARM64_IMAGE_FLAGS.to_le_bytes());
+        header.extend([0; 24]); // res2, res3, res4
+        header.extend(ARM64_IMAGE_MAGIC.to_le_bytes());
+        header.extend(0_u32.to_le_bytes()); // res5
+        assert!(header.len() as u64 == ARM64_IMAGE_HEADER_SIZE);
+
+        header
+    }
+
+    fn fit_image(&self, payload: &[u8]) -> Vec<u8> {
+        let arch = match self.arch {
+            Arch::Aarch64 => "arm64",
+            Arch::Riscv64 => "riscv",
+        };
+
+        let mut fdt = FdtBuilder::default();
+        fdt.begin_node("");
+        fdt.prop_str("description", "Microkit system image");
+        fdt.prop_u32("#address-cells", 2);
+
+        fdt.begin_node("images");
+        fdt.begin_node("loader");
+        fdt.prop_str("description", "Microkit loader");
+        fdt.prop("data", payload);
+        fdt.prop_str("type", "kernel");
+        fdt.prop_str("arch", arch);
+        fdt.prop_str("os", "linux");
+        fdt.prop_str("compression", "none");
+        fdt.prop_u64("load", self.image_vaddr);
+        fdt.prop_u64("entry", self.image_vaddr);
+        fdt.begin_node("hash-1");
+        fdt.prop_u32("value", crc32(payload));
+        fdt.prop_str("algo", "crc32");
+        fdt.end_node();
+        fdt.end_node();
+        fdt.end_node();
+
+        fdt.begin_node("configurations");
+        fdt.prop_str("default", "conf-1");
+        fdt.begin_node("conf-1");
+        fdt.prop_str("description", "Microkit system image");
+        fdt.prop_str("kernel", "loader");
+        fdt.end_node();
+        fdt.end_node();
+
+        fdt.end_node();
+
+        fdt.finish()
     }
 
     fn riscv64_setup_pagetables(
@@ -496,3 +917,182 @@ impl<'a> Loader<'a> {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push_words(image: &mut Vec<u8>, words: &[u64]) {
+        for word in words {
+            image.extend(word.to_le_bytes());
+        }
+    }
+
+    fn from_hex(lines: &[&str]) -> Vec<u8> {
+        let hex: String = lines.concat().replace('_', "");
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn loader(arch: Arch, image_vaddr: u64) -> Loader<'static> {
+        Loader {
+            arch,
+            image_vaddr,
+            image: Vec::new(),
+            header: LoaderHeader64 {
+                magic: LOADER_MAGIC_64,
+                flags: 0,
+                kernel_entry: 0,
+                ui_p_reg_start: 0,
+                ui_p_reg_end: 0,
+                pv_offset: 0,
+                v_entry: 0,
+                extra_device_addr_p: 0,
+                extra_device_size: 0,
+                num_regions: 0,
+            },
+            region_metadata: Vec::new(),
+            regions: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_uimage_header() {
+        let header = loader(Arch::Aarch64, 0x80280000).uimage_header(b"123456789");
+        let expected = from_hex(&[
+            "27051956", // magic
+            "d3c7382a", // header crc
+            "00000000", // timestamp
+            "00000009", // size
+            "80280000", // load address
+            "80280000", // entry point
+            "cbf43926", // data crc
+            "05160200", // os, arch, type, compression
+            "4d6963726f6b6974000000000000000000000000000000000000000000000000",
+        ]);
+        assert_eq!(header, expected);
+
+        let header = loader(Arch::Riscv64, 0x80200000).uimage_header(b"123456789");
+        assert_eq!(header[29], UIMAGE_ARCH_RISCV);
+    }
+
+    #[test]
+    fn test_fdt_builder() {
+        let mut fdt = FdtBuilder::default();
+        fdt.begin_node("");
+        fdt.prop_u32("a", 1);
+        fdt.begin_node("n");
+        fdt.prop_str("bc", "x");
+        fdt.prop_u64("a", 2);
+        fdt.end_node();
+        fdt.end_node();
+
+        let expected = from_hex(&[
+            // header
+            "d00dfeed", // magic
+            "0000008d", // totalsize
+            "00000038", // off_dt_struct
+            "00000088", // off_dt_strings
+            "00000028", // off_mem_rsvmap
+            "00000011", // version
+            "00000010", // last_comp_version
+            "00000000", // boot_cpuid_phys
+            "00000005", // size_dt_strings
+            "00000050", // size_dt_struct
+            // empty memory reservation block
+            "00000000000000000000000000000000",
+            // structure block
+            "00000001_00000000",                            // begin node ""
+            "00000003_00000004_00000000_00000001",          // a = <1>
+            "00000001_6e000000",                            // begin node "n"
+            "00000003_00000002_00000002_78000000",          // bc = "x"
+            "00000003_00000008_00000000_00000000_00000002", // a = /bits/ 64 <2>
+            "00000002",                                     // end node
+            "00000002",                                     // end node
+            "00000009",                                     // end
+            // strings block
+            "6100_626300",
+        ]);
+        assert_eq!(fdt.finish(), expected);
+    }
+
+    #[test]
+    fn test_linux_arm64_header() {
+        let header = loader(Arch::Aarch64, 0x80280000).linux_arm64_header(b"123456789");
+        let expected = from_hex(&[
+            "10000014",                                         // code0: b #64
+            "00000000",                                         // code1
+            "c0ff070000000000",                                 // text_offset
+            "4900000000000000",                                 // image_size
+            "0800000000000000",                                 // flags
+            "000000000000000000000000000000000000000000000000", // res2, res3, res4
+            "41524d64",                                         // magic
+            "00000000",                                         // res5
+        ]);
+        assert_eq!(header, expected);
+    }
+
+    #[test]
+    fn test_fit_image() {
+        let fit = loader(Arch::Aarch64, 0x80280000).fit_image(b"123456789");
+        let expected = from_hex(&[
+            "d00dfeed0000024e00000038000001f000000028000000110000001000000000",
+            "0000005e000001b8000000000000000000000000000000000000000100000000",
+            "0000000300000016000000004d6963726f6b69742073797374656d20696d6167",
+            "6500000000000003000000040000000c0000000200000001696d616765730000",
+            "000000016c6f6164657200000000000300000010000000004d6963726f6b6974",
+            "206c6f616465720000000003000000090000001b313233343536373839000000",
+            "0000000300000007000000206b65726e656c0000000000030000000600000025",
+            "61726d363400000000000003000000060000002a6c696e757800000000000003",
+            "000000050000002d6e6f6e650000000000000003000000080000003900000000",
+            "8028000000000003000000080000003e00000000802800000000000168617368",
+            "2d310000000000030000000400000044cbf4392600000003000000060000004a",
+            "637263333200000000000002000000020000000200000001636f6e6669677572",
+            "6174696f6e73000000000003000000070000004f636f6e662d31000000000001",
+            "636f6e662d3100000000000300000016000000004d6963726f6b697420737973",
+            "74656d20696d6167650000000000000300000007000000576c6f616465720000",
+            "000000020000000200000002000000096465736372697074696f6e0023616464",
+            "726573732d63656c6c73006461746100747970650061726368006f7300636f6d",
+            "7072657373696f6e006c6f616400656e7472790076616c756500616c676f0064",
+            "656661756c74006b65726e656c00",
+        ]);
+        assert_eq!(fit, expected);
+    }
+
+    #[test]
+    fn test_loader_image_parse() {
+        // The magic appearing within the loader itself should be skipped
+        let mut image = vec![0xaa; 13];
+        push_words(&mut image, &[LOADER_MAGIC_64, 0, 0, 0, 0, 0, 0, 0, 0, 1000]);
+
+        push_words(
+            &mut image,
+            &[
+                LOADER_MAGIC_64,
+                1,
+                0x4000,
+                0x8000,
+                0x9000,
+                0x100,
+                0x7f00,
+                0xa000,
+                0x1000,
+                2,
+            ],
+        );
+        push_words(&mut image, &[0x8000, 3, 0, 1, 0xa000, 2, 3, 1]);
+        image.extend([1, 2, 3, 4, 5]);
+
+        let loader_image = LoaderImage::parse(&image).unwrap();
+        assert_eq!(loader_image.ui_p_reg_start, 0x8000);
+        assert_eq!(loader_image.pv_offset, 0x100);
+        assert_eq!(loader_image.extra_device_addr_p, 0xa000);
+        assert_eq!(loader_image.region(0x8000), Some(&[1, 2, 3][..]));
+        assert_eq!(loader_image.region(0xa000), Some(&[4, 5][..]));
+        assert_eq!(loader_image.region(0xb000), None);
+
+        assert!(LoaderImage::parse(&image[..image.len() - 1]).is_err());
+    }
+}
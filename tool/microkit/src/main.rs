@@ -7,21 +7,25 @@
 // we want our asserts, even if the compiler figures out they hold true already during compile-time
 #![allow(clippy::assertions_on_constants)]
 
-use elf::ElfFile;
-use loader::Loader;
+use elf::{ElfFile, ElfSegment};
+use graph::GraphFormat;
+use lint::{LintLevel, LintLevels};
+use loader::{ImageType, Loader, LoaderImage};
 use microkit_tool::{
-    elf, loader, sdf, sel4, util, DisjointMemoryRegion, MemoryRegion, ObjectAllocator, Region,
-    UntypedObject, MAX_PDS, PD_MAX_NAME_LENGTH,
+    capdl, elf, graph, harden, lint, loader, sched, sdf, sel4, sim, util, AllocationPolicy,
+    DisjointMemoryRegion, KernelAllocation, MemoryRegion, ObjectAllocator, Region, UntypedObject,
+    MAX_PDS, PD_MAX_NAME_LENGTH,
 };
 use sdf::{
-    parse, ProtectionDomain, SysMap, SysMapPerms, SysMemoryRegion, SystemDescription,
-    VirtualMachine,
+    parse, ProtectionDomain, SysMap, SysMapPerms, SysMemoryRegion, SysReservedMemory,
+    SystemDescription, VirtualMachine,
 };
 use sel4::{
     default_vm_attr, Aarch64Regs, Arch, ArmVmAttributes, BootInfo, Config, Invocation,
     InvocationArgs, Object, ObjectType, PageSize, Rights, Riscv64Regs, RiscvVirtualMemory,
     RiscvVmAttributes,
 };
+use sim::Simulator;
 use std::cmp::{max, min};
 use std::collections::{HashMap, HashSet};
 use std::fs;
@@ -41,7 +45,6 @@ const FAULT_BADGE: u64 = 1 << 62;
 const PPC_BADGE: u64 = 1 << 63;
 
 const INPUT_CAP_IDX: u64 = 1;
-#[allow(dead_code)]
 const FAULT_EP_CAP_IDX: u64 = 2;
 const VSPACE_CAP_IDX: u64 = 3;
 const REPLY_CAP_IDX: u64 = 4;
@@ -172,7 +175,7 @@ impl<'a> InitSystem<'a> {
                 }
             })
             .collect();
-        device_untyped.sort_by(|a, b| a.ut.base().cmp(&b.ut.base()));
+        device_untyped.sort_by_key(|a| a.ut.base());
 
         let mut normal_untyped: Vec<FixedUntypedAlloc> = kernel_boot_info
             .untyped_objects
@@ -185,7 +188,7 @@ impl<'a> InitSystem<'a> {
                 }
             })
             .collect();
-        normal_untyped.sort_by(|a, b| a.ut.base().cmp(&b.ut.base()));
+        normal_untyped.sort_by_key(|a| a.ut.base());
 
         InitSystem {
             config,
@@ -229,13 +232,58 @@ impl<'a> InitSystem<'a> {
         }
     }
 
+    /// Consume the memory of 'untyped' from 'watermark' up to 'phys_address'
+    /// so that the next object retyped from it is placed at 'phys_address'.
+    fn pad_untyped(&mut self, untyped: u64, watermark: u64, phys_address: u64) {
+        // If the watermark isn't at the right spot, then we need to
+        // create padding objects until it is.
+        let mut padding_required = phys_address - watermark;
+        // We are restricted in how much we can pad:
+        // 1: Untyped objects must be power-of-two sized.
+        // 2: Untyped objects must be aligned to their size.
+        let mut padding_sizes = Vec::new();
+        // We have two potential approaches for how we pad.
+        // 1: Use largest objects possible respecting alignment
+        // and size restrictions.
+        // 2: Use a fixed size object multiple times. This will
+        // create more objects, but as same sized objects can be
+        // create in a batch, required fewer invocations.
+        // For now we choose #1
+        let mut wm = watermark;
+        while padding_required > 0 {
+            let wm_lsb = util::lsb(wm);
+            let sz_msb = util::msb(padding_required);
+            let pad_obejct_size = 1 << min(wm_lsb, sz_msb);
+            padding_sizes.push(pad_obejct_size);
+            wm += pad_obejct_size;
+            padding_required -= pad_obejct_size;
+        }
+
+        for sz in padding_sizes {
+            self.invocations.push(Invocation::new(
+                self.config,
+                InvocationArgs::UntypedRetype {
+                    untyped,
+                    object_type: ObjectType::Untyped,
+                    size_bits: sz.ilog2() as u64,
+                    root: self.cnode_cap,
+                    node_index: 1,
+                    node_depth: 1,
+                    node_offset: self.cap_slot,
+                    num_objects: 1,
+                },
+            ));
+            self.cap_slot += 1;
+        }
+    }
+
     /// Note: Fixed objects must be allocated in order!
     pub fn allocate_fixed_object(
         &mut self,
         phys_address: u64,
         object_type: ObjectType,
         name: String,
-    ) -> Object {
+    ) -> Result<Object, String> {
         assert!(phys_address >= self.last_fixed_address);
         assert!(object_type.fixed_size(self.config).is_some());
 
@@ -260,83 +308,47 @@ impl<'a> InitSystem<'a> {
         } else if let Some(fut) = normal_fut {
             fut
         } else {
-            panic!(
-                "Error: physical address {:x} not in any device untyped",
-                phys_address
-            )
+            return Err(format!(
+                "Error: could not allocate {}: physical address 0x{:x} is not in any untyped",
+                name, phys_address
+            ));
         };
 
+        // Objects at fixed addresses in normal memory must be allocated after
+        // anything the kernel object allocator has allocated from the untyped.
+        if !fut.ut.is_device {
+            fut.watermark = max(fut.watermark, self.kao.free_start(fut.ut.cap));
+        }
+
         let space_left = fut.ut.region.end - fut.watermark;
         if space_left < alloc_size {
-            for ut in &self.device_untyped {
-                let space_left = ut.ut.region.end - ut.watermark;
-                println!(
-                    "ut [0x{:x}..0x{:x}], space left: 0x{:x}",
-                    ut.ut.region.base, ut.ut.region.end, space_left
-                );
-            }
-            panic!(
-                "Error: allocation for physical address {:x} is too large ({:x}) for untyped",
-                phys_address, alloc_size
-            );
+            return Err(format!(
+                "Error: could not allocate {}: need 0x{:x} bytes at physical address 0x{:x} but the untyped [0x{:x}..0x{:x}) only has 0x{:x} bytes left",
+                name, alloc_size, phys_address, fut.ut.region.base, fut.ut.region.end, space_left
+            ));
         }
 
         if phys_address < fut.watermark {
-            panic!(
-                "Error: physical address {:x} is below watermark",
-                phys_address
-            );
+            return Err(format!(
+                "Error: could not allocate {}: physical address 0x{:x} is already in use",
+                name, phys_address
+            ));
         }
 
-        if fut.watermark != phys_address {
-            // If the watermark isn't at the right spot, then we need to
-            // create padding objects until it is.
-            let mut padding_required = phys_address - fut.watermark;
-            // We are restricted in how much we can pad:
-            // 1: Untyped objects must be power-of-two sized.
-            // 2: Untyped objects must be aligned to their size.
-            let mut padding_sizes = Vec::new();
-            // We have two potential approaches for how we pad.
-            // 1: Use largest objects possible respecting alignment
-            // and size restrictions.
-            // 2: Use a fixed size object multiple times. This will
-            // create more objects, but as same sized objects can be
-            // create in a batch, required fewer invocations.
-            // For now we choose #1
-            let mut wm = fut.watermark;
-            while padding_required > 0 {
-                let wm_lsb = util::lsb(wm);
-                let sz_msb = util::msb(padding_required);
-                let pad_obejct_size = 1 << min(wm_lsb, sz_msb);
-                padding_sizes.push(pad_obejct_size);
-                wm += pad_obejct_size;
-                padding_required -= pad_obejct_size;
-            }
-
-            for sz in padding_sizes {
-                self.invocations.push(Invocation::new(
-                    self.config,
-                    InvocationArgs::UntypedRetype {
-                        untyped: fut.ut.cap,
-                        object_type: ObjectType::Untyped,
-                        size_bits: sz.ilog2() as u64,
-                        root: self.cnode_cap,
-                        node_index: 1,
-                        node_depth: 1,
-                        node_offset: self.cap_slot,
-                        num_objects: 1,
-                    },
-                ));
-                self.cap_slot += 1;
-            }
+        let untyped = fut.ut.cap;
+        let watermark = fut.watermark;
+        fut.watermark = phys_address + alloc_size;
+        if !fut.ut.is_device {
+            self.kao.advance(untyped, fut.watermark);
         }
+        self.pad_untyped(untyped, watermark, phys_address);
 
         let object_cap = self.cap_slot;
         self.cap_slot += 1;
         self.invocations.push(Invocation::new(
             self.config,
             InvocationArgs::UntypedRetype {
-                untyped: fut.ut.cap,
+                untyped,
                 object_type,
                 size_bits: 0,
                 root: self.cnode_cap,
@@ -347,7 +359,6 @@ impl<'a> InitSystem<'a> {
             },
         ));
 
-        fut.watermark = phys_address + alloc_size;
         self.last_fixed_address = phys_address + alloc_size;
         let cap_addr = self.cnode_mask | object_cap;
         let kernel_object = Object {
@@ -358,7 +369,7 @@ impl<'a> InitSystem<'a> {
         self.objects.push(kernel_object);
         self.cap_address_names.insert(cap_addr, name);
 
-        kernel_object
+        Ok(kernel_object)
     }
 
     pub fn allocate_objects(
@@ -366,10 +377,10 @@ impl<'a> InitSystem<'a> {
         object_type: ObjectType,
         names: Vec<String>,
         size: Option<u64>,
-    ) -> Vec<Object> {
+    ) -> Result<Vec<Object>, String> {
         // Nothing to do if we get a zero count.
         if names.is_empty() {
-            return Vec::new();
+            return Ok(Vec::new());
         }
 
         let count = names.len() as u64;
@@ -390,7 +401,76 @@ impl<'a> InitSystem<'a> {
             panic!("Internal error: invalid object type: {:?}", object_type);
         }
 
-        let allocation = self.kao.alloc_n(alloc_size, count);
+        let allocation = match self.kao.alloc_n(alloc_size, count) {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                let objects = if count == 1 {
+                    format!("'{}'", names[0])
+                } else {
+                    format!("'{}' to '{}'", names[0], names[names.len() - 1])
+                };
+                return Err(format!(
+                    "Error: could not allocate {} {} object(s) ({}): {}",
+                    count,
+                    object_type.to_str(),
+                    objects,
+                    err
+                ));
+            }
+        };
+        Ok(self.retype_objects(allocation, object_type, names, api_size, alloc_size))
+    }
+
+    /// Allocate page objects that are physically contiguous, placed at an address
+    /// aligned to 'alignment' such that all of them are within [lower, upper).
+    pub fn allocate_contiguous_objects(
+        &mut self,
+        object_type: ObjectType,
+        names: Vec<String>,
+        alignment: u64,
+        lower: u64,
+        upper: u64,
+    ) -> Result<Vec<Object>, String> {
+        assert!(!names.is_empty());
+        let count = names.len() as u64;
+        let alloc_size = object_type.fixed_size(self.config).unwrap();
+
+        let (allocation, watermark) = match self
+            .kao
+            .alloc_n_within(alloc_size, count, alignment, lower, upper)
+        {
+            Ok(allocation) => allocation,
+            Err(err) => {
+                return Err(format!(
+                    "Error: could not allocate {} {} object(s) ('{}' to '{}'): {}",
+                    count,
+                    object_type.to_str(),
+                    names[0],
+                    names[names.len() - 1],
+                    err
+                ))
+            }
+        };
+        self.pad_untyped(
+            allocation.untyped_cap_address,
+            watermark,
+            allocation.phys_addr,
+        );
+
+        Ok(self.retype_objects(allocation, object_type, names, 0, alloc_size))
+    }
+
+    /// Create the retype invocations for objects that have been allocated by
+    /// the kernel object allocator, and record the objects.
+    fn retype_objects(
+        &mut self,
+        allocation: KernelAllocation,
+        object_type: ObjectType,
+        names: Vec<String>,
+        api_size: u64,
+        alloc_size: u64,
+    ) -> Vec<Object> {
+        let count = names.len() as u64;
         let base_cap_slot = self.cap_slot;
         self.cap_slot += count;
 
@@ -439,10 +519,13 @@ impl<'a> InitSystem<'a> {
 
 struct BuiltSystem {
     number_of_system_caps: u64,
-    invocation_data: Vec<u8>,
     invocation_data_size: u64,
     bootstrap_invocations: Vec<Invocation>,
+    /// Number and size of the bootstrap invocations before batching
+    bootstrap_invocations_unbatched: (usize, u64),
     system_invocations: Vec<Invocation>,
+    /// Number and size of the system invocations before batching
+    system_invocations_unbatched: (usize, u64),
     kernel_boot_info: BootInfo,
     reserved_region: MemoryRegion,
     fault_ep_cap_address: u64,
@@ -451,14 +534,27 @@ struct BuiltSystem {
     tcb_caps: Vec<u64>,
     sched_caps: Vec<u64>,
     ntfn_caps: Vec<u64>,
+    /// Cap addresses of the CNode of each protection domain
+    cnode_caps: Vec<u64>,
     pd_elf_regions: Vec<Vec<Region>>,
+    /// Name and physical address of each memory region with initial contents
+    mr_source_regions: Vec<(String, u64)>,
+    /// Name and physical address of each memory region the tool placed in
+    /// physically contiguous memory
+    mr_contiguous_regions: Vec<(String, u64)>,
     pd_setvar_values: Vec<Vec<u64>>,
     kernel_objects: Vec<Object>,
     initial_task_virt_region: MemoryRegion,
     initial_task_phys_region: MemoryRegion,
+    tool_large_page_mappings: u64,
+    object_allocator: ObjectAllocator,
 }
 
+/// Symbols describing the heap, written only if the program image has them
+const PD_HEAP_SYMBOLS: [&str; 2] = ["microkit_heap_start", "microkit_heap_size"];
+
 pub fn pd_write_symbols(
+    config: &Config,
     pds: &[ProtectionDomain],
     pd_elf_files: &mut [ElfFile],
     pd_setvar_values: &[Vec<u64>],
@@ -470,6 +566,16 @@ pub fn pd_write_symbols(
         elf.write_symbol("microkit_name", &name[..name_length])?;
         elf.write_symbol("microkit_passive", &[pd.passive as u8])?;
 
+        if let Some((heap_start, heap_end)) = pd.heap(config) {
+            let [start_symbol, size_symbol] = PD_HEAP_SYMBOLS;
+            if elf.find_symbol(start_symbol).is_ok() {
+                elf.write_symbol(start_symbol, &heap_start.to_le_bytes())?;
+            }
+            if elf.find_symbol(size_symbol).is_ok() {
+                elf.write_symbol(size_symbol, &(heap_end - heap_start).to_le_bytes())?;
+            }
+        }
+
         for (setvar_idx, setvar) in pd.setvars.iter().enumerate() {
             let value = pd_setvar_values[i][setvar_idx];
             let result = elf.write_symbol(&setvar.symbol, &value.to_le_bytes());
@@ -487,6 +593,125 @@ pub fn pd_write_symbols(
     Ok(())
 }
 
+fn elf_segment_perms(segment: &ElfSegment) -> u8 {
+    let mut perms = 0;
+    if segment.is_readable() {
+        perms |= SysMapPerms::Read as u8;
+    }
+    if segment.is_writable() {
+        perms |= SysMapPerms::Write as u8;
+    }
+    if segment.is_executable() {
+        perms |= SysMapPerms::Execute as u8;
+    }
+
+    perms
+}
+
+/// A segment can only be shared between PDs if it is not writable and none
+/// of the symbols patched by pd_write_symbols are within it, otherwise the
+/// per-PD contents would differ. When hardening, executable segments are not
+/// shared either, as executable memory must be private to each PD.
+fn elf_segment_shareable(
+    pd: &ProtectionDomain,
+    elf: &ElfFile,
+    segment: &ElfSegment,
+    harden: bool,
+) -> bool {
+    if !segment.loadable || segment.is_writable() || (harden && segment.is_executable()) {
+        return false;
+    }
+
+    let segment_end = segment.virt_addr + segment.mem_size();
+    let mut symbols = vec!["microkit_name", "microkit_passive"];
+    if pd.heap_size > 0 {
+        symbols.extend(PD_HEAP_SYMBOLS);
+    }
+    symbols.extend(pd.setvars.iter().map(|setvar| setvar.symbol.as_str()));
+
+    !symbols.iter().any(|symbol| match elf.find_symbol(symbol) {
+        Ok((vaddr, size)) => vaddr < segment_end && vaddr + size > segment.virt_addr,
+        Err(_) => false,
+    })
+}
+
+/// Find read-only segments that are identical to a segment of a PD earlier
+/// in the system, so that they can be backed by the same frames.
+/// Maps (PD index, segment index) to the (PD index, segment index) of the
+/// segment that should be shared.
+fn shared_elf_segments(
+    pds: &[ProtectionDomain],
+    pd_elf_files: &[ElfFile],
+    harden: bool,
+) -> HashMap<(usize, usize), (usize, usize)> {
+    let mut shared = HashMap::new();
+    let mut owners: Vec<(usize, usize)> = Vec::new();
+    for (pd_idx, pd) in pds.iter().enumerate() {
+        let elf = &pd_elf_files[pd_idx];
+        for (seg_idx, segment) in elf.segments.iter().enumerate() {
+            if !elf_segment_shareable(pd, elf, segment, harden) {
+                continue;
+            }
+
+            let owner = owners.iter().find(|(owner_pd_idx, owner_seg_idx)| {
+                let owner_segment = &pd_elf_files[*owner_pd_idx].segments[*owner_seg_idx];
+                owner_segment.virt_addr == segment.virt_addr
+                    && owner_segment.mem_size() == segment.mem_size()
+                    && owner_segment.is_readable() == segment.is_readable()
+                    && owner_segment.is_executable() == segment.is_executable()
+                    && owner_segment.data == segment.data
+            });
+
+            match owner {
+                Some(owner) => {
+                    shared.insert((pd_idx, seg_idx), *owner);
+                }
+                None => owners.push((pd_idx, seg_idx)),
+            }
+        }
+    }
+
+    shared
+}
+
+/// Memory created by the tool (ELF segments, stacks and heaps) is mapped with
+/// large pages wherever the alignment and size allow it. The large page aligned
+/// middle of the memory is mapped with large pages and the edges with small
+/// pages. Returns the offset, size and page size of each part of the memory.
+fn tool_memory_parts(vaddr: u64, size: u64) -> Vec<(u64, u64, PageSize)> {
+    let large_page_size = PageSize::Large as u64;
+    let end = vaddr + size;
+    let large_start = util::round_up(vaddr, large_page_size);
+    let large_end = util::round_down(end, large_page_size);
+    if large_start >= large_end {
+        return vec![(0, size, PageSize::Small)];
+    }
+
+    let mut parts = Vec::with_capacity(3);
+    if vaddr < large_start {
+        parts.push((0, large_start - vaddr, PageSize::Small));
+    }
+    parts.push((
+        large_start - vaddr,
+        large_end - large_start,
+        PageSize::Large,
+    ));
+    if large_end < end {
+        parts.push((large_end - vaddr, end - large_end, PageSize::Small));
+    }
+
+    parts
+}
+
+/// Name of the memory region for the part of tool-created memory at 'offset',
+/// the first part keeps the name of the memory itself.
+fn tool_memory_part_name(name: &str, offset: u64) -> String {
+    match offset {
+        0 => name.to_string(),
+        _ => format!("{}+0x{:x}", name, offset),
+    }
+}
+
 /// Determine the physical memory regions for an ELF file with a given
 /// alignment.
 ///
@@ -520,7 +745,7 @@ fn phys_mem_region_from_elf(elf: &ElfFile, alignment: u64) -> MemoryRegion {
 
 /// Determine the virtual memory regions for an ELF file with a given
 /// alignment.
-
+///
 /// The returned region shall be extended (if necessary) so that the start
 /// and end are congruent with the specified alignment (usually a page size).
 fn virt_mem_regions_from_elf(elf: &ElfFile, alignment: u64) -> Vec<MemoryRegion> {
@@ -676,52 +901,67 @@ fn kernel_boot_mem(kernel_elf: &ElfFile) -> MemoryRegion {
 /// This factors the common parts of 'emulate_kernel_boot' and
 /// 'emulate_kernel_boot_partial' to avoid code duplication.
 ///
-fn kernel_partial_boot(kernel_config: &Config, kernel_elf: &ElfFile) -> KernelPartialBootInfo {
+fn kernel_partial_boot(
+    kernel_config: &Config,
+    kernel_elf: &ElfFile,
+) -> Result<KernelPartialBootInfo, String> {
     // Determine the untyped caps of the system
     // This lets allocations happen correctly.
     let mut device_memory = DisjointMemoryRegion::default();
-    let mut normal_memory = DisjointMemoryRegion::default();
+    // The kernel keeps each region of physical memory separate, even when
+    // they are adjacent, and creates untyped objects for each of them.
+    let mut normal_memory = DisjointMemoryRegion::without_merging();
 
     // Start by allocating the entire physical address space
     // as device memory.
-    device_memory.insert_region(0, kernel_config.paddr_user_device_top);
+    device_memory
+        .insert_region(0, kernel_config.paddr_user_device_top)
+        .map_err(|err| format!("Error: could not add device memory: {}", err))?;
 
     // Next, remove all the kernel devices.
     // NOTE: There is an assumption each kernel device is one frame
     // in size only. It's possible this assumption could break in the
     // future.
     for paddr in kernel_device_addrs(kernel_config, kernel_elf) {
-        device_memory.remove_region(paddr, paddr + kernel_config.kernel_frame_size);
+        device_memory
+            .remove_region(paddr, paddr + kernel_config.kernel_frame_size)
+            .map_err(|err| format!("Error: could not reserve kernel device: {}", err))?;
     }
 
     // Remove all the actual physical memory from the device regions
     // but add it all to the actual normal memory regions
     for (start, end) in kernel_phys_mem(kernel_config, kernel_elf) {
-        device_memory.remove_region(start, end);
-        normal_memory.insert_region(start, end);
+        device_memory
+            .remove_region(start, end)
+            .map_err(|err| format!("Error: could not reserve physical memory: {}", err))?;
+        normal_memory
+            .insert_region(start, end)
+            .map_err(|err| format!("Error: could not add physical memory: {}", err))?;
     }
 
     // Remove the kernel image itself
     let self_mem = kernel_self_mem(kernel_elf);
-    normal_memory.remove_region(self_mem.base, self_mem.end);
+    normal_memory
+        .remove_region(self_mem.base, self_mem.end)
+        .map_err(|err| format!("Error: could not reserve kernel image: {}", err))?;
 
     // but get the boot region, we'll add that back later
     // FIXME: Why calcaultae it now if we add it back later?
     let boot_region = kernel_boot_mem(kernel_elf);
 
-    KernelPartialBootInfo {
+    Ok(KernelPartialBootInfo {
         device_memory,
         normal_memory,
         boot_region,
-    }
+    })
 }
 
 fn emulate_kernel_boot_partial(
     kernel_config: &Config,
     kernel_elf: &ElfFile,
-) -> (DisjointMemoryRegion, MemoryRegion) {
-    let partial_info = kernel_partial_boot(kernel_config, kernel_elf);
-    (partial_info.normal_memory, partial_info.boot_region)
+) -> Result<(DisjointMemoryRegion, MemoryRegion), String> {
+    let partial_info = kernel_partial_boot(kernel_config, kernel_elf)?;
+    Ok((partial_info.normal_memory, partial_info.boot_region))
 }
 
 fn get_n_paging(region: MemoryRegion, bits: u64) -> u64 {
@@ -794,15 +1034,20 @@ fn emulate_kernel_boot(
     initial_task_phys_region: MemoryRegion,
     initial_task_virt_region: MemoryRegion,
     reserved_region: MemoryRegion,
-) -> BootInfo {
+    reserved_memory: &[MemoryRegion],
+) -> Result<BootInfo, String> {
     assert!(initial_task_phys_region.size() == initial_task_virt_region.size());
-    let partial_info = kernel_partial_boot(config, kernel_elf);
+    let partial_info = kernel_partial_boot(config, kernel_elf)?;
     let mut normal_memory = partial_info.normal_memory;
     let device_memory = partial_info.device_memory;
     let boot_region = partial_info.boot_region;
 
-    normal_memory.remove_region(initial_task_phys_region.base, initial_task_phys_region.end);
-    normal_memory.remove_region(reserved_region.base, reserved_region.end);
+    normal_memory
+        .remove_region(initial_task_phys_region.base, initial_task_phys_region.end)
+        .map_err(|err| format!("Error: could not reserve initial task memory: {}", err))?;
+    normal_memory
+        .remove_region(reserved_region.base, reserved_region.end)
+        .map_err(|err| format!("Error: could not reserve the reserved region: {}", err))?;
 
     // Now, the tricky part! determine which memory is used for the initial task objects
     let initial_objects_size = calculate_rootserver_size(config, initial_task_virt_region);
@@ -813,7 +1058,7 @@ fn emulate_kernel_boot(
     // (or at least we hope it does!)
     // TOOD: this loop could be done better in a functional way?
     let mut region_to_remove: Option<u64> = None;
-    for region in normal_memory.regions.iter().rev() {
+    for region in normal_memory.regions().rev() {
         let start = util::round_down(
             region.end - initial_objects_size,
             1 << initial_objects_align,
@@ -824,9 +1069,25 @@ fn emulate_kernel_boot(
         }
     }
     if let Some(start) = region_to_remove {
-        normal_memory.remove_region(start, start + initial_objects_size);
+        // The kernel does not know about reserved memory, so we can only
+        // check that it will not place the objects there.
+        let initial_objects_end = start + initial_objects_size;
+        if let Some(r) = reserved_memory
+            .iter()
+            .find(|r| r.base < initial_objects_end && start < r.end)
+        {
+            return Err(format!(
+                "Error: the kernel will place the initial task kernel objects at [0x{:x}..0x{:x}) which overlaps with reserved memory [0x{:x}..0x{:x})",
+                start, initial_objects_end, r.base, r.end
+            ));
+        }
+        normal_memory.remove_region(start, initial_objects_end)?;
     } else {
-        panic!("Couldn't find appropriate region for initial task kernel objects");
+        return Err(format!(
+            "Error: could not find a region of {} bytes of normal memory for the initial task kernel objects; the largest free region is {} bytes",
+            initial_objects_size,
+            normal_memory.largest_region_size()
+        ));
     }
 
     let fixed_cap_count = 0x10;
@@ -864,24 +1125,40 @@ fn emulate_kernel_boot(
 
     let first_available_cap =
         first_untyped_cap + device_regions.len() as u64 + normal_regions.len() as u64;
-    BootInfo {
+    Ok(BootInfo {
         fixed_cap_count,
         paging_cap_count,
         page_cap_count,
         sched_control_cap,
         first_available_cap,
         untyped_objects,
-    }
+    })
+}
+
+/// Number of invocations and the size of their raw encoding.
+fn invocations_summary(config: &Config, invocations: &[Invocation]) -> (usize, u64) {
+    let size = invocations
+        .iter()
+        .map(|invocation| invocation.raw_size(config))
+        .sum();
+    (invocations.len(), size)
 }
 
+/// Build the system with the given system CNode and invocation table sizes.
+///
+/// The system invocations are not serialised here, only their size is
+/// determined, since the sizes may turn out to be too small for the system.
+#[allow(clippy::too_many_arguments)]
 fn build_system(
     config: &Config,
-    pd_elf_files: &Vec<ElfFile>,
+    pd_elf_files: &[ElfFile],
     kernel_elf: &ElfFile,
     monitor_elf: &ElfFile,
     system: &SystemDescription,
     invocation_table_size: u64,
     system_cnode_size: u64,
+    allocation_policy: AllocationPolicy,
+    harden: bool,
 ) -> Result<BuiltSystem, String> {
     assert!(util::is_power_of_two(system_cnode_size));
     assert!(invocation_table_size % config.minimum_page_size == 0);
@@ -909,29 +1186,127 @@ fn build_system(
     // and allows the monitor (initial task) to create memory regions
     // from this area, which can then be made available to the appropriate
     // protection domains
-    let mut pd_elf_size = 0;
-    for pd_elf in pd_elf_files {
-        for r in phys_mem_regions_from_elf(pd_elf, config.minimum_page_size) {
-            pd_elf_size += r.size();
+    //
+    // Segments that are shared between PDs are only stored once.
+    //
+    // The parts of segments that are mapped with large pages are placed at the
+    // end of the reserved region, starting at a large page aligned address.
+    //
+    // Memory regions with initial contents are placed in the same way, after
+    // the segments that use the same page size.
+    let shared_segments = shared_elf_segments(&system.protection_domains, pd_elf_files, harden);
+    let mut pd_elf_small_size = 0;
+    let mut pd_elf_large_size = 0;
+    for (i, pd_elf) in pd_elf_files.iter().enumerate() {
+        let loadable_segments = pd_elf
+            .segments
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| s.loadable);
+        let regions = virt_mem_regions_from_elf(pd_elf, config.minimum_page_size);
+        for ((seg_idx, _), r) in zip(loadable_segments, regions) {
+            if shared_segments.contains_key(&(i, seg_idx)) {
+                continue;
+            }
+            for (_, size, page_size) in tool_memory_parts(r.base, r.size()) {
+                if page_size == PageSize::Small {
+                    pd_elf_small_size += size;
+                } else {
+                    pd_elf_large_size += size;
+                }
+            }
+        }
+    }
+    let source_mrs: Vec<&SysMemoryRegion> = system
+        .memory_regions
+        .iter()
+        .filter(|mr| mr.source.is_some())
+        .collect();
+    let mut mr_source_small_size = 0;
+    let mut mr_source_large_size = 0;
+    for mr in &source_mrs {
+        match mr.page_size {
+            PageSize::Small => mr_source_small_size += mr.size,
+            PageSize::Large => mr_source_large_size += mr.size,
+            PageSize::Huge => unreachable!("source is not allowed with huge pages"),
         }
     }
-    let reserved_size = invocation_table_size + pd_elf_size;
+    let tool_small_size = pd_elf_small_size + mr_source_small_size;
+    let tool_large_size = pd_elf_large_size + mr_source_large_size;
+
+    // The invocation table is mapped with as many large pages as possible,
+    // with small pages for the remainder.
+    let large_page_size = PageSize::Large as u64;
+    let invocation_table_large_pages = invocation_table_size / large_page_size;
+    let invocation_table_small_pages =
+        (invocation_table_size % large_page_size) / config.minimum_page_size;
+
+    let tool_small_offset = invocation_table_size;
+    let tool_large_offset = if tool_large_size > 0 {
+        util::round_up(tool_small_offset + tool_small_size, large_page_size)
+    } else {
+        tool_small_offset + tool_small_size
+    };
+    let reserved_size = tool_large_offset + tool_large_size;
+    let reserved_alignment = if invocation_table_large_pages > 0 || tool_large_size > 0 {
+        large_page_size
+    } else {
+        config.minimum_page_size
+    };
 
     // Now that the size is determined, find a free region in the physical memory
     // space.
     let (mut available_memory, kernel_boot_region) =
-        emulate_kernel_boot_partial(config, kernel_elf);
+        emulate_kernel_boot_partial(config, kernel_elf)?;
+
+    // Memory that the kernel considers available may be reserved, e.g by
+    // firmware, in which case nothing can be placed there.
+    let reserved_memory: Vec<MemoryRegion> = system
+        .reserved_memory
+        .iter()
+        .map(|r| MemoryRegion::new(r.base, r.end()))
+        .collect();
+    for r in &reserved_memory {
+        available_memory.exclude_region(r.base, r.end);
+    }
 
+    // The allocation policy only applies to kernel objects; the reserved region
+    // and the initial task are always placed with first fit, i.e as low as
+    // possible in memory.
+    //
     // The kernel relies on the reserved region being allocated above the kernel
     // boot/ELF region, so we have the end of the kernel boot region as the lower
     // bound for allocating the reserved region.
-    let reserved_base = available_memory.allocate_from(reserved_size, kernel_boot_region.end);
+    let reserved_base = available_memory
+        .allocate_from(
+            reserved_size,
+            kernel_boot_region.end,
+            reserved_alignment,
+            AllocationPolicy::FirstFit,
+        )
+        .map_err(|err| {
+            format!(
+                "Error: could not allocate the reserved region for the invocation table and PD ELF segments: {}",
+                err
+            )
+        })?;
     assert!(kernel_boot_region.base < reserved_base);
     // The kernel relies on the initial task being allocated above the reserved
     // region, so we have the address of the end of the reserved region as the
     // lower bound for allocating the initial task.
-    let initial_task_phys_base =
-        available_memory.allocate_from(initial_task_size, reserved_base + reserved_size);
+    let initial_task_phys_base = available_memory
+        .allocate_from(
+            initial_task_size,
+            reserved_base + reserved_size,
+            config.minimum_page_size,
+            AllocationPolicy::FirstFit,
+        )
+        .map_err(|err| {
+            format!(
+                "Error: could not allocate the memory for the monitor: {}",
+                err
+            )
+        })?;
     assert!(reserved_base < initial_task_phys_base);
 
     let initial_task_phys_region = MemoryRegion::new(
@@ -957,7 +1332,8 @@ fn build_system(
         initial_task_phys_region,
         initial_task_virt_region,
         reserved_region,
-    );
+        &reserved_memory,
+    )?;
 
     for ut in &kernel_boot_info.untyped_objects {
         let dev_str = if ut.is_device { " (device)" } else { "" };
@@ -971,7 +1347,7 @@ fn build_system(
     }
 
     // The kernel boot info allows us to create an allocator for kernel objects
-    let mut kao = ObjectAllocator::new(&kernel_boot_info);
+    let mut kao = ObjectAllocator::new(&kernel_boot_info, allocation_policy, &reserved_memory);
 
     // 2. Now that the available resources are known it is possible to proceed with the
     // monitor task boot strap.
@@ -1009,13 +1385,17 @@ fn build_system(
     //  slot 0: the existing init cnode
     //  slot 1: our main system cnode
     let root_cnode_bits = 1;
-    let root_cnode_allocation = kao.alloc((1 << root_cnode_bits) * (1 << SLOT_BITS));
+    let root_cnode_allocation = kao
+        .alloc((1 << root_cnode_bits) * (1 << SLOT_BITS))
+        .map_err(|err| format!("Error: could not allocate the root CNode: {}", err))?;
     let root_cnode_cap = kernel_boot_info.first_available_cap;
     cap_address_names.insert(root_cnode_cap, "CNode: root".to_string());
 
     // 2.1.2: Allocate the *system* CNode. It is the cnodes that
     // will have enough slots for all required caps.
-    let system_cnode_allocation = kao.alloc(system_cnode_size * (1 << SLOT_BITS));
+    let system_cnode_allocation = kao
+        .alloc(system_cnode_size * (1 << SLOT_BITS))
+        .map_err(|err| format!("Error: could not allocate the system CNode: {}", err))?;
     let system_cnode_cap = kernel_boot_info.first_available_cap + 1;
     cap_address_names.insert(system_cnode_cap, "CNode: system".to_string());
 
@@ -1126,20 +1506,20 @@ fn build_system(
     // of the reserved region. We can retype multiple frames as a time (
     // which reduces the number of invocations we need). However, it is possible
     // that the region spans multiple untyped objects.
-    // At this point in time we assume we will map the area using the minimum
-    // page size. It would be good in the future to use super pages (when
-    // it makes sense to - this would reduce memory usage, and the number of
-    // invocations required to set up the address space
-    let pages_required = invocation_table_size / config.minimum_page_size;
+    // As much of the region as possible is mapped with large pages, this
+    // reduces memory usage and the number of invocations required to set up
+    // the address space. The remainder is mapped with the minimum page size.
+    let pages_required = invocation_table_large_pages + invocation_table_small_pages;
     let base_page_cap = 0;
     for pta in base_page_cap..base_page_cap + pages_required {
-        cap_address_names.insert(
-            system_cap_address_mask | pta,
-            "SmallPage: monitor invocation table".to_string(),
-        );
+        let page_str = if pta - base_page_cap < invocation_table_large_pages {
+            "LargePage: monitor invocation table"
+        } else {
+            "SmallPage: monitor invocation table"
+        };
+        cap_address_names.insert(system_cap_address_mask | pta, page_str.to_string());
     }
 
-    let mut remaining_pages = pages_required;
     let mut invocation_table_allocations = Vec::new();
     let mut cap_slot = base_page_cap;
     let mut phys_addr = invocation_table_region.base;
@@ -1149,35 +1529,48 @@ fn build_system(
         .iter()
         .filter(|o| o.is_device)
         .collect();
+    let mut remaining_pages = [
+        (ObjectType::LargePage, invocation_table_large_pages),
+        (ObjectType::SmallPage, invocation_table_small_pages),
+    ];
     for ut in boot_info_device_untypeds {
-        let ut_pages = ut.region.size() / config.minimum_page_size;
-        let retype_page_count = min(ut_pages, remaining_pages);
+        assert!(ut.base() <= phys_addr);
+        for (object_type, remaining) in remaining_pages.iter_mut() {
+            let page_size = object_type.fixed_size(config).unwrap();
+            let ut_pages = (ut.end() - phys_addr) / page_size;
+            let retype_page_count = min(ut_pages, *remaining);
+
+            let mut retypes_remaining = retype_page_count;
+            while retypes_remaining > 0 {
+                let num_retypes = min(retypes_remaining, config.fan_out_limit);
+                bootstrap_invocations.push(Invocation::new(
+                    config,
+                    InvocationArgs::UntypedRetype {
+                        untyped: ut.cap,
+                        object_type: *object_type,
+                        size_bits: 0,
+                        root: root_cnode_cap,
+                        node_index: 1,
+                        node_depth: 1,
+                        node_offset: cap_slot,
+                        num_objects: num_retypes,
+                    },
+                ));
 
-        let mut retypes_remaining = retype_page_count;
-        while retypes_remaining > 0 {
-            let num_retypes = min(retypes_remaining, config.fan_out_limit);
-            bootstrap_invocations.push(Invocation::new(
-                config,
-                InvocationArgs::UntypedRetype {
-                    untyped: ut.cap,
-                    object_type: ObjectType::SmallPage,
-                    size_bits: 0,
-                    root: root_cnode_cap,
-                    node_index: 1,
-                    node_depth: 1,
-                    node_offset: cap_slot,
-                    num_objects: num_retypes,
-                },
-            ));
+                retypes_remaining -= num_retypes;
+                cap_slot += num_retypes;
+            }
 
-            retypes_remaining -= num_retypes;
-            cap_slot += num_retypes;
+            *remaining -= retype_page_count;
+            phys_addr += retype_page_count * page_size;
+            // Small pages can only be allocated once all the large pages are.
+            if *remaining > 0 {
+                break;
+            }
         }
 
-        remaining_pages -= retype_page_count;
-        phys_addr += retype_page_count * config.minimum_page_size;
         invocation_table_allocations.push((ut, phys_addr));
-        if remaining_pages == 0 {
+        if remaining_pages.iter().all(|(_, remaining)| *remaining == 0) {
             break;
         }
     }
@@ -1189,90 +1582,121 @@ fn build_system(
     // invocations to occur at system startup. This should be enough for any reasonable
     // sized system.
     //
-    // Before mapping it is necessary to install page tables that can cover the region.
-    let large_page_size = ObjectType::LargePage.fixed_size(config).unwrap();
+    // Before mapping small pages it is necessary to install page tables that can
+    // cover the part of the region not mapped by large pages.
+    let page_vaddr: u64 = 0x8000_0000;
+    let small_page_vaddr = page_vaddr + invocation_table_large_pages * large_page_size;
     let page_table_size = ObjectType::PageTable.fixed_size(config).unwrap();
-    let page_tables_required =
-        util::round_up(invocation_table_size, large_page_size) / large_page_size;
-    let page_table_allocation = kao.alloc_n(page_table_size, page_tables_required);
+    let page_tables_required = util::round_up(
+        invocation_table_small_pages * config.minimum_page_size,
+        large_page_size,
+    ) / large_page_size;
     let base_page_table_cap = cap_slot;
 
-    for pta in base_page_table_cap..base_page_table_cap + page_tables_required {
-        cap_address_names.insert(
-            system_cap_address_mask | pta,
-            "PageTable: monitor".to_string(),
-        );
-    }
+    if page_tables_required > 0 {
+        let page_table_allocation =
+            kao.alloc_n(page_table_size, page_tables_required)
+                .map_err(|err| {
+                    format!(
+                        "Error: could not allocate the monitor invocation table page tables: {}",
+                        err
+                    )
+                })?;
+
+        for pta in base_page_table_cap..base_page_table_cap + page_tables_required {
+            cap_address_names.insert(
+                system_cap_address_mask | pta,
+                "PageTable: monitor".to_string(),
+            );
+        }
 
-    assert!(page_tables_required <= config.fan_out_limit);
-    bootstrap_invocations.push(Invocation::new(
-        config,
-        InvocationArgs::UntypedRetype {
-            untyped: page_table_allocation.untyped_cap_address,
-            object_type: ObjectType::PageTable,
-            size_bits: 0,
-            root: root_cnode_cap,
-            node_index: 1,
-            node_depth: 1,
-            node_offset: cap_slot,
-            num_objects: page_tables_required,
-        },
-    ));
-    cap_slot += page_tables_required;
+        assert!(page_tables_required <= config.fan_out_limit);
+        bootstrap_invocations.push(Invocation::new(
+            config,
+            InvocationArgs::UntypedRetype {
+                untyped: page_table_allocation.untyped_cap_address,
+                object_type: ObjectType::PageTable,
+                size_bits: 0,
+                root: root_cnode_cap,
+                node_index: 1,
+                node_depth: 1,
+                node_offset: cap_slot,
+                num_objects: page_tables_required,
+            },
+        ));
+        cap_slot += page_tables_required;
 
-    let page_table_vaddr: u64 = 0x8000_0000;
-    // Now that the page tables are allocated they can be mapped into vspace
-    let bootstrap_pt_attr = match config.arch {
-        Arch::Aarch64 => ArmVmAttributes::default(),
-        Arch::Riscv64 => RiscvVmAttributes::default(),
-    };
-    let mut pt_map_invocation = Invocation::new(
-        config,
-        InvocationArgs::PageTableMap {
-            page_table: system_cap_address_mask | base_page_table_cap,
-            vspace: INIT_VSPACE_CAP_ADDRESS,
-            vaddr: page_table_vaddr,
-            attr: bootstrap_pt_attr,
-        },
-    );
-    pt_map_invocation.repeat(
-        page_tables_required as u32,
-        InvocationArgs::PageTableMap {
-            page_table: 1,
-            vspace: 0,
-            vaddr: ObjectType::LargePage.fixed_size(config).unwrap(),
-            attr: 0,
-        },
-    );
-    bootstrap_invocations.push(pt_map_invocation);
+        // Now that the page tables are allocated they can be mapped into vspace
+        let bootstrap_pt_attr = match config.arch {
+            Arch::Aarch64 => ArmVmAttributes::default(),
+            Arch::Riscv64 => RiscvVmAttributes::default(),
+        };
+        let mut pt_map_invocation = Invocation::new(
+            config,
+            InvocationArgs::PageTableMap {
+                page_table: system_cap_address_mask | base_page_table_cap,
+                vspace: INIT_VSPACE_CAP_ADDRESS,
+                vaddr: small_page_vaddr,
+                attr: bootstrap_pt_attr,
+            },
+        );
+        pt_map_invocation.repeat(
+            page_tables_required as u32,
+            InvocationArgs::PageTableMap {
+                page_table: 1,
+                vspace: 0,
+                vaddr: large_page_size,
+                attr: 0,
+            },
+        );
+        bootstrap_invocations.push(pt_map_invocation);
+    }
 
     // Finally, once the page tables are allocated the pages can be mapped
-    let page_vaddr: u64 = 0x8000_0000;
     let bootstrap_page_attr = match config.arch {
         Arch::Aarch64 => ArmVmAttributes::default() | ArmVmAttributes::ExecuteNever as u64,
         Arch::Riscv64 => RiscvVmAttributes::default() | RiscvVmAttributes::ExecuteNever as u64,
     };
-    let mut map_invocation = Invocation::new(
-        config,
-        InvocationArgs::PageMap {
-            page: system_cap_address_mask | base_page_cap,
-            vspace: INIT_VSPACE_CAP_ADDRESS,
-            vaddr: page_vaddr,
-            rights: Rights::Read as u64,
-            attr: bootstrap_page_attr,
-        },
-    );
-    map_invocation.repeat(
-        pages_required as u32,
-        InvocationArgs::PageMap {
-            page: 1,
-            vspace: 0,
-            vaddr: config.minimum_page_size,
-            rights: 0,
-            attr: 0,
-        },
-    );
-    bootstrap_invocations.push(map_invocation);
+    let page_maps = [
+        (
+            base_page_cap,
+            page_vaddr,
+            invocation_table_large_pages,
+            large_page_size,
+        ),
+        (
+            base_page_cap + invocation_table_large_pages,
+            small_page_vaddr,
+            invocation_table_small_pages,
+            config.minimum_page_size,
+        ),
+    ];
+    for (page_cap, vaddr, count, page_size) in page_maps {
+        if count == 0 {
+            continue;
+        }
+        let mut map_invocation = Invocation::new(
+            config,
+            InvocationArgs::PageMap {
+                page: system_cap_address_mask | page_cap,
+                vspace: INIT_VSPACE_CAP_ADDRESS,
+                vaddr,
+                rights: Rights::Read as u64,
+                attr: bootstrap_page_attr,
+            },
+        );
+        map_invocation.repeat(
+            count as u32,
+            InvocationArgs::PageMap {
+                page: 1,
+                vspace: 0,
+                vaddr: page_size,
+                rights: 0,
+                attr: 0,
+            },
+        );
+        bootstrap_invocations.push(map_invocation);
+    }
 
     // 3. Now we can start setting up the system based on the information
     // the user provided in the System Description Format.
@@ -1291,7 +1715,8 @@ fn build_system(
     //     as needed by MRs
     //  Page table structs:
     //     as needed by protection domains based on mappings required
-    let mut phys_addr_next = reserved_base + invocation_table_size;
+    let mut phys_addr_next_small = reserved_base + tool_small_offset;
+    let mut phys_addr_next_large = reserved_base + tool_large_offset;
     // Now we create additional MRs (and mappings) for the ELF files.
     let mut pd_elf_regions: Vec<Vec<Region>> = Vec::with_capacity(system.protection_domains.len());
     let mut extra_mrs = Vec::new();
@@ -1303,91 +1728,151 @@ fn build_system(
                 continue;
             }
 
-            let segment_phys_addr = phys_addr_next + (segment.virt_addr % config.minimum_page_size);
-            pd_elf_regions[i].push(Region::new(
-                format!("PD-ELF {}-{}", pd.name, seg_idx),
-                segment_phys_addr,
-                segment.data.len() as u64,
-                seg_idx,
-            ));
-
-            let mut perms = 0;
-            if segment.is_readable() {
-                perms |= SysMapPerms::Read as u8;
-            }
-            if segment.is_writable() {
-                perms |= SysMapPerms::Write as u8;
-            }
-            if segment.is_executable() {
-                perms |= SysMapPerms::Execute as u8;
-            }
+            let perms = elf_segment_perms(segment);
 
             let base_vaddr = util::round_down(segment.virt_addr, config.minimum_page_size);
             let end_vaddr = util::round_up(
                 segment.virt_addr + segment.mem_size(),
                 config.minimum_page_size,
             );
-            let aligned_size = end_vaddr - base_vaddr;
-            let name = format!("ELF:{}-{}", pd.name, seg_idx);
-            let mr = SysMemoryRegion {
-                name,
-                size: aligned_size,
-                page_size: PageSize::Small,
-                page_count: aligned_size / PageSize::Small as u64,
-                phys_addr: Some(phys_addr_next),
-                text_pos: None,
-            };
-            phys_addr_next += aligned_size;
-
-            let mp = SysMap {
-                mr: mr.name.clone(),
-                vaddr: base_vaddr,
-                perms,
-                cached: true,
-                text_pos: None,
-            };
-            if let Some(extra_maps) = pd_extra_maps.get_mut(pd) {
-                extra_maps.push(mp);
-            } else {
-                pd_extra_maps.insert(pd, vec![mp]);
+            let parts = tool_memory_parts(base_vaddr, end_vaddr - base_vaddr);
+
+            if let Some((owner_pd_idx, owner_seg_idx)) = shared_segments.get(&(i, seg_idx)) {
+                // Map the frames of the identical segment from the other PD
+                // rather than creating new memory regions.
+                let owner_name = &system.protection_domains[*owner_pd_idx].name;
+                let owner_mr_name = format!("ELF:{}-{}", owner_name, owner_seg_idx);
+                for (offset, _, _) in parts {
+                    let mp = SysMap {
+                        mr: tool_memory_part_name(&owner_mr_name, offset),
+                        vaddr: base_vaddr + offset,
+                        perms,
+                        cached: true,
+                        guard: 0,
+                        text_pos: None,
+                    };
+                    pd_extra_maps.entry(pd).or_default().push(mp);
+                }
+                continue;
             }
 
-            // Add to extra_mrs at the end to avoid movement issues with the MR since it's used in
-            // constructing the SysMap struct
-            extra_mrs.push(mr);
+            let segment_end = segment.virt_addr + segment.mem_size();
+            for (offset, size, page_size) in parts {
+                let phys_addr_next = if page_size == PageSize::Small {
+                    &mut phys_addr_next_small
+                } else {
+                    &mut phys_addr_next_large
+                };
+                let part_vaddr = base_vaddr + offset;
+
+                // The part of the segment's data that is within this part
+                let data_start = max(part_vaddr, segment.virt_addr);
+                let data_end = min(part_vaddr + size, segment_end);
+                pd_elf_regions[i].push(Region::new(
+                    tool_memory_part_name(&format!("PD-ELF {}-{}", pd.name, seg_idx), offset),
+                    *phys_addr_next + (data_start - part_vaddr),
+                    data_end - data_start,
+                    seg_idx,
+                    data_start - segment.virt_addr,
+                ));
+
+                let name = tool_memory_part_name(&format!("ELF:{}-{}", pd.name, seg_idx), offset);
+                let mr = SysMemoryRegion {
+                    name,
+                    size,
+                    page_size,
+                    page_count: size / page_size as u64,
+                    phys_addr: Some(*phys_addr_next),
+                    source: None,
+                    source_offset: 0,
+                    contiguous: None,
+                    text_pos: None,
+                };
+                *phys_addr_next += size;
+
+                let mp = SysMap {
+                    mr: mr.name.clone(),
+                    vaddr: part_vaddr,
+                    perms,
+                    cached: true,
+                    guard: 0,
+                    text_pos: None,
+                };
+                pd_extra_maps.entry(pd).or_default().push(mp);
+
+                // Add to extra_mrs at the end to avoid movement issues with the MR since it's used in
+                // constructing the SysMap struct
+                extra_mrs.push(mr);
+            }
         }
     }
 
-    assert!(phys_addr_next - (reserved_base + invocation_table_size) == pd_elf_size);
+    assert!(phys_addr_next_small - (reserved_base + tool_small_offset) == pd_elf_small_size);
+    assert!(phys_addr_next_large - (reserved_base + tool_large_offset) == pd_elf_large_size);
+
+    // Memory regions with initial contents are backed by the reserved region,
+    // where the loader places their contents, rather than by any free memory.
+    let mut mr_source_regions = Vec::with_capacity(source_mrs.len());
+    let mut system_mrs = system.memory_regions.clone();
+    for mr in system_mrs.iter_mut().filter(|mr| mr.source.is_some()) {
+        let phys_addr_next = match mr.page_size {
+            PageSize::Small => &mut phys_addr_next_small,
+            _ => &mut phys_addr_next_large,
+        };
+        mr.phys_addr = Some(*phys_addr_next);
+        mr_source_regions.push((mr.name.clone(), *phys_addr_next));
+        *phys_addr_next += mr.size;
+    }
+    assert!(phys_addr_next_small - (reserved_base + tool_small_offset) == tool_small_size);
+    assert!(phys_addr_next_large - (reserved_base + tool_large_offset) == tool_large_size);
 
     // Here we create a memory region/mapping for the stack for each PD.
     // We allocate the stack at the highest possible virtual address that the
     // kernel allows us.
+    //
+    // The heap, if there is one, is placed below the stack and its guard.
     for pd in &system.protection_domains {
-        let stack_mr = SysMemoryRegion {
-            name: format!("STACK:{}", pd.name),
-            size: pd.stack_size,
-            page_size: PageSize::Small,
-            page_count: pd.stack_size / PageSize::Small as u64,
-            phys_addr: None,
-            text_pos: None,
-        };
+        let stack_vaddr = config.pd_stack_bottom(pd.stack_size);
+        let mut memory = vec![(format!("STACK:{}", pd.name), stack_vaddr, pd.stack_size)];
+        if let Some((heap_vaddr, heap_end)) = pd.heap(config) {
+            memory.push((
+                format!("HEAP:{}", pd.name),
+                heap_vaddr,
+                heap_end - heap_vaddr,
+            ));
+        }
 
-        let stack_map = SysMap {
-            mr: stack_mr.name.clone(),
-            vaddr: config.pd_stack_bottom(pd.stack_size),
-            perms: SysMapPerms::Read as u8 | SysMapPerms::Write as u8,
-            cached: true,
-            text_pos: None,
-        };
+        for (name, vaddr, size) in memory {
+            for (offset, part_size, page_size) in tool_memory_parts(vaddr, size) {
+                let mr = SysMemoryRegion {
+                    name: tool_memory_part_name(&name, offset),
+                    size: part_size,
+                    page_size,
+                    page_count: part_size / page_size as u64,
+                    phys_addr: None,
+                    source: None,
+                    source_offset: 0,
+                    contiguous: None,
+                    text_pos: None,
+                };
 
-        extra_mrs.push(stack_mr);
-        pd_extra_maps.get_mut(pd).unwrap().push(stack_map);
+                let map = SysMap {
+                    mr: mr.name.clone(),
+                    vaddr: vaddr + offset,
+                    perms: SysMapPerms::Read as u8 | SysMapPerms::Write as u8,
+                    cached: true,
+                    guard: 0,
+                    text_pos: None,
+                };
+
+                extra_mrs.push(mr);
+                pd_extra_maps.entry(pd).or_default().push(map);
+            }
+        }
     }
 
-    let mut all_mrs: Vec<&SysMemoryRegion> =
-        Vec::with_capacity(system.memory_regions.len() + extra_mrs.len());
-    for mr_set in [&system.memory_regions, &extra_mrs] {
+    let mut all_mrs: Vec<&SysMemoryRegion> = Vec::with_capacity(system_mrs.len() + extra_mrs.len());
+    for mr_set in [&system_mrs, &extra_mrs] {
         for mr in mr_set {
             all_mrs.push(mr);
         }
@@ -1395,6 +1880,18 @@ fn build_system(
     let all_mr_by_name: HashMap<&str, &SysMemoryRegion> =
         all_mrs.iter().map(|mr| (mr.name.as_str(), *mr)).collect();
 
+    // Each large page mapped for memory created by the tool replaces
+    // a page table full of small pages.
+    let mut tool_large_page_mappings = invocation_table_large_pages;
+    for maps in pd_extra_maps.values() {
+        for map in maps {
+            let mr = all_mr_by_name[map.mr.as_str()];
+            if mr.page_size == PageSize::Large {
+                tool_large_page_mappings += mr.page_count;
+            }
+        }
+    }
+
     let mut system_invocations: Vec<Invocation> = Vec::new();
     let mut init_system = InitSystem::new(
         config,
@@ -1412,6 +1909,7 @@ fn build_system(
     // 3.1 Work out how many regular (non-fixed) page objects are required
     let mut small_page_names = Vec::new();
     let mut large_page_names = Vec::new();
+    let mut huge_page_names = Vec::new();
 
     for pd in &system.protection_domains {
         let (page_size_human, page_size_label) = util::human_size_strict(PageSize::Small as u64);
@@ -1423,7 +1921,7 @@ fn build_system(
     }
 
     for mr in &all_mrs {
-        if mr.phys_addr.is_some() {
+        if mr.phys_addr.is_some() || mr.contiguous.is_some() {
             continue;
         }
 
@@ -1436,40 +1934,81 @@ fn build_system(
             match mr.page_size as PageSize {
                 PageSize::Small => small_page_names.push(page_str),
                 PageSize::Large => large_page_names.push(page_str),
+                PageSize::Huge => huge_page_names.push(page_str),
             }
         }
     }
 
+    let mut mr_pages: HashMap<&SysMemoryRegion, Vec<Object>> = HashMap::new();
+
+    // Contiguous MRs are allocated before any other pages as where they
+    // can be placed is constrained.
+    let mut mr_contiguous_regions = Vec::new();
+    for mr in &all_mrs {
+        if let Some(contiguous) = mr.contiguous {
+            let obj_type = match mr.page_size {
+                PageSize::Small => ObjectType::SmallPage,
+                PageSize::Large => ObjectType::LargePage,
+                PageSize::Huge => ObjectType::HugePage,
+            };
+            let (page_size_human, page_size_label) = util::human_size_strict(mr.page_size as u64);
+            let names = (0..mr.page_count)
+                .map(|idx| {
+                    format!(
+                        "Page({} {}): MR={} #{}",
+                        page_size_human, page_size_label, mr.name, idx
+                    )
+                })
+                .collect();
+            let objs = init_system.allocate_contiguous_objects(
+                obj_type,
+                names,
+                contiguous.align,
+                contiguous.phys_min,
+                contiguous.phys_max,
+            )?;
+            mr_contiguous_regions.push((mr.name.clone(), objs[0].phys_addr));
+            mr_pages.insert(mr, objs);
+        }
+    }
+
+    let huge_page_objs =
+        init_system.allocate_objects(ObjectType::HugePage, huge_page_names, None)?;
     let large_page_objs =
-        init_system.allocate_objects(ObjectType::LargePage, large_page_names, None);
+        init_system.allocate_objects(ObjectType::LargePage, large_page_names, None)?;
     let small_page_objs =
-        init_system.allocate_objects(ObjectType::SmallPage, small_page_names, None);
+        init_system.allocate_objects(ObjectType::SmallPage, small_page_names, None)?;
 
     // All the IPC buffers are the first to be allocated which is why this works
     let ipc_buffer_objs = &small_page_objs[..system.protection_domains.len()];
 
-    let mut mr_pages: HashMap<&SysMemoryRegion, Vec<Object>> = HashMap::new();
-
     let mut page_small_idx = ipc_buffer_objs.len();
     let mut page_large_idx = 0;
+    let mut page_huge_idx = 0;
 
     for mr in &all_mrs {
         if mr.phys_addr.is_some() {
             mr_pages.insert(mr, vec![]);
             continue;
         }
+        if mr.contiguous.is_some() {
+            continue;
+        }
         let idx = match mr.page_size {
             PageSize::Small => page_small_idx,
             PageSize::Large => page_large_idx,
+            PageSize::Huge => page_huge_idx,
         };
         let objs = match mr.page_size {
             PageSize::Small => small_page_objs[idx..idx + mr.page_count as usize].to_vec(),
             PageSize::Large => large_page_objs[idx..idx + mr.page_count as usize].to_vec(),
+            PageSize::Huge => huge_page_objs[idx..idx + mr.page_count as usize].to_vec(),
         };
         mr_pages.insert(mr, objs);
         match mr.page_size {
             PageSize::Small => page_small_idx += mr.page_count as usize,
             PageSize::Large => page_large_idx += mr.page_count as usize,
+            PageSize::Huge => page_huge_idx += mr.page_count as usize,
         }
     }
 
@@ -1495,6 +2034,7 @@ fn build_system(
         let obj_type = match mr.page_size {
             PageSize::Small => ObjectType::SmallPage,
             PageSize::Large => ObjectType::LargePage,
+            PageSize::Huge => ObjectType::HugePage,
         };
 
         let (page_size_human, page_size_label) = util::human_size_strict(mr.page_size as u64);
@@ -1502,7 +2042,7 @@ fn build_system(
             "Page({} {}): MR={} @ {:x}",
             page_size_human, page_size_label, mr.name, phys_addr
         );
-        let page = init_system.allocate_fixed_object(phys_addr, obj_type, name);
+        let page = init_system.allocate_fixed_object(phys_addr, obj_type, name)?;
         mr_pages.get_mut(mr).unwrap().push(page);
     }
 
@@ -1528,7 +2068,7 @@ fn build_system(
         }
     }
     tcb_names.extend(vcpu_tcb_names);
-    let tcb_objs = init_system.allocate_objects(ObjectType::Tcb, tcb_names, None);
+    let tcb_objs = init_system.allocate_objects(ObjectType::Tcb, tcb_names, None)?;
     let tcb_caps: Vec<u64> = tcb_objs.iter().map(|tcb| tcb.cap_addr).collect();
 
     let pd_tcb_objs = &tcb_objs[..system.protection_domains.len()];
@@ -1541,7 +2081,7 @@ fn build_system(
             vcpu_names.push(format!("VCPU-{}: VM={}", vcpu.id, vm.name));
         }
     }
-    let vcpu_objs = init_system.allocate_objects(ObjectType::Vcpu, vcpu_names, None);
+    let vcpu_objs = init_system.allocate_objects(ObjectType::Vcpu, vcpu_names, None)?;
     // Scheduling Contexts
     let mut sched_context_names: Vec<String> = system
         .protection_domains
@@ -1559,7 +2099,7 @@ fn build_system(
         ObjectType::SchedContext,
         sched_context_names,
         Some(PD_SCHEDCONTEXT_SIZE),
-    );
+    )?;
     let sched_context_caps: Vec<u64> = sched_context_objs.iter().map(|sc| sc.cap_addr).collect();
 
     let pd_sched_context_objs = &sched_context_objs[..system.protection_domains.len()];
@@ -1581,11 +2121,11 @@ fn build_system(
         .map(|pd| format!("Reply: PD={}", pd.name))
         .collect();
     let reply_names = [vec![format!("Reply: Monitor")], pd_reply_names].concat();
-    let reply_objs = init_system.allocate_objects(ObjectType::Reply, reply_names, None);
+    let reply_objs = init_system.allocate_objects(ObjectType::Reply, reply_names, None)?;
     let reply_obj = &reply_objs[0];
     // FIXME: Probably only need reply objects for PPs
     let pd_reply_objs = &reply_objs[1..];
-    let endpoint_objs = init_system.allocate_objects(ObjectType::Endpoint, endpoint_names, None);
+    let endpoint_objs = init_system.allocate_objects(ObjectType::Endpoint, endpoint_names, None)?;
     let fault_ep_endpoint_object = &endpoint_objs[0];
 
     // Because the first reply object is for the monitor, we map from index 1 of endpoint_objs
@@ -1613,7 +2153,7 @@ fn build_system(
         .map(|pd| format!("Notification: PD={}", pd.name))
         .collect();
     let notification_objs =
-        init_system.allocate_objects(ObjectType::Notification, notification_names, None);
+        init_system.allocate_objects(ObjectType::Notification, notification_names, None)?;
     let notification_caps = notification_objs.iter().map(|ntfn| ntfn.cap_addr).collect();
 
     // Determine number of upper directory / directory / page table objects required
@@ -1626,7 +2166,10 @@ fn build_system(
     //
     // Page table (level 3 table) is based on how many 2 MiB parts of the
     // address space is covered (excluding any 2MiB regions covered by large
-    // pages).
+    // or huge pages).
+    //
+    // Huge pages are mapped directly into the upper directory and so do not
+    // need a page directory.
     let mut all_pd_uds: Vec<(usize, u64)> = Vec::new();
     let mut all_pd_ds: Vec<(usize, u64)> = Vec::new();
     let mut all_pd_pts: Vec<(usize, u64)> = Vec::new();
@@ -1663,7 +2206,9 @@ fn build_system(
                 Arch::Riscv64 => {}
             }
 
-            directory_vaddrs.insert(util::mask_bits(vaddr, 12 + 9 + 9));
+            if page_size != PageSize::Huge {
+                directory_vaddrs.insert(util::mask_bits(vaddr, 12 + 9 + 9));
+            }
             if page_size == PageSize::Small {
                 page_table_vaddrs.insert(util::mask_bits(vaddr, 12 + 9));
             }
@@ -1717,7 +2262,9 @@ fn build_system(
             if config.arm_pa_size_bits.unwrap() != 40 {
                 upper_directory_vaddrs.insert(util::mask_bits(vaddr, 12 + 9 + 9 + 9));
             }
-            directory_vaddrs.insert(util::mask_bits(vaddr, 12 + 9 + 9));
+            if page_size != PageSize::Huge {
+                directory_vaddrs.insert(util::mask_bits(vaddr, 12 + 9 + 9));
+            }
             if page_size == PageSize::Small {
                 page_table_vaddrs.insert(util::mask_bits(vaddr, 12 + 9));
             }
@@ -1765,7 +2312,7 @@ fn build_system(
         .map(|vm| format!("VSpace: VM={}", vm.name))
         .collect();
     vspace_names.extend(vm_vspace_names);
-    let vspace_objs = init_system.allocate_objects(ObjectType::VSpace, vspace_names, None);
+    let vspace_objs = init_system.allocate_objects(ObjectType::VSpace, vspace_names, None)?;
     let pd_vspace_objs = &vspace_objs[..system.protection_domains.len()];
     let vm_vspace_objs = &vspace_objs[system.protection_domains.len()..];
 
@@ -1778,8 +2325,8 @@ fn build_system(
         .map(|(vm_idx, vaddr)| format!("PageTable: VM={} VADDR=0x{:x}", vm_names[*vm_idx], vaddr))
         .collect();
 
-    let pd_ud_objs = init_system.allocate_objects(ObjectType::PageTable, pd_ud_names, None);
-    let vm_ud_objs = init_system.allocate_objects(ObjectType::PageTable, vm_ud_names, None);
+    let pd_ud_objs = init_system.allocate_objects(ObjectType::PageTable, pd_ud_names, None)?;
+    let vm_ud_objs = init_system.allocate_objects(ObjectType::PageTable, vm_ud_names, None)?;
 
     if !config.hypervisor {
         assert!(vm_ud_objs.is_empty());
@@ -1793,8 +2340,8 @@ fn build_system(
         .iter()
         .map(|(vm_idx, vaddr)| format!("PageTable: VM={} VADDR=0x{:x}", vm_names[*vm_idx], vaddr))
         .collect();
-    let pd_d_objs = init_system.allocate_objects(ObjectType::PageTable, pd_d_names, None);
-    let vm_d_objs = init_system.allocate_objects(ObjectType::PageTable, vm_d_names, None);
+    let pd_d_objs = init_system.allocate_objects(ObjectType::PageTable, pd_d_names, None)?;
+    let vm_d_objs = init_system.allocate_objects(ObjectType::PageTable, vm_d_names, None)?;
 
     let pd_pt_names: Vec<String> = all_pd_pts
         .iter()
@@ -1804,8 +2351,8 @@ fn build_system(
         .iter()
         .map(|(vm_idx, vaddr)| format!("PageTable: VM={} VADDR=0x{:x}", vm_names[*vm_idx], vaddr))
         .collect();
-    let pd_pt_objs = init_system.allocate_objects(ObjectType::PageTable, pd_pt_names, None);
-    let vm_pt_objs = init_system.allocate_objects(ObjectType::PageTable, vm_pt_names, None);
+    let pd_pt_objs = init_system.allocate_objects(ObjectType::PageTable, pd_pt_names, None)?;
+    let vm_pt_objs = init_system.allocate_objects(ObjectType::PageTable, vm_pt_names, None)?;
 
     // Create CNodes - all CNode objects are the same size: 128 slots.
     let mut cnode_names: Vec<String> = system
@@ -1820,7 +2367,7 @@ fn build_system(
     cnode_names.extend(vm_cnode_names);
 
     let cnode_objs =
-        init_system.allocate_objects(ObjectType::CNode, cnode_names, Some(PD_CAP_SIZE));
+        init_system.allocate_objects(ObjectType::CNode, cnode_names, Some(PD_CAP_SIZE))?;
     let mut cnode_objs_by_pd: HashMap<&ProtectionDomain, &Object> =
         HashMap::with_capacity(system.protection_domains.len());
     for (i, pd) in system.protection_domains.iter().enumerate() {
@@ -2442,7 +2989,6 @@ fn build_system(
     // All minting is complete at this point
 
     // Associate badges
-    // FIXME: This could use repeat
     for pd in &system.protection_domains {
         for (irq_cap_address, badged_notification_cap_address) in
             zip(&irq_cap_addresses[pd], &badged_irq_caps[pd])
@@ -2823,10 +3369,16 @@ fn build_system(
 
     // And now we are finally done. We have all the invocations
 
-    let mut system_invocation_data: Vec<u8> = Vec::new();
-    for system_invocation in &system_invocations {
-        system_invocation.add_raw_invocation(config, &mut system_invocation_data);
-    }
+    // Batch runs of similar invocations to reduce the size of the invocation data
+    let bootstrap_invocations_unbatched = invocations_summary(config, &bootstrap_invocations);
+    let bootstrap_invocations = Invocation::batch(config, bootstrap_invocations);
+    let system_invocations_unbatched = invocations_summary(config, &system_invocations);
+    let system_invocations = Invocation::batch(config, system_invocations);
+
+    let invocation_data_size = system_invocations
+        .iter()
+        .map(|invocation| invocation.raw_size(config))
+        .sum();
 
     let pd_setvar_values: Vec<Vec<u64>> = system
         .protection_domains
@@ -2837,8 +3389,7 @@ fn build_system(
                 .map(|setvar| match &setvar.kind {
                     sdf::SysSetVarKind::Vaddr { address } => *address,
                     sdf::SysSetVarKind::Paddr { region } => {
-                        let mr = system
-                            .memory_regions
+                        let mr = system_mrs
                             .iter()
                             .find(|mr| mr.name == *region)
                             .unwrap_or_else(|| panic!("Cannot find region: {}", region));
@@ -2852,10 +3403,11 @@ fn build_system(
 
     Ok(BuiltSystem {
         number_of_system_caps: final_cap_slot,
-        invocation_data_size: system_invocation_data.len() as u64,
-        invocation_data: system_invocation_data,
+        invocation_data_size,
         bootstrap_invocations,
+        bootstrap_invocations_unbatched,
         system_invocations,
+        system_invocations_unbatched,
         kernel_boot_info,
         reserved_region,
         fault_ep_cap_address: fault_ep_endpoint_object.cap_addr,
@@ -2864,19 +3416,29 @@ fn build_system(
         tcb_caps: tcb_caps[..system.protection_domains.len()].to_vec(),
         sched_caps: sched_context_caps,
         ntfn_caps: notification_caps,
+        cnode_caps: cnode_objs[..system.protection_domains.len()]
+            .iter()
+            .map(|cnode| cnode.cap_addr)
+            .collect(),
         pd_elf_regions,
+        mr_source_regions,
+        mr_contiguous_regions,
         pd_setvar_values,
         kernel_objects,
         initial_task_phys_region,
         initial_task_virt_region,
+        tool_large_page_mappings,
+        object_allocator: kao,
     })
 }
 
 fn write_report<W: std::io::Write>(
     buf: &mut BufWriter<W>,
     config: &Config,
+    system: &SystemDescription,
     built_system: &BuiltSystem,
     bootstrap_invocation_data: &[u8],
+    schedulability: Option<&sched::Analysis>,
 ) -> std::io::Result<()> {
     writeln!(buf, "# Kernel Boot Info\n")?;
 
@@ -2906,6 +3468,74 @@ fn write_report<W: std::io::Write>(
             writeln!(buf, "       {}", region)?;
         }
     }
+    for (mr_name, phys_addr) in &built_system.mr_source_regions {
+        let mr = system
+            .memory_regions
+            .iter()
+            .find(|mr| mr.name == *mr_name)
+            .unwrap();
+        writeln!(
+            buf,
+            "       <Region name=MR {} addr=0x{:x} size={}>",
+            mr_name, phys_addr, mr.size
+        )?;
+    }
+    let mut guard_regions = Vec::new();
+    for pd in &system.protection_domains {
+        if let Some((start, end)) = pd.stack_guard(config) {
+            guard_regions.push(format!("{}: stack [0x{:x}..0x{:x})", pd.name, start, end));
+        }
+        let mut maps: Vec<(&String, &SysMap)> = pd.maps.iter().map(|map| (&pd.name, map)).collect();
+        if let Some(vm) = &pd.virtual_machine {
+            maps.extend(vm.maps.iter().map(|map| (&vm.name, map)));
+        }
+        for (name, map) in maps {
+            let mr = system
+                .memory_regions
+                .iter()
+                .find(|mr| mr.name == map.mr)
+                .unwrap();
+            for (start, end) in map.guard_regions(mr.size) {
+                guard_regions.push(format!(
+                    "{}: map for '{}' [0x{:x}..0x{:x})",
+                    name, map.mr, start, end
+                ));
+            }
+        }
+    }
+    if !guard_regions.is_empty() {
+        writeln!(buf, "\n# Guard Regions\n")?;
+        for guard_region in &guard_regions {
+            writeln!(buf, "     {}", guard_region)?;
+        }
+    }
+    if !built_system.mr_contiguous_regions.is_empty() {
+        writeln!(buf, "\n# Contiguous Memory Regions\n")?;
+    }
+    for (mr_name, phys_addr) in &built_system.mr_contiguous_regions {
+        let mr = system
+            .memory_regions
+            .iter()
+            .find(|mr| mr.name == *mr_name)
+            .unwrap();
+        writeln!(
+            buf,
+            "     {}: [0x{:x}..0x{:x})",
+            mr_name,
+            phys_addr,
+            phys_addr + mr.size
+        )?;
+    }
+    if !system.reserved_memory.is_empty() {
+        writeln!(buf, "\n# Reserved Memory\n")?;
+    }
+    for r in &system.reserved_memory {
+        let source = match r.text_pos {
+            Some(_) => "system description",
+            None => "board",
+        };
+        writeln!(buf, "     [0x{:x}..0x{:x}) ({})", r.base, r.end(), source)?;
+    }
     writeln!(buf, "\n# Monitor (Initial Task) Info\n")?;
     writeln!(
         buf,
@@ -2917,6 +3547,31 @@ fn write_report<W: std::io::Write>(
         "     physical memory: {}",
         built_system.initial_task_phys_region
     )?;
+    writeln!(buf, "\n# Large Pages For Tool-Created Memory\n")?;
+    let large_page_mappings = built_system.tool_large_page_mappings;
+    let small_pages_per_large_page = PageSize::Large as u64 / PageSize::Small as u64;
+    writeln!(
+        buf,
+        "     # of large page mappings        : {:>10}",
+        comma_sep_u64(large_page_mappings)
+    )?;
+    writeln!(
+        buf,
+        "     # of small page mappings saved  : {:>10}",
+        comma_sep_u64(large_page_mappings * (small_pages_per_large_page - 1))
+    )?;
+    writeln!(buf, "\n# Untyped Allocation Summary\n")?;
+    for ut in built_system.object_allocator.untyped() {
+        writeln!(
+            buf,
+            "     Untyped @ 0x{:x}:0x{:x}: used: {:>14}  wasted on alignment: {:>14}  free: {:>14}",
+            ut.base(),
+            ut.untyped().region.size(),
+            comma_sep_u64(ut.used()),
+            comma_sep_u64(ut.wasted()),
+            comma_sep_u64(ut.free())
+        )?;
+    }
     writeln!(buf, "\n# Allocated Kernel Objects Summary\n")?;
     writeln!(
         buf,
@@ -2934,6 +3589,16 @@ fn write_report<W: std::io::Write>(
         "     size of invocations: {:>10}",
         comma_sep_usize(bootstrap_invocation_data.len())
     )?;
+    writeln!(
+        buf,
+        "     # unbatched        : {:>10}",
+        comma_sep_usize(built_system.bootstrap_invocations_unbatched.0)
+    )?;
+    writeln!(
+        buf,
+        "     size unbatched     : {:>10}",
+        comma_sep_u64(built_system.bootstrap_invocations_unbatched.1)
+    )?;
     writeln!(buf, "\n# System Kernel Invocations Summary\n")?;
     writeln!(
         buf,
@@ -2943,8 +3608,22 @@ fn write_report<W: std::io::Write>(
     writeln!(
         buf,
         "     size of invocations: {:>10}",
-        comma_sep_usize(built_system.invocation_data.len())
+        comma_sep_u64(built_system.invocation_data_size)
+    )?;
+    writeln!(
+        buf,
+        "     # unbatched        : {:>10}",
+        comma_sep_usize(built_system.system_invocations_unbatched.0)
+    )?;
+    writeln!(
+        buf,
+        "     size unbatched     : {:>10}",
+        comma_sep_u64(built_system.system_invocations_unbatched.1)
     )?;
+    if let Some(analysis) = schedulability {
+        writeln!(buf, "\n# Schedulability Analysis\n")?;
+        write!(buf, "{}", analysis.report())?;
+    }
     writeln!(buf, "\n# Allocated Kernel Objects Detail\n")?;
     for ko in &built_system.kernel_objects {
         // FIXME: would be good to print both the number for the object type and the string
@@ -2972,29 +3651,436 @@ fn write_report<W: std::io::Write>(
     Ok(())
 }
 
+#[derive(Copy, Clone)]
+enum CapAuditFormat {
+    Text,
+    Json,
+}
+
+impl CapAuditFormat {
+    fn parse(s: &str) -> Option<CapAuditFormat> {
+        match s {
+            "text" => Some(CapAuditFormat::Text),
+            "json" => Some(CapAuditFormat::Json),
+            _ => None,
+        }
+    }
+
+    fn valid_formats() -> Vec<&'static str> {
+        vec!["text", "json"]
+    }
+}
+
+/// A capability in the CSpace of a protection domain
+struct CapAuditEntry {
+    slot: u64,
+    /// Slot relative to the constant that the slot is allocated from
+    slot_name: String,
+    object: String,
+    rights: u64,
+    badge: u64,
+    /// Short, stable description of why the cap exists
+    kind: &'static str,
+    reason: String,
+}
+
+fn rights_letters(rights: u64) -> String {
+    [
+        (Rights::Read, 'R'),
+        (Rights::Write, 'W'),
+        (Rights::Grant, 'G'),
+        (Rights::GrantReply, 'P'),
+    ]
+    .into_iter()
+    .filter(|(right, _)| rights & *right as u64 != 0)
+    .map(|(_, letter)| letter)
+    .collect()
+}
+
+/// Work out why the cap in 'slot' of the PD's CSpace exists, along with
+/// the name of the slot relative to the base it is allocated from.
+fn cap_purpose(
+    system: &SystemDescription,
+    pd_idx: usize,
+    slot: u64,
+) -> (String, &'static str, String) {
+    let pd = &system.protection_domains[pd_idx];
+    let fixed = [
+        (
+            INPUT_CAP_IDX,
+            "INPUT_CAP_IDX",
+            "input",
+            "endpoint or notification the PD receives on",
+        ),
+        (
+            FAULT_EP_CAP_IDX,
+            "FAULT_EP_CAP_IDX",
+            "fault",
+            "fault endpoint",
+        ),
+        (
+            VSPACE_CAP_IDX,
+            "VSPACE_CAP_IDX",
+            "vspace",
+            "the PD's own VSpace",
+        ),
+        (
+            REPLY_CAP_IDX,
+            "REPLY_CAP_IDX",
+            "reply",
+            "reply object for protected procedure calls",
+        ),
+        (
+            MONITOR_EP_CAP_IDX,
+            "MONITOR_EP_CAP_IDX",
+            "monitor",
+            "endpoint to the monitor for passive PDs",
+        ),
+        (
+            TCB_CAP_IDX,
+            "TCB_CAP_IDX",
+            "tcb",
+            "the PD's own TCB, for the benchmark configuration",
+        ),
+        (SMC_CAP_IDX, "SMC_CAP_IDX", "smc", "SMC calls"),
+    ];
+    if let Some((_, slot_name, kind, reason)) = fixed.iter().find(|(idx, ..)| *idx == slot) {
+        return (slot_name.to_string(), kind, reason.to_string());
+    }
+
+    let bases = [
+        (BASE_OUTPUT_NOTIFICATION_CAP, "BASE_OUTPUT_NOTIFICATION_CAP"),
+        (BASE_OUTPUT_ENDPOINT_CAP, "BASE_OUTPUT_ENDPOINT_CAP"),
+        (BASE_IRQ_CAP, "BASE_IRQ_CAP"),
+        (BASE_PD_TCB_CAP, "BASE_PD_TCB_CAP"),
+        (BASE_VM_TCB_CAP, "BASE_VM_TCB_CAP"),
+        (BASE_VCPU_CAP, "BASE_VCPU_CAP"),
+    ];
+    let Some((base, base_name)) = bases.iter().rev().find(|(base, _)| *base <= slot) else {
+        return (slot.to_string(), "unknown", "unknown".to_string());
+    };
+    let id = slot - base;
+    let slot_name = format!("{} + {}", base_name, id);
+
+    // The PD at the other end of the channel 'id'
+    let channel_peer = || {
+        system.channels.iter().find_map(|cc| {
+            if cc.end_a.pd == pd_idx && cc.end_a.id == id {
+                Some(&system.protection_domains[cc.end_b.pd].name)
+            } else if cc.end_b.pd == pd_idx && cc.end_b.id == id {
+                Some(&system.protection_domains[cc.end_a.pd].name)
+            } else {
+                None
+            }
+        })
+    };
+    let vm_name = || pd.virtual_machine.as_ref().map_or("", |vm| &vm.name);
+
+    // Caps that cannot be attributed are reported as unknown rather than
+    // failing the audit.
+    let purpose = match *base {
+        BASE_OUTPUT_NOTIFICATION_CAP => channel_peer()
+            .map(|peer| ("channel", format!("notify PD '{}' on channel {}", peer, id))),
+        BASE_OUTPUT_ENDPOINT_CAP => {
+            channel_peer().map(|peer| ("ppc", format!("PPC into PD '{}' on channel {}", peer, id)))
+        }
+        BASE_IRQ_CAP => pd.irqs.iter().find(|irq| irq.id == id).map(|irq| {
+            (
+                "irq",
+                format!("acknowledge IRQ {} on channel {}", irq.irq, id),
+            )
+        }),
+        BASE_PD_TCB_CAP => system
+            .protection_domains
+            .iter()
+            .find(|child| child.parent == Some(pd_idx) && child.id == Some(id))
+            .map(|child| ("child_tcb", format!("TCB of child PD '{}'", child.name))),
+        BASE_VM_TCB_CAP => Some(("vcpu", format!("TCB of vCPU {} of VM '{}'", id, vm_name()))),
+        BASE_VCPU_CAP => Some(("vcpu", format!("vCPU {} of VM '{}'", id, vm_name()))),
+        _ => unreachable!(),
+    };
+    let (kind, reason) = purpose.unwrap_or(("unknown", "unknown".to_string()));
+
+    (slot_name, kind, reason)
+}
+
+/// List the caps in the CSpace of each protection domain, as minted or
+/// copied by the system invocations.
+fn cap_audit(
+    config: &Config,
+    system: &SystemDescription,
+    built_system: &BuiltSystem,
+) -> Vec<Vec<CapAuditEntry>> {
+    let mut audit: Vec<Vec<CapAuditEntry>> = system
+        .protection_domains
+        .iter()
+        .map(|_| Vec::new())
+        .collect();
+
+    for invocation in &built_system.system_invocations {
+        for args in invocation.expand(config) {
+            let (cnode, slot, src_obj, rights, badge) = match args {
+                InvocationArgs::CnodeMint {
+                    cnode,
+                    dest_index,
+                    src_obj,
+                    rights,
+                    badge,
+                    ..
+                } => (cnode, dest_index, src_obj, rights, badge),
+                InvocationArgs::CnodeCopy {
+                    cnode,
+                    dest_index,
+                    src_obj,
+                    rights,
+                    ..
+                } => (cnode, dest_index, src_obj, rights, 0),
+                _ => continue,
+            };
+            let Some(pd_idx) = built_system.cnode_caps.iter().position(|cap| *cap == cnode) else {
+                continue;
+            };
+
+            let (slot_name, kind, reason) = cap_purpose(system, pd_idx, slot);
+            audit[pd_idx].push(CapAuditEntry {
+                slot,
+                slot_name,
+                object: built_system.cap_lookup[&src_obj].clone(),
+                rights,
+                badge,
+                kind,
+                reason,
+            });
+        }
+    }
+
+    for entries in &mut audit {
+        entries.sort_by_key(|entry| entry.slot);
+    }
+
+    audit
+}
+
+fn write_cap_audit<W: std::io::Write>(
+    buf: &mut BufWriter<W>,
+    format: CapAuditFormat,
+    system: &SystemDescription,
+    audit: &[Vec<CapAuditEntry>],
+) -> std::io::Result<()> {
+    match format {
+        CapAuditFormat::Text => {
+            for (pd, entries) in zip(&system.protection_domains, audit) {
+                writeln!(buf, "# Protection domain '{}'\n", pd.name)?;
+                for entry in entries {
+                    writeln!(
+                        buf,
+                        "    slot {:>3} ({}): {}",
+                        entry.slot, entry.slot_name, entry.object
+                    )?;
+                    writeln!(
+                        buf,
+                        "        rights: {}  badge: 0x{:x}",
+                        rights_letters(entry.rights),
+                        entry.badge
+                    )?;
+                    writeln!(buf, "        {}: {}", entry.kind, entry.reason)?;
+                }
+                writeln!(buf)?;
+            }
+        }
+        CapAuditFormat::Json => {
+            let pds: Vec<serde_json::Value> = zip(&system.protection_domains, audit)
+                .map(|(pd, entries)| {
+                    let caps: Vec<serde_json::Value> = entries
+                        .iter()
+                        .map(|entry| {
+                            serde_json::json!({
+                                "slot": entry.slot,
+                                "slot_name": entry.slot_name,
+                                "object": entry.object,
+                                "rights": rights_letters(entry.rights),
+                                "badge": entry.badge,
+                                "kind": entry.kind,
+                                "reason": entry.reason,
+                            })
+                        })
+                        .collect();
+                    serde_json::json!({ "name": pd.name, "caps": caps })
+                })
+                .collect();
+            let json = serde_json::json!({ "protection_domains": pds });
+            writeln!(buf, "{}", serde_json::to_string_pretty(&json).unwrap())?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the bootstrap and system invocations embedded in a loader image.
+/// The monitor's symbols are used to find the invocations in the image.
+fn dump_invocations(
+    config: &Config,
+    monitor_config: &MonitorConfig,
+    monitor_elf: &ElfFile,
+    image_path: &Path,
+) -> Result<(), String> {
+    let image_data = fs::read(image_path).map_err(|err| {
+        format!(
+            "Error: could not read image '{}': {}",
+            image_path.display(),
+            err
+        )
+    })?;
+    let image = LoaderImage::parse(&image_data).map_err(|err| {
+        format!(
+            "Error: could not parse image '{}': {}",
+            image_path.display(),
+            err
+        )
+    })?;
+
+    // The monitor (with the symbols patched by the tool) is loaded at the
+    // start of the initial task region.
+    let monitor_data = match image.region(image.ui_p_reg_start) {
+        Some(data) => data,
+        None => return Err("Error: could not find the monitor in the image".to_string()),
+    };
+    let monitor_vaddr = image.ui_p_reg_start.wrapping_sub(image.pv_offset);
+    let monitor_symbol = |name: &str| -> Result<&[u8], String> {
+        let (vaddr, size) = monitor_elf.find_symbol(name)?;
+        let offset = vaddr.wrapping_sub(monitor_vaddr);
+        monitor_data
+            .get(offset as usize..offset.wrapping_add(size) as usize)
+            .ok_or(format!("symbol '{}' is not in the monitor's data", name))
+    };
+    let monitor_count = |name: &str| -> Result<usize, String> {
+        match monitor_symbol(name)?.get(..8) {
+            Some(bytes) => Ok(u64::from_le_bytes(bytes.try_into().unwrap()) as usize),
+            None => Err(format!("symbol '{}' is too small", name)),
+        }
+    };
+
+    let bootstrap_invocations =
+        monitor_count(monitor_config.bootstrap_invocation_count_symbol_name)
+            .and_then(|count| {
+                let data = monitor_symbol(monitor_config.bootstrap_invocation_data_symbol_name)?;
+                Invocation::decode(config, data, count)
+            })
+            .map_err(|err| format!("Error: could not decode bootstrap invocations: {}", err))?;
+
+    // The system invocations are loaded at the start of the reserved region
+    let system_invocations = monitor_count(monitor_config.system_invocation_count_symbol_name)
+        .and_then(|count| match image.region(image.extra_device_addr_p) {
+            Some(data) => Invocation::decode(config, data, count),
+            None => Err("could not find the invocation table in the image".to_string()),
+        })
+        .map_err(|err| format!("Error: could not decode system invocations: {}", err))?;
+
+    let cap_lookup = HashMap::new();
+    let mut buf = BufWriter::new(std::io::stdout());
+    let write_err = |err: std::io::Error| format!("Error: could not write invocations: {}", err);
+    writeln!(buf, "# Bootstrap Kernel Invocations Detail\n").map_err(write_err)?;
+    for (i, invocation) in bootstrap_invocations.iter().enumerate() {
+        write!(buf, "    0x{:04x} ", i).map_err(write_err)?;
+        invocation.report_fmt(&mut buf, config, &cap_lookup);
+    }
+    writeln!(buf, "\n# System Kernel Invocations Detail\n").map_err(write_err)?;
+    for (i, invocation) in system_invocations.iter().enumerate() {
+        write!(buf, "    0x{:04x} ", i).map_err(write_err)?;
+        invocation.report_fmt(&mut buf, config, &cap_lookup);
+    }
+    buf.flush().map_err(write_err)?;
+
+    Ok(())
+}
+
 fn print_usage(available_boards: &[String]) {
-    println!("usage: microkit [-h] [-o OUTPUT] [-r REPORT] --board {{{}}} --config CONFIG [--search-path [SEARCH_PATH ...]] system", available_boards.join(","))
+    println!("usage: microkit [-h] [-o OUTPUT] [-r REPORT] [--capdl CAPDL] [--no-verify] [--cap-audit CAP_AUDIT] [--cap-audit-format {{{}}}] [--sched-analysis] [--harden] [--allow LINT] [--warn LINT] [--deny LINT] [--max-utilisation PERCENT] [--max-response PERCENT] [--image-type {{{}}}] [--allocation-policy {{{}}}] --board {{{}}} --config CONFIG [--search-path [SEARCH_PATH ...]] system", CapAuditFormat::valid_formats().join(","), ImageType::valid_types().join(","), AllocationPolicy::valid_policies().join(","), available_boards.join(","));
+    println!(
+        "       microkit dump-invocations [-h] --board {{{}}} --config CONFIG image",
+        available_boards.join(",")
+    );
+    println!(
+        "       microkit graph [-h] [--format {{{}}}] --board {{{}}} --config CONFIG system",
+        GraphFormat::valid_formats().join(","),
+        available_boards.join(",")
+    );
+    println!(
+        "       microkit lint [-h] [--allow LINT] [--warn LINT] [--deny LINT] --board {{{}}} --config CONFIG system",
+        available_boards.join(",")
+    )
 }
 
 fn print_help(available_boards: &[String]) {
     print_usage(available_boards);
     println!("\npositional arguments:");
     println!("  system");
+    println!("  image, loader image to dump the invocations of (dump-invocations only)");
     println!("\noptions:");
     println!("  -h, --help, show this help message and exit");
     println!("  -o, --output OUTPUT");
     println!("  -r, --report REPORT");
+    println!("  --capdl CAPDL, write a capDL specification of the system");
+    println!("  --no-verify, do not check the generated invocations against a model of the kernel");
+    println!("  --sched-analysis, add a schedulability analysis to the report");
+    println!(
+        "  --harden, reject writable and executable, shared executable and cached device mappings"
+    );
+    println!("  --allow LINT, silence a lint, given by its code, name or 'all'");
+    println!("  --warn LINT, report a lint as a warning (default)");
+    println!("  --deny LINT, report a lint as an error");
+    println!("  --max-utilisation PERCENT, fail if the total utilisation is higher");
+    println!("  --max-response PERCENT, fail if a worst-case response time is longer than this percentage of the period");
+    println!("  --cap-audit CAP_AUDIT, write the capabilities held by each protection domain");
+    println!(
+        "  --cap-audit-format {{{}}}",
+        CapAuditFormat::valid_formats().join(",")
+    );
+    println!("  --image-type {{{}}}", ImageType::valid_types().join(","));
+    println!(
+        "  --allocation-policy {{{}}}",
+        AllocationPolicy::valid_policies().join(",")
+    );
     println!("  --board {{{}}}", available_boards.join(","));
     println!("  --config CONFIG");
     println!("  --search-path [SEARCH_PATH ...]");
+    println!(
+        "  --format {{{}}}, format of the graph (graph only)",
+        GraphFormat::valid_formats().join(",")
+    );
+}
+
+enum Command<'a> {
+    /// Build a loader image from a system description
+    Build { system: &'a str },
+    /// Print the invocations embedded in a loader image built by the tool
+    DumpInvocations { image: &'a str },
+    /// Print the topology of a system description as a graph
+    Graph {
+        system: &'a str,
+        format: GraphFormat,
+    },
+    /// Check a system description against the lints, without building it
+    Lint { system: &'a str },
 }
 
 struct Args<'a> {
-    system: &'a str,
+    command: Command<'a>,
     board: &'a str,
     config: &'a str,
     report: &'a str,
+    capdl: Option<&'a str>,
+    verify: bool,
+    cap_audit: Option<&'a str>,
+    cap_audit_format: CapAuditFormat,
+    sched_analysis: bool,
+    harden: bool,
+    lint_levels: LintLevels,
+    max_utilisation: Option<f64>,
+    max_response: Option<f64>,
     output: &'a str,
+    image_type: ImageType,
+    allocation_policy: AllocationPolicy,
     search_paths: Vec<&'a String>,
 }
 
@@ -3003,9 +4089,21 @@ impl<'a> Args<'a> {
         // Default arguments
         let mut output = "loader.img";
         let mut report = "report.txt";
+        let mut capdl = None;
+        let mut verify = true;
+        let mut cap_audit = None;
+        let mut cap_audit_format = CapAuditFormat::Text;
+        let mut graph_format = GraphFormat::Dot;
+        let mut sched_analysis = false;
+        let mut harden = false;
+        let mut lint_levels = LintLevels::default();
+        let mut max_utilisation = None;
+        let mut max_response = None;
+        let mut image_type = ImageType::Binary;
+        let mut allocation_policy = AllocationPolicy::FirstFit;
         let mut search_paths = Vec::new();
         // Arguments expected to be provided by the user
-        let mut system = None;
+        let mut positional = None;
         let mut board = None;
         let mut config = None;
 
@@ -3014,7 +4112,12 @@ impl<'a> Args<'a> {
             std::process::exit(1);
         }
 
-        let mut i = 1;
+        let subcommand = match args[1].as_str() {
+            subcommand @ ("dump-invocations" | "graph" | "lint") => Some(subcommand),
+            _ => None,
+        };
+
+        let mut i = if subcommand.is_some() { 2 } else { 1 };
         let mut unknown = vec![];
         let mut in_search_path = false;
         while i < args.len() {
@@ -3043,6 +4146,110 @@ impl<'a> Args<'a> {
                         std::process::exit(1);
                     }
                 }
+                "--capdl" => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        capdl = Some(args[i + 1].as_str());
+                        i += 1;
+                    } else {
+                        eprintln!("microkit: error: argument --capdl: expected one argument");
+                        std::process::exit(1);
+                    }
+                }
+                "--cap-audit" => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        cap_audit = Some(args[i + 1].as_str());
+                        i += 1;
+                    } else {
+                        eprintln!("microkit: error: argument --cap-audit: expected one argument");
+                        std::process::exit(1);
+                    }
+                }
+                "--cap-audit-format" => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        cap_audit_format = match CapAuditFormat::parse(&args[i + 1]) {
+                            Some(cap_audit_format) => cap_audit_format,
+                            None => {
+                                eprintln!(
+                                    "microkit: error: argument --cap-audit-format: invalid choice: '{}' (choose from: {})",
+                                    args[i + 1],
+                                    CapAuditFormat::valid_formats().join(", ")
+                                );
+                                std::process::exit(1);
+                            }
+                        };
+                        i += 1;
+                    } else {
+                        eprintln!(
+                            "microkit: error: argument --cap-audit-format: expected one argument"
+                        );
+                        std::process::exit(1);
+                    }
+                }
+                "--format" => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        graph_format = match GraphFormat::parse(&args[i + 1]) {
+                            Some(graph_format) => graph_format,
+                            None => {
+                                eprintln!(
+                                    "microkit: error: argument --format: invalid choice: '{}' (choose from: {})",
+                                    args[i + 1],
+                                    GraphFormat::valid_formats().join(", ")
+                                );
+                                std::process::exit(1);
+                            }
+                        };
+                        i += 1;
+                    } else {
+                        eprintln!("microkit: error: argument --format: expected one argument");
+                        std::process::exit(1);
+                    }
+                }
+                "--image-type" => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        image_type = match ImageType::parse(&args[i + 1]) {
+                            Some(image_type) => image_type,
+                            None => {
+                                eprintln!(
+                                    "microkit: error: argument --image-type: invalid choice: '{}' (choose from: {})",
+                                    args[i + 1],
+                                    ImageType::valid_types().join(", ")
+                                );
+                                std::process::exit(1);
+                            }
+                        };
+                        i += 1;
+                    } else {
+                        eprintln!("microkit: error: argument --image-type: expected one argument");
+                        std::process::exit(1);
+                    }
+                }
+                "--allocation-policy" => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        allocation_policy = match AllocationPolicy::parse(&args[i + 1]) {
+                            Some(allocation_policy) => allocation_policy,
+                            None => {
+                                eprintln!(
+                                    "microkit: error: argument --allocation-policy: invalid choice: '{}' (choose from: {})",
+                                    args[i + 1],
+                                    AllocationPolicy::valid_policies().join(", ")
+                                );
+                                std::process::exit(1);
+                            }
+                        };
+                        i += 1;
+                    } else {
+                        eprintln!(
+                            "microkit: error: argument --allocation-policy: expected one argument"
+                        );
+                        std::process::exit(1);
+                    }
+                }
                 "--board" => {
                     in_search_path = false;
                     if i < args.len() - 1 {
@@ -3066,11 +4273,74 @@ impl<'a> Args<'a> {
                 "--search-path" => {
                     in_search_path = true;
                 }
+                "--sched-analysis" => {
+                    in_search_path = false;
+                    sched_analysis = true;
+                }
+                "--harden" => {
+                    in_search_path = false;
+                    harden = true;
+                }
+                "--no-verify" => {
+                    in_search_path = false;
+                    verify = false;
+                }
+                arg @ ("--allow" | "--warn" | "--deny") => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        let level = match arg {
+                            "--allow" => LintLevel::Allow,
+                            "--warn" => LintLevel::Warn,
+                            _ => LintLevel::Deny,
+                        };
+                        if let Err(err) = lint_levels.set(&args[i + 1], level) {
+                            eprintln!("microkit: error: argument {}: {}", arg, err);
+                            std::process::exit(1);
+                        }
+                        i += 1;
+                    } else {
+                        eprintln!("microkit: error: argument {}: expected one argument", arg);
+                        std::process::exit(1);
+                    }
+                }
+                arg @ ("--max-utilisation" | "--max-response") => {
+                    in_search_path = false;
+                    if i < args.len() - 1 {
+                        let percent = match args[i + 1].parse::<f64>() {
+                            Ok(percent) if percent > 0.0 => percent,
+                            _ => {
+                                eprintln!(
+                                    "microkit: error: argument {}: invalid percentage: '{}'",
+                                    arg,
+                                    args[i + 1]
+                                );
+                                std::process::exit(1);
+                            }
+                        };
+                        if arg == "--max-utilisation" {
+                            max_utilisation = Some(percent);
+                        } else if percent > 100.0 {
+                            eprintln!(
+                                "microkit: error: argument {}: response time must be at most 100% of the period",
+                                arg
+                            );
+                            std::process::exit(1);
+                        } else {
+                            max_response = Some(percent);
+                        }
+                        // Thresholds are checked against the analysis
+                        sched_analysis = true;
+                        i += 1;
+                    } else {
+                        eprintln!("microkit: error: argument {}: expected one argument", arg);
+                        std::process::exit(1);
+                    }
+                }
                 _ => {
                     if in_search_path {
                         search_paths.push(&args[i]);
-                    } else if system.is_none() {
-                        system = Some(&args[i]);
+                    } else if positional.is_none() {
+                        positional = Some(&args[i]);
                     } else {
                         // This call to clone is okay since having unknown
                         // arguments is rare.
@@ -3098,8 +4368,12 @@ impl<'a> Args<'a> {
         if config.is_none() {
             missing_args.push("--config");
         }
-        if system.is_none() {
-            missing_args.push("system");
+        if positional.is_none() {
+            missing_args.push(if subcommand == Some("dump-invocations") {
+                "image"
+            } else {
+                "system"
+            });
         }
 
         if !missing_args.is_empty() {
@@ -3111,17 +4385,94 @@ impl<'a> Args<'a> {
             std::process::exit(1);
         }
 
+        let command = match subcommand {
+            Some("dump-invocations") => Command::DumpInvocations {
+                image: positional.unwrap(),
+            },
+            Some("graph") => Command::Graph {
+                system: positional.unwrap(),
+                format: graph_format,
+            },
+            Some("lint") => Command::Lint {
+                system: positional.unwrap(),
+            },
+            _ => Command::Build {
+                system: positional.unwrap(),
+            },
+        };
+
         Args {
-            system: system.unwrap(),
+            command,
             board: board.unwrap(),
             config: config.unwrap(),
             report,
+            capdl,
+            verify,
+            cap_audit,
+            cap_audit_format,
+            sched_analysis,
+            harden,
+            lint_levels,
+            max_utilisation,
+            max_response,
             output,
+            image_type,
+            allocation_policy,
             search_paths,
         }
     }
 }
 
+/// Read the reserved memory of a board, which is a list of objects with the
+/// 'base' and 'size' of each reserved region as decimal strings.
+fn read_board_reserved_memory(path: &Path) -> Result<Vec<SysReservedMemory>, String> {
+    let json = fs::read_to_string(path).map_err(|err| {
+        format!(
+            "Error: could not read board reserved memory '{}': {}",
+            path.display(),
+            err
+        )
+    })?;
+    let json: serde_json::Value = serde_json::from_str(&json).map_err(|err| {
+        format!(
+            "Error: could not parse board reserved memory '{}': {}",
+            path.display(),
+            err
+        )
+    })?;
+    let Some(entries) = json.as_array() else {
+        return Err(format!(
+            "Error: board reserved memory '{}' is not a list",
+            path.display()
+        ));
+    };
+
+    let mut reserved_memory = Vec::with_capacity(entries.len());
+    for (i, entry) in entries.iter().enumerate() {
+        let field = |name: &str| -> Result<u64, String> {
+            entry
+                .get(name)
+                .and_then(|value| value.as_str())
+                .and_then(|value| value.parse::<u64>().ok())
+                .ok_or_else(|| {
+                    format!(
+                        "Error: entry {} of board reserved memory '{}' does not have a '{}' that is a decimal string",
+                        i,
+                        path.display(),
+                        name
+                    )
+                })
+        };
+        reserved_memory.push(SysReservedMemory {
+            base: field("base")?,
+            size: field("size")?,
+            text_pos: None,
+        });
+    }
+
+    Ok(reserved_memory)
+}
+
 fn main() -> Result<(), String> {
     let exe_path = std::env::current_exe().unwrap();
     let sdk_env = std::env::var("MICROKIT_SDK");
@@ -3220,6 +4571,14 @@ fn main() -> Result<(), String> {
         .join(args.config)
         .join("invocations_all.json");
 
+    // Optional, memory that firmware on the board owns but that the kernel
+    // considers available.
+    let board_reserved_memory_path = sdk_dir
+        .join("board")
+        .join(args.board)
+        .join(args.config)
+        .join("reserved_memory.json");
+
     if !elf_path.exists() {
         eprintln!(
             "Error: board ELF directory '{}' does not exist",
@@ -3263,17 +4622,6 @@ fn main() -> Result<(), String> {
         std::process::exit(1);
     }
 
-    let system_path = Path::new(args.system);
-    if !system_path.exists() {
-        eprintln!(
-            "Error: system description file '{}' does not exist",
-            system_path.display()
-        );
-        std::process::exit(1);
-    }
-
-    let xml: String = fs::read_to_string(args.system).unwrap();
-
     let kernel_config_json: serde_json::Value =
         serde_json::from_str(&fs::read_to_string(kernel_config_path).unwrap()).unwrap();
 
@@ -3349,7 +4697,51 @@ fn main() -> Result<(), String> {
         "Microkit tool has various assumptions about the word size being 64-bits."
     );
 
-    let system = match parse(args.system, &xml, &kernel_config) {
+    if args.image_type == ImageType::LinuxArm64 && kernel_config.arch != Arch::Aarch64 {
+        eprintln!(
+            "microkit: error: argument --image-type: 'linux-arm64' is only supported on AArch64"
+        );
+        std::process::exit(1);
+    }
+
+    let monitor_config = MonitorConfig {
+        untyped_info_symbol_name: "untyped_info",
+        bootstrap_invocation_count_symbol_name: "bootstrap_invocation_count",
+        bootstrap_invocation_data_symbol_name: "bootstrap_invocation_data",
+        system_invocation_count_symbol_name: "system_invocation_count",
+    };
+
+    let (system_file, graph_format, lint_only) = match args.command {
+        Command::Build { system } => (system, None, false),
+        Command::Graph { system, format } => (system, Some(format), false),
+        Command::Lint { system } => (system, None, true),
+        Command::DumpInvocations { image } => {
+            let monitor_elf = ElfFile::from_path(&monitor_elf_path)?;
+            if let Err(err) = dump_invocations(
+                &kernel_config,
+                &monitor_config,
+                &monitor_elf,
+                Path::new(image),
+            ) {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+    };
+
+    let system_path = Path::new(system_file);
+    if !system_path.exists() {
+        eprintln!(
+            "Error: system description file '{}' does not exist",
+            system_path.display()
+        );
+        std::process::exit(1);
+    }
+
+    let xml: String = fs::read_to_string(system_file).unwrap();
+
+    let mut system = match parse(system_file, &xml, &kernel_config) {
         Ok(system) => system,
         Err(err) => {
             eprintln!("{err}");
@@ -3357,11 +4749,68 @@ fn main() -> Result<(), String> {
         }
     };
 
-    let monitor_config = MonitorConfig {
-        untyped_info_symbol_name: "untyped_info",
-        bootstrap_invocation_count_symbol_name: "bootstrap_invocation_count",
-        bootstrap_invocation_data_symbol_name: "bootstrap_invocation_data",
-        system_invocation_count_symbol_name: "system_invocation_count",
+    if board_reserved_memory_path.exists() {
+        let board_reserved_memory = read_board_reserved_memory(&board_reserved_memory_path)?;
+        for r in &system.reserved_memory {
+            if let Some(other) = board_reserved_memory
+                .iter()
+                .find(|other| other.base < r.end() && r.base < other.end())
+            {
+                let pos = r.text_pos.unwrap();
+                return Err(format!(
+                    "Error: reserved memory [0x{:x}..0x{:x}) overlaps with reserved memory [0x{:x}..0x{:x}) of the board @ {}:{}:{}",
+                    r.base,
+                    r.end(),
+                    other.base,
+                    other.end(),
+                    system_file,
+                    pos.row,
+                    pos.col
+                ));
+            }
+        }
+        system.reserved_memory.extend(board_reserved_memory);
+    }
+
+    if let Some(format) = graph_format {
+        print!("{}", graph::render(&system, format));
+        return Ok(());
+    }
+
+    if let Err(err) = lint::report(system_file, &lint::check(&system), &args.lint_levels) {
+        eprintln!("{err}");
+        std::process::exit(1);
+    }
+    if lint_only {
+        return Ok(());
+    }
+
+    let schedulability = if args.sched_analysis {
+        let analysis = sched::analyse(&system);
+        let utilisation = analysis.total_utilisation();
+        if utilisation > 100.0 {
+            println!("WARNING: total utilisation of {utilisation:.2}% is over 100%");
+        }
+        if let Some(max_utilisation) = args.max_utilisation {
+            if utilisation > max_utilisation {
+                eprintln!("Error: total utilisation of {utilisation:.2}% is over the maximum of {max_utilisation}%");
+                std::process::exit(1);
+            }
+        }
+        if let (Some(max_response), Some((task, response))) =
+            (args.max_response, analysis.worst_response())
+        {
+            if response > max_response {
+                eprintln!(
+                    "Error: worst-case response time of '{}' is over {}% of its period",
+                    task.name, max_response
+                );
+                std::process::exit(1);
+            }
+        }
+        Some(analysis)
+    } else {
+        None
     };
 
     let kernel_elf = ElfFile::from_path(&kernel_elf_path)?;
@@ -3398,12 +4847,63 @@ fn main() -> Result<(), String> {
         }
     }
 
+    // Get the initial contents of each memory region that has them, the rest
+    // of the region is zeroed by the loader.
+    let mut mr_contents: HashMap<&str, Vec<u8>> = HashMap::new();
+    for mr in &system.memory_regions {
+        let Some(source) = &mr.source else {
+            continue;
+        };
+        let data = match get_full_path(source, &search_paths) {
+            Some(path) => fs::read(&path).map_err(|err| {
+                format!(
+                    "could not read source '{}' of memory region '{}': {}",
+                    path.display(),
+                    mr.name,
+                    err
+                )
+            })?,
+            None => {
+                return Err(format!(
+                    "unable to find source of memory region '{}': '{}'",
+                    mr.name,
+                    source.display()
+                ))
+            }
+        };
+        if mr.source_offset + data.len() as u64 > mr.size {
+            return Err(format!(
+                "source '{}' (0x{:x} bytes) does not fit at offset 0x{:x} of memory region '{}' (0x{:x} bytes)",
+                source.display(),
+                data.len(),
+                mr.source_offset,
+                mr.name,
+                mr.size
+            ));
+        }
+
+        mr_contents.insert(&mr.name, data);
+    }
+
+    if args.harden {
+        let mut violations = harden::check_maps(system_file, &system);
+        violations.extend(harden::check_elfs(&system, &pd_elf_files));
+        if !violations.is_empty() {
+            for violation in &violations {
+                eprintln!("Error: hardening: {violation}");
+            }
+            std::process::exit(1);
+        }
+    }
+
+    // Find the system CNode and invocation table sizes needed. The sizes affect
+    // the layout of the system (and therefore the caps and invocations needed),
+    // so we grow them until everything fits and keep the build that does.
     let mut invocation_table_size = kernel_config.minimum_page_size;
     let mut system_cnode_size = 2;
 
-    let mut built_system;
-    loop {
-        built_system = build_system(
+    let built_system = loop {
+        let built_system = match build_system(
             &kernel_config,
             &pd_elf_files,
             &kernel_elf,
@@ -3411,14 +4911,20 @@ fn main() -> Result<(), String> {
             &system,
             invocation_table_size,
             system_cnode_size,
-        )?;
-        println!("BUILT: system_cnode_size={} built_system.number_of_system_caps={} invocation_table_size={} built_system.invocation_data_size={}",
-                 system_cnode_size, built_system.number_of_system_caps, invocation_table_size, built_system.invocation_data_size);
+            args.allocation_policy,
+            args.harden,
+        ) {
+            Ok(built_system) => built_system,
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        };
 
         if built_system.number_of_system_caps <= system_cnode_size
             && built_system.invocation_data_size <= invocation_table_size
         {
-            break;
+            break built_system;
         }
 
         // Recalculate the sizes for the next iteration
@@ -3435,6 +4941,43 @@ fn main() -> Result<(), String> {
 
         invocation_table_size = max(invocation_table_size, new_invocation_table_size);
         system_cnode_size = max(system_cnode_size, new_system_cnode_size);
+    };
+
+    let mut system_invocation_data: Vec<u8> = Vec::new();
+    for invocation in &built_system.system_invocations {
+        invocation.add_raw_invocation(&kernel_config, &mut system_invocation_data);
+    }
+    assert!(system_invocation_data.len() as u64 == built_system.invocation_data_size);
+
+    // Before producing the image, check that the kernel would accept every
+    // invocation the monitor is going to make. The capDL spec is produced from
+    // the state of the simulated kernel, so it always needs the simulation.
+    if args.verify || args.capdl.is_some() {
+        let mut simulator = Simulator::new(
+            &kernel_config,
+            &built_system.kernel_boot_info,
+            built_system.initial_task_virt_region,
+        );
+        let simulated = simulator
+            .run("bootstrap", &built_system.bootstrap_invocations)
+            .and_then(|_| simulator.run("system", &built_system.system_invocations));
+        if let Err(err) = simulated {
+            eprintln!("Error: the kernel would reject the generated invocations: {err}");
+            std::process::exit(1);
+        }
+
+        if let Some(capdl_path) = args.capdl {
+            let spec = capdl::spec(
+                &kernel_config,
+                &simulator,
+                &built_system.kernel_objects,
+                &built_system.cap_lookup,
+            );
+            if let Err(err) = fs::write(capdl_path, spec) {
+                eprintln!("Error: could not write capDL spec '{capdl_path}': {err}");
+                std::process::exit(1);
+            }
+        }
     }
 
     // At this point we just need to patch the files (in memory) and write out the final image.
@@ -3574,6 +5117,7 @@ fn main() -> Result<(), String> {
 
     // Write out all the symbols for each PD
     pd_write_symbols(
+        &kernel_config,
         &system.protection_domains,
         &mut pd_elf_files,
         &built_system.pd_setvar_values,
@@ -3594,8 +5138,10 @@ fn main() -> Result<(), String> {
     match write_report(
         &mut report_buf,
         &kernel_config,
+        &system,
         &built_system,
         &bootstrap_invocation_data,
+        schedulability.as_ref(),
     ) {
         Ok(()) => report_buf.flush().unwrap(),
         Err(err) => {
@@ -3607,15 +5153,52 @@ fn main() -> Result<(), String> {
     }
     report_buf.flush().unwrap();
 
-    let mut loader_regions: Vec<(u64, &[u8])> = vec![(
-        built_system.reserved_region.base,
-        &built_system.invocation_data,
-    )];
+    if let Some(cap_audit_path) = args.cap_audit {
+        let audit = cap_audit(&kernel_config, &system, &built_system);
+        let file = match std::fs::File::create(cap_audit_path) {
+            Ok(file) => file,
+            Err(e) => {
+                return Err(format!(
+                    "Could not create capability audit file '{}': {}",
+                    cap_audit_path, e
+                ))
+            }
+        };
+        let mut buf = BufWriter::new(file);
+        if let Err(err) = write_cap_audit(&mut buf, args.cap_audit_format, &system, &audit)
+            .and_then(|_| buf.flush())
+        {
+            return Err(format!(
+                "Could not write out capability audit file '{}': {}",
+                cap_audit_path, err
+            ));
+        }
+    }
+
+    let mut loader_regions: Vec<(u64, &[u8])> =
+        vec![(built_system.reserved_region.base, &system_invocation_data)];
     for (i, regions) in built_system.pd_elf_regions.iter().enumerate() {
         for r in regions {
             loader_regions.push((r.addr, r.data(&pd_elf_files[i])));
         }
     }
+    let mut zero_regions = Vec::new();
+    for (mr_name, phys_addr) in &built_system.mr_source_regions {
+        let mr = system
+            .memory_regions
+            .iter()
+            .find(|mr| mr.name == *mr_name)
+            .unwrap();
+        let data = &mr_contents[mr_name.as_str()];
+        let data_start = phys_addr + mr.source_offset;
+        let data_end = data_start + data.len() as u64;
+        loader_regions.push((data_start, data));
+        for (base, end) in [(*phys_addr, data_start), (data_end, phys_addr + mr.size)] {
+            if base < end {
+                zero_regions.push(MemoryRegion::new(base, end));
+            }
+        }
+    }
 
     let loader = Loader::new(
         &kernel_config,
@@ -3625,8 +5208,9 @@ fn main() -> Result<(), String> {
         Some(built_system.initial_task_phys_region.base),
         built_system.reserved_region,
         loader_regions,
+        zero_regions,
     );
-    loader.write_image(Path::new(args.output));
+    loader.write_image(Path::new(args.output), args.image_type);
 
     Ok(())
 }
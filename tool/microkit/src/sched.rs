@@ -0,0 +1,388 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+// Static schedulability analysis of a system description.
+//
+// Each protection domain and virtual machine CPU is treated as a sporadic task
+// that may run for its budget once every period, scheduled by fixed priority on
+// a single core. Passive protection domains do not have budget of their own;
+// they run on the budget of the PD that called them, at their own priority. A
+// PD that can call into passive servers therefore interferes with every PD at or
+// below the highest priority it can reach through those calls, not just with the
+// PDs below its own priority.
+//
+// A scheduling context whose budget is equal to its period is a round-robin
+// context which is never throttled by the kernel. Such PDs cannot be analysed
+// and the interference they cause to lower priorities is unbounded. They are
+// counted as using all of the core.
+
+use crate::sdf::SystemDescription;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Response {
+    /// Worst-case response time, in microseconds
+    Bounded(u64),
+    /// The response time is longer than the period
+    ExceedsPeriod,
+    /// Interference from a round-robin task makes the response time unbounded
+    Unbounded { round_robin: usize },
+}
+
+#[derive(Debug)]
+pub struct Task {
+    pub name: String,
+    pub priority: u8,
+    /// Highest priority the task runs at, including when in passive servers
+    /// it can call into
+    pub effective_priority: u8,
+    pub budget: u64,
+    pub period: u64,
+    pub passive: bool,
+    /// Only for tasks with their own periodic scheduling context
+    pub response: Option<Response>,
+}
+
+impl Task {
+    pub fn is_round_robin(&self) -> bool {
+        !self.passive && self.budget == self.period
+    }
+
+    fn is_periodic(&self) -> bool {
+        !self.passive && self.budget < self.period
+    }
+
+    /// Utilisation as a percentage of a single core, a round-robin task
+    /// uses all of it
+    pub fn utilisation(&self) -> f64 {
+        match self.passive {
+            true => 0.0,
+            false => self.budget as f64 * 100.0 / self.period as f64,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Analysis {
+    pub tasks: Vec<Task>,
+}
+
+impl Analysis {
+    /// Total utilisation of all tasks, as a percentage
+    pub fn total_utilisation(&self) -> f64 {
+        self.tasks.iter().map(Task::utilisation).sum()
+    }
+
+    /// Utilisation at each priority, from highest to lowest, along with the
+    /// cumulative utilisation of that priority and all higher priorities.
+    pub fn bands(&self) -> Vec<(u8, f64, f64)> {
+        let mut bands: BTreeMap<u8, f64> = BTreeMap::new();
+        for task in self.tasks.iter().filter(|task| !task.passive) {
+            *bands.entry(task.priority).or_default() += task.utilisation();
+        }
+
+        let mut cumulative = 0.0;
+        bands
+            .into_iter()
+            .rev()
+            .map(|(priority, utilisation)| {
+                cumulative += utilisation;
+                (priority, utilisation, cumulative)
+            })
+            .collect()
+    }
+
+    /// The largest worst-case response time of any task as a percentage of its
+    /// period, along with the task. Tasks that miss their period count as
+    /// infinitely late.
+    pub fn worst_response(&self) -> Option<(&Task, f64)> {
+        self.tasks
+            .iter()
+            .filter_map(|task| match task.response? {
+                Response::Bounded(response) => {
+                    Some((task, response as f64 * 100.0 / task.period as f64))
+                }
+                _ => Some((task, f64::INFINITY)),
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+
+    pub fn report(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Total utilisation: {:.2}%", self.total_utilisation()).unwrap();
+
+        writeln!(out, "\nUtilisation by priority:").unwrap();
+        for (priority, utilisation, cumulative) in self.bands() {
+            let overloaded = if cumulative > 100.0 {
+                " (OVERLOADED)"
+            } else {
+                ""
+            };
+            writeln!(
+                out,
+                "    priority {:>3}: {:>7.2}%, cumulative {:>7.2}%{}",
+                priority, utilisation, cumulative, overloaded
+            )
+            .unwrap();
+        }
+
+        writeln!(out, "\nTasks:").unwrap();
+        for task in &self.tasks {
+            if task.passive {
+                writeln!(
+                    out,
+                    "    {}: priority {}, passive, runs on the budget of its callers",
+                    task.name, task.priority
+                )
+                .unwrap();
+                continue;
+            }
+
+            write!(out, "    {}: priority {}", task.name, task.priority).unwrap();
+            if task.effective_priority != task.priority {
+                write!(
+                    out,
+                    " (up to {} in passive servers)",
+                    task.effective_priority
+                )
+                .unwrap();
+            }
+            write!(
+                out,
+                ", budget {} us, period {} us: ",
+                task.budget, task.period
+            )
+            .unwrap();
+            match task.response {
+                None => writeln!(out, "round-robin (budget = period), not analysed"),
+                Some(Response::Bounded(response)) => writeln!(
+                    out,
+                    "utilisation {:.2}%, worst-case response time {} us",
+                    task.utilisation(),
+                    response
+                ),
+                Some(Response::ExceedsPeriod) => writeln!(
+                    out,
+                    "utilisation {:.2}%, MISSES its period",
+                    task.utilisation()
+                ),
+                Some(Response::Unbounded { round_robin }) => writeln!(
+                    out,
+                    "utilisation {:.2}%, response time unbounded due to round-robin '{}'",
+                    task.utilisation(),
+                    self.tasks[round_robin].name
+                ),
+            }
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+/// Worst-case response time of 'tasks[idx]' using the standard fixed-priority
+/// recurrence R = C + sum(ceil(R / T_j) * C_j) over the interfering tasks.
+fn response_time(tasks: &[Task], idx: usize) -> Response {
+    let task = &tasks[idx];
+    // Tasks at the same priority are scheduled round-robin, so may interfere
+    let interfering: Vec<(usize, &Task)> = tasks
+        .iter()
+        .enumerate()
+        .filter(|(j, other)| {
+            *j != idx && !other.passive && other.effective_priority >= task.priority
+        })
+        .collect();
+
+    if let Some((round_robin, _)) = interfering.iter().find(|(_, other)| other.is_round_robin()) {
+        return Response::Unbounded {
+            round_robin: *round_robin,
+        };
+    }
+
+    let mut response = task.budget;
+    loop {
+        let next = task.budget
+            + interfering
+                .iter()
+                .map(|(_, other)| response.div_ceil(other.period) * other.budget)
+                .sum::<u64>();
+        if next > task.period {
+            return Response::ExceedsPeriod;
+        }
+        if next == response {
+            return Response::Bounded(response);
+        }
+        response = next;
+    }
+}
+
+pub fn analyse(system: &SystemDescription) -> Analysis {
+    let pds = &system.protection_domains;
+
+    let mut tasks = Vec::new();
+    for (pd_idx, pd) in pds.iter().enumerate() {
+        // Follow PPCs for as long as the callee is passive and so is running on
+        // the budget of this PD.
+        let mut effective_priority = pd.priority;
+        let mut visited = vec![pd_idx];
+        let mut to_visit = vec![pd_idx];
+        while let Some(caller) = to_visit.pop() {
+            for cc in &system.channels {
+                for (from, to) in [(&cc.end_a, &cc.end_b), (&cc.end_b, &cc.end_a)] {
+                    if from.pd == caller
+                        && from.pp
+                        && pds[to.pd].passive
+                        && !visited.contains(&to.pd)
+                    {
+                        effective_priority = effective_priority.max(pds[to.pd].priority);
+                        visited.push(to.pd);
+                        to_visit.push(to.pd);
+                    }
+                }
+            }
+        }
+
+        tasks.push(Task {
+            name: pd.name.clone(),
+            priority: pd.priority,
+            effective_priority,
+            budget: pd.budget,
+            period: pd.period,
+            passive: pd.passive,
+            response: None,
+        });
+
+        if let Some(vm) = &pd.virtual_machine {
+            for vcpu in &vm.vcpus {
+                tasks.push(Task {
+                    name: format!("{} (vCPU {})", vm.name, vcpu.id),
+                    priority: vm.priority,
+                    effective_priority: vm.priority,
+                    budget: vm.budget,
+                    period: vm.period,
+                    passive: false,
+                    response: None,
+                });
+            }
+        }
+    }
+
+    for idx in 0..tasks.len() {
+        if tasks[idx].is_periodic() {
+            tasks[idx].response = Some(response_time(&tasks, idx));
+        }
+    }
+
+    Analysis { tasks }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sdf;
+    use crate::sel4::test_util::config;
+
+    fn analyse_xml(pds: &str) -> Analysis {
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<system>\n{}\n</system>\n",
+            pds
+        );
+        analyse(&sdf::parse("test.system", &xml, &config()).unwrap())
+    }
+
+    #[test]
+    fn test_response_times() {
+        let analysis = analyse_xml(
+            r#"
+            <protection_domain name="high" priority="3" budget="100" period="400">
+                <program_image path="high.elf" />
+            </protection_domain>
+            <protection_domain name="mid" priority="2" budget="200" period="1000">
+                <program_image path="mid.elf" />
+            </protection_domain>
+            <protection_domain name="low" priority="1" budget="300" period="2000">
+                <program_image path="low.elf" />
+            </protection_domain>
+            "#,
+        );
+
+        assert_eq!(analysis.tasks[0].response, Some(Response::Bounded(100)));
+        // 200 + 100 = 300, then 200 + ceil(300 / 400) * 100 = 300
+        assert_eq!(analysis.tasks[1].response, Some(Response::Bounded(300)));
+        // 300 + 100 + 200 = 600, 300 + 2 * 100 + 200 = 700, then stable
+        assert_eq!(analysis.tasks[2].response, Some(Response::Bounded(700)));
+        assert_eq!(format!("{:.2}", analysis.total_utilisation()), "60.00");
+        let bands: Vec<u8> = analysis.bands().iter().map(|band| band.0).collect();
+        assert_eq!(bands, vec![3, 2, 1]);
+        assert_eq!(analysis.worst_response().unwrap().0.name, "low");
+    }
+
+    #[test]
+    fn test_passive_server_donation() {
+        let analysis = analyse_xml(
+            r#"
+            <protection_domain name="server" priority="10" passive="true">
+                <program_image path="server.elf" />
+            </protection_domain>
+            <protection_domain name="other" priority="5" budget="100" period="300">
+                <program_image path="other.elf" />
+            </protection_domain>
+            <protection_domain name="client" priority="1" budget="250" period="300">
+                <program_image path="client.elf" />
+            </protection_domain>
+            <protection_domain name="spinner" priority="0">
+                <program_image path="spinner.elf" />
+            </protection_domain>
+            <channel>
+                <end pd="client" id="0" pp="true" />
+                <end pd="server" id="0" />
+            </channel>
+            "#,
+        );
+
+        let client = &analysis.tasks[2];
+        assert_eq!(client.effective_priority, 10);
+        // The client runs above 'other' while in the server
+        assert_eq!(analysis.tasks[1].response, Some(Response::ExceedsPeriod));
+        assert_eq!(analysis.tasks[0].response, None);
+        assert_eq!(analysis.tasks[3].response, None);
+        assert!(analysis.report().contains("spinner: priority 0, budget 1000 us, period 1000 us: round-robin (budget = period), not analysed"));
+    }
+
+    #[test]
+    fn test_round_robin_interference() {
+        let analysis = analyse_xml(
+            r#"
+            <protection_domain name="spinner" priority="2">
+                <program_image path="spinner.elf" />
+            </protection_domain>
+            <protection_domain name="periodic" priority="1" budget="100" period="300">
+                <program_image path="periodic.elf" />
+            </protection_domain>
+            "#,
+        );
+
+        assert_eq!(
+            analysis.tasks[1].response,
+            Some(Response::Unbounded { round_robin: 0 })
+        );
+        assert_eq!(analysis.worst_response().unwrap().1, f64::INFINITY);
+
+        // The round-robin task counts as using the whole core
+        assert_eq!(analysis.tasks[0].utilisation(), 100.0);
+        assert_eq!(format!("{:.2}", analysis.total_utilisation()), "133.33");
+        let bands: Vec<(u8, String)> = analysis
+            .bands()
+            .iter()
+            .map(|(priority, _, cumulative)| (*priority, format!("{:.2}", cumulative)))
+            .collect();
+        assert_eq!(
+            bands,
+            vec![(2, "100.00".to_string()), (1, "133.33".to_string())]
+        );
+    }
+}
@@ -5,24 +5,25 @@
 //
 
 use crate::sel4::{Config, IrqTrigger, PageSize};
-use crate::util::str_to_bool;
+use crate::util::{is_power_of_two, str_to_bool};
 use crate::MAX_PDS;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 
-///
-/// This module is responsible for parsing the System Description Format (SDF)
-/// which is based on XML.
-/// We do not use any fancy XML, and instead keep things as minimal and simple
-/// as possible.
-///
-/// As much as possible of the validation of the SDF is done when parsing the XML
-/// here.
-///
-/// There are various XML parsing/deserialising libraries within the Rust eco-system
-/// but few seem to be concerned with giving any introspection regarding the parsed
-/// XML. The roxmltree project allows us to work on a lower-level than something based
-/// on serde and so we can report proper user errors.
-///
+//
+// This module is responsible for parsing the System Description Format (SDF)
+// which is based on XML.
+// We do not use any fancy XML, and instead keep things as minimal and simple
+// as possible.
+//
+// As much as possible of the validation of the SDF is done when parsing the XML
+// here.
+//
+// There are various XML parsing/deserialising libraries within the Rust eco-system
+// but few seem to be concerned with giving any introspection regarding the parsed
+// XML. The roxmltree project allows us to work on a lower-level than something based
+// on serde and so we can report proper user errors.
+//
 
 /// Events that come through entry points (e.g notified or protected) are given an
 /// identifier that is used as the badge at runtime.
@@ -87,6 +88,8 @@ pub struct SysMap {
     pub vaddr: u64,
     pub perms: u8,
     pub cached: bool,
+    /// Size of the unmapped region to reserve below and above the mapping
+    pub guard: u64,
     /// Location in the parsed SDF file. Because this struct is
     /// used in a non-XML context, we make the position optional.
     pub text_pos: Option<roxmltree::TextPos>,
@@ -99,9 +102,43 @@ pub struct SysMemoryRegion {
     pub page_size: PageSize,
     pub page_count: u64,
     pub phys_addr: Option<u64>,
+    /// File to load as the initial contents of the memory region, found on
+    /// the search paths
+    pub source: Option<PathBuf>,
+    /// Offset in the memory region to load the contents of 'source' at
+    pub source_offset: u64,
+    /// Set if the tool must place the memory region in physically contiguous
+    /// memory of its choosing
+    pub contiguous: Option<SysContiguous>,
     pub text_pos: Option<roxmltree::TextPos>,
 }
 
+/// Constraints on where a physically contiguous memory region is placed.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub struct SysContiguous {
+    /// Lowest physical address the memory region may start at
+    pub phys_min: u64,
+    /// Physical address the memory region must end at or below
+    pub phys_max: u64,
+    pub align: u64,
+}
+
+/// Physical memory, e.g owned by firmware, that the tool must not allocate from
+/// even though the kernel considers it to be available.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct SysReservedMemory {
+    pub base: u64,
+    pub size: u64,
+    /// None if the reserved memory is a default of the board
+    pub text_pos: Option<roxmltree::TextPos>,
+}
+
+impl SysReservedMemory {
+    pub fn end(&self) -> u64 {
+        self.base + self.size
+    }
+}
+
 impl SysMemoryRegion {
     pub fn page_bytes(&self) -> u64 {
         self.page_size as u64
@@ -139,6 +176,8 @@ pub struct ChannelEnd {
 pub struct Channel {
     pub end_a: ChannelEnd,
     pub end_b: ChannelEnd,
+    /// Location in the parsed SDF file
+    pub text_pos: roxmltree::TextPos,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -151,6 +190,10 @@ pub struct ProtectionDomain {
     pub period: u64,
     pub passive: bool,
     pub stack_size: u64,
+    /// Size of the unmapped region to reserve below the stack
+    pub stack_guard_size: u64,
+    /// Size of the heap created by the tool, zero if there is no heap
+    pub heap_size: u64,
     pub smc: bool,
     pub program_image: PathBuf,
     pub maps: Vec<SysMap>,
@@ -164,8 +207,12 @@ pub struct ProtectionDomain {
     /// Index into the total list of protection domains if a parent
     /// protection domain exists
     pub parent: Option<usize>,
+    /// Set when the priority is to be assigned by the tool
+    auto_priority: bool,
+    /// Names of the protection domains that this PD must have a higher priority than
+    above: Vec<String>,
     /// Location in the parsed SDF file
-    text_pos: roxmltree::TextPos,
+    pub text_pos: roxmltree::TextPos,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -176,6 +223,8 @@ pub struct VirtualMachine {
     pub priority: u8,
     pub budget: u64,
     pub period: u64,
+    /// Location in the parsed SDF file
+    pub text_pos: roxmltree::TextPos,
 }
 
 #[derive(Debug, PartialEq, Eq, Hash)]
@@ -227,13 +276,25 @@ impl SysMapPerms {
 }
 
 impl SysMap {
+    /// The unmapped regions reserved below and above the mapping of a memory
+    /// region of 'mr_size' bytes, if any.
+    pub fn guard_regions(&self, mr_size: u64) -> Vec<(u64, u64)> {
+        match self.guard {
+            0 => vec![],
+            guard => vec![
+                (self.vaddr - guard, self.vaddr),
+                (self.vaddr + mr_size, self.vaddr + mr_size + guard),
+            ],
+        }
+    }
+
     fn from_xml(
         xml_sdf: &XmlSystemDescription,
         node: &roxmltree::Node,
         allow_setvar: bool,
         max_vaddr: u64,
     ) -> Result<SysMap, String> {
-        let mut attrs = vec!["mr", "vaddr", "perms", "cached"];
+        let mut attrs = vec!["mr", "vaddr", "perms", "cached", "guard"];
         if allow_setvar {
             attrs.push("setvar_vaddr");
         }
@@ -291,17 +352,54 @@ impl SysMap {
             true
         };
 
+        let guard = if let Some(xml_guard) = node.attribute("guard") {
+            sdf_parse_number(xml_guard, node)?
+        } else {
+            0
+        };
+
+        if guard > vaddr {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "guard (0x{:x}) must not extend below address 0 from vaddr (0x{:x})",
+                    guard, vaddr
+                ),
+            ));
+        }
+
         Ok(SysMap {
             mr,
             vaddr,
             perms,
             cached,
+            guard,
             text_pos: Some(xml_sdf.doc.text_pos_at(node.range().start)),
         })
     }
 }
 
 impl ProtectionDomain {
+    /// The unmapped region reserved below the stack, if any.
+    pub fn stack_guard(&self, config: &Config) -> Option<(u64, u64)> {
+        let stack_bottom = config.pd_stack_bottom(self.stack_size);
+        match self.stack_guard_size {
+            0 => None,
+            size => Some((stack_bottom - size, stack_bottom)),
+        }
+    }
+
+    /// The virtual address range of the heap, if any. The heap is placed
+    /// directly below the stack and its guard region.
+    pub fn heap(&self, config: &Config) -> Option<(u64, u64)> {
+        let heap_top = config.pd_stack_bottom(self.stack_size) - self.stack_guard_size;
+        match self.heap_size {
+            0 => None,
+            size => Some((heap_top - size, heap_top)),
+        }
+    }
+
     pub fn needs_ep(&self, self_id: usize, channels: &[Channel]) -> bool {
         self.has_children
             || self.virtual_machine.is_some()
@@ -320,10 +418,13 @@ impl ProtectionDomain {
         let mut attrs = vec![
             "name",
             "priority",
+            "above",
             "budget",
             "period",
             "passive",
             "stack_size",
+            "stack_guard_size",
+            "heap_size",
             // The SMC field is only available in certain configurations
             // but we do the error-checking further down.
             "smc",
@@ -387,6 +488,19 @@ impl ProtectionDomain {
             PD_DEFAULT_STACK_SIZE
         };
 
+        let stack_guard_size =
+            if let Some(xml_stack_guard_size) = node.attribute("stack_guard_size") {
+                sdf_parse_number(xml_stack_guard_size, node)?
+            } else {
+                0
+            };
+
+        let heap_size = if let Some(xml_heap_size) = node.attribute("heap_size") {
+            sdf_parse_number(xml_heap_size, node)?
+        } else {
+            0
+        };
+
         let smc = if let Some(xml_smc) = node.attribute("smc") {
             match str_to_bool(xml_smc) {
                 Some(val) => val,
@@ -441,6 +555,50 @@ impl ProtectionDomain {
             ));
         }
 
+        if stack_guard_size % config.page_sizes()[0] != 0 {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "stack guard size must be aligned to the smallest page size, {} bytes",
+                    config.page_sizes()[0]
+                ),
+            ));
+        }
+
+        if stack_guard_size >= config.pd_stack_bottom(stack_size) {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "stack guard size must be less than 0x{:x} bytes",
+                    config.pd_stack_bottom(stack_size)
+                ),
+            ));
+        }
+
+        if heap_size % config.page_sizes()[0] != 0 {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "heap size must be aligned to the smallest page size, {} bytes",
+                    config.page_sizes()[0]
+                ),
+            ));
+        }
+
+        if heap_size >= config.pd_stack_bottom(stack_size) - stack_guard_size {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "heap size must be less than 0x{:x} bytes",
+                    config.pd_stack_bottom(stack_size) - stack_guard_size
+                ),
+            ));
+        }
+
         let mut maps = Vec::new();
         let mut irqs = Vec::new();
         let mut setvars: Vec<SysSetVar> = Vec::new();
@@ -449,11 +607,20 @@ impl ProtectionDomain {
         let mut program_image = None;
         let mut virtual_machine = None;
 
-        // Default to minimum priority
-        let priority = if let Some(xml_priority) = node.attribute("priority") {
-            sdf_parse_number(xml_priority, node)?
-        } else {
-            0
+        // Default to minimum priority. An automatic priority is filled in once
+        // all PDs and channels have been parsed.
+        let auto_priority = node.attribute("priority") == Some("auto");
+        let priority = match node.attribute("priority") {
+            Some(xml_priority) if !auto_priority => sdf_parse_number(xml_priority, node)?,
+            _ => 0,
+        };
+
+        let above = match node.attribute("above") {
+            Some(xml_above) => xml_above
+                .split(',')
+                .map(|pd_name| pd_name.trim().to_string())
+                .collect(),
+            None => Vec::new(),
         };
 
         if priority > PD_MAX_PRIORITY as u64 {
@@ -484,7 +651,8 @@ impl ProtectionDomain {
                     program_image = Some(Path::new(program_image_path).to_path_buf());
                 }
                 "map" => {
-                    let map_max_vaddr = config.pd_map_max_vaddr(stack_size);
+                    let map_max_vaddr =
+                        config.pd_map_max_vaddr(stack_size) - stack_guard_size - heap_size;
                     let map = SysMap::from_xml(xml_sdf, &child, true, map_max_vaddr)?;
 
                     if let Some(setvar_vaddr) = child.attribute("setvar_vaddr") {
@@ -613,6 +781,8 @@ impl ProtectionDomain {
             period,
             passive,
             stack_size,
+            stack_guard_size,
+            heap_size,
             smc,
             program_image: program_image.unwrap(),
             maps,
@@ -622,6 +792,8 @@ impl ProtectionDomain {
             virtual_machine,
             has_children,
             parent: None,
+            auto_priority,
+            above,
             text_pos: xml_sdf.doc.text_pos_at(node.range().start),
         })
     }
@@ -734,6 +906,7 @@ impl VirtualMachine {
             priority: priority as u8,
             budget,
             period,
+            text_pos: xml_sdf.doc.text_pos_at(node.range().start),
         })
     }
 }
@@ -744,7 +917,22 @@ impl SysMemoryRegion {
         xml_sdf: &XmlSystemDescription,
         node: &roxmltree::Node,
     ) -> Result<SysMemoryRegion, String> {
-        check_attributes(xml_sdf, node, &["name", "size", "page_size", "phys_addr"])?;
+        check_attributes(
+            xml_sdf,
+            node,
+            &[
+                "name",
+                "size",
+                "page_size",
+                "phys_addr",
+                "source",
+                "offset",
+                "contiguous",
+                "phys_min",
+                "phys_max",
+                "align",
+            ],
+        )?;
 
         let name = checked_lookup(xml_sdf, node, "name")?;
         let size = sdf_parse_number(checked_lookup(xml_sdf, node, "size")?, node)?;
@@ -756,7 +944,8 @@ impl SysMemoryRegion {
             config.optimal_page_size(size)
         };
 
-        let page_size_valid = config.page_sizes().contains(&page_size);
+        let page_size_valid =
+            config.page_sizes().contains(&page_size) || config.huge_page_size() == Some(page_size);
         if !page_size_valid {
             return Err(value_error(
                 xml_sdf,
@@ -787,6 +976,140 @@ impl SysMemoryRegion {
             ));
         }
 
+        let source = node.attribute("source").map(PathBuf::from);
+
+        if source.is_some() && phys_addr.is_some() {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                "source cannot be used with phys_addr".to_string(),
+            ));
+        }
+
+        // The contents are placed by the tool in the same way as ELF segments,
+        // which are only ever mapped with small or large pages.
+        if source.is_some() && config.huge_page_size() == Some(page_size) {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "source cannot be used with a page size of 0x{:x}",
+                    page_size
+                ),
+            ));
+        }
+
+        let source_offset = if let Some(xml_offset) = node.attribute("offset") {
+            if source.is_none() {
+                return Err(value_error(
+                    xml_sdf,
+                    node,
+                    "offset can only be used with source".to_string(),
+                ));
+            }
+            sdf_parse_number(xml_offset, node)?
+        } else {
+            0
+        };
+
+        if source_offset >= size {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                format!(
+                    "offset (0x{:x}) must be less than the size (0x{:x})",
+                    source_offset, size
+                ),
+            ));
+        }
+
+        let contiguous = if let Some(xml_contiguous) = node.attribute("contiguous") {
+            match str_to_bool(xml_contiguous) {
+                Some(val) => val,
+                None => {
+                    return Err(value_error(
+                        xml_sdf,
+                        node,
+                        "contiguous must be 'true' or 'false'".to_string(),
+                    ))
+                }
+            }
+        } else {
+            false
+        };
+
+        if !contiguous {
+            for attr in ["phys_min", "phys_max", "align"] {
+                if node.attribute(attr).is_some() {
+                    return Err(value_error(
+                        xml_sdf,
+                        node,
+                        format!("{} can only be used with contiguous=\"true\"", attr),
+                    ));
+                }
+            }
+        }
+
+        let contiguous = if contiguous {
+            if phys_addr.is_some() {
+                return Err(value_error(
+                    xml_sdf,
+                    node,
+                    "contiguous cannot be used with phys_addr".to_string(),
+                ));
+            }
+            if source.is_some() {
+                return Err(value_error(
+                    xml_sdf,
+                    node,
+                    "contiguous cannot be used with source".to_string(),
+                ));
+            }
+
+            let phys_min = match node.attribute("phys_min") {
+                Some(xml_phys_min) => sdf_parse_number(xml_phys_min, node)?,
+                None => 0,
+            };
+            let phys_max = match node.attribute("phys_max") {
+                Some(xml_phys_max) => sdf_parse_number(xml_phys_max, node)?,
+                None => u64::MAX,
+            };
+            let align = match node.attribute("align") {
+                Some(xml_align) => sdf_parse_number(xml_align, node)?,
+                None => page_size,
+            };
+
+            if !is_power_of_two(align) || align < page_size {
+                return Err(value_error(
+                    xml_sdf,
+                    node,
+                    format!(
+                        "align (0x{:x}) must be a power of two and at least the page size (0x{:x})",
+                        align, page_size
+                    ),
+                ));
+            }
+
+            if phys_max < phys_min || phys_max - phys_min < size {
+                return Err(value_error(
+                    xml_sdf,
+                    node,
+                    format!(
+                        "range from phys_min to phys_max [0x{:x}..0x{:x}) cannot fit the size (0x{:x})",
+                        phys_min, phys_max, size
+                    ),
+                ));
+            }
+
+            Some(SysContiguous {
+                phys_min,
+                phys_max,
+                align,
+            })
+        } else {
+            None
+        };
+
         let page_count = size / page_size;
 
         Ok(SysMemoryRegion {
@@ -795,6 +1118,52 @@ impl SysMemoryRegion {
             page_size: page_size.into(),
             page_count,
             phys_addr,
+            source,
+            source_offset,
+            contiguous,
+            text_pos: Some(xml_sdf.doc.text_pos_at(node.range().start)),
+        })
+    }
+}
+
+impl SysReservedMemory {
+    fn from_xml(
+        config: &Config,
+        xml_sdf: &XmlSystemDescription,
+        node: &roxmltree::Node,
+    ) -> Result<SysReservedMemory, String> {
+        check_attributes(xml_sdf, node, &["base", "size"])?;
+
+        let base = sdf_parse_number(checked_lookup(xml_sdf, node, "base")?, node)?;
+        let size = sdf_parse_number(checked_lookup(xml_sdf, node, "size")?, node)?;
+
+        if base % config.minimum_page_size != 0 {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                "base is not aligned to the page size".to_string(),
+            ));
+        }
+
+        if size == 0 || size % config.minimum_page_size != 0 {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                "size must be a non-zero multiple of the page size".to_string(),
+            ));
+        }
+
+        if base.checked_add(size).is_none() {
+            return Err(value_error(
+                xml_sdf,
+                node,
+                "base plus size must not overflow".to_string(),
+            ));
+        }
+
+        Ok(SysReservedMemory {
+            base,
+            size,
             text_pos: Some(xml_sdf.doc.text_pos_at(node.range().start)),
         })
     }
@@ -904,10 +1273,455 @@ impl Channel {
         Ok(Channel {
             end_a: end_a.clone(),
             end_b: end_b.clone(),
+            text_pos: xml_sdf.doc.text_pos_at(node.range().start),
         })
     }
 }
 
+/// Assign priorities to PDs with an automatic priority, such that every PD has
+/// a higher priority than the PDs that are listed in its 'above' attribute or
+/// that can make a PPC to it. Each automatic priority is set to the lowest
+/// priority that satisfies these constraints. PDs with an explicit priority are
+/// checked against the constraints.
+fn assign_priorities(
+    xml_sdf: &XmlSystemDescription,
+    pds: &mut [ProtectionDomain],
+    channels: &[Channel],
+) -> Result<(), String> {
+    if pds
+        .iter()
+        .all(|pd| !pd.auto_priority && pd.above.is_empty())
+    {
+        return Ok(());
+    }
+
+    // Edges from each PD to the PDs that must have a higher priority than it
+    let mut higher: Vec<Vec<usize>> = vec![Vec::new(); pds.len()];
+    for (pd_idx, pd) in pds.iter().enumerate() {
+        for name in &pd.above {
+            match pds.iter().position(|other| &other.name == name) {
+                Some(lower) => higher[lower].push(pd_idx),
+                None => {
+                    return Err(format!(
+                        "Error: invalid PD name '{}' in 'above' of protection domain '{}' @ {}",
+                        name,
+                        pd.name,
+                        loc_string(xml_sdf, pd.text_pos)
+                    ))
+                }
+            }
+        }
+    }
+    for cc in channels {
+        if cc.end_a.pp {
+            higher[cc.end_a.pd].push(cc.end_b.pd);
+        }
+        if cc.end_b.pp {
+            higher[cc.end_b.pd].push(cc.end_a.pd);
+        }
+    }
+
+    // Order the PDs so that every PD comes before those that must be higher
+    let mut lower_count = vec![0; pds.len()];
+    for edges in &higher {
+        for &pd_idx in edges {
+            lower_count[pd_idx] += 1;
+        }
+    }
+    let mut order: Vec<usize> = (0..pds.len()).filter(|i| lower_count[*i] == 0).collect();
+    let mut next = 0;
+    while next < order.len() {
+        for &pd_idx in &higher[order[next]] {
+            lower_count[pd_idx] -= 1;
+            if lower_count[pd_idx] == 0 {
+                order.push(pd_idx);
+            }
+        }
+        next += 1;
+    }
+
+    if order.len() < pds.len() {
+        // Every PD that was not ordered is on, or leads to, a cycle. Follow
+        // the edges between them until we come back to a PD we have seen.
+        let mut path = vec![(0..pds.len()).find(|i| lower_count[*i] > 0).unwrap()];
+        loop {
+            let last = *path.last().unwrap();
+            let next = *higher[last]
+                .iter()
+                .find(|pd_idx| lower_count[**pd_idx] > 0)
+                .unwrap();
+            if let Some(start) = path.iter().position(|pd_idx| *pd_idx == next) {
+                let cycle: Vec<&str> = path[start..]
+                    .iter()
+                    .chain([&next])
+                    .map(|pd_idx| pds[*pd_idx].name.as_str())
+                    .collect();
+                return Err(format!(
+                    "Error: priority constraints form a cycle, each protection domain must have a lower priority than the next: {}",
+                    cycle.join(" -> ")
+                ));
+            }
+            path.push(next);
+        }
+    }
+
+    // The range of priorities each PD can have, working up from the lowest PDs
+    // and down from the highest.
+    let fixed = |pd: &ProtectionDomain| (!pd.auto_priority).then_some(pd.priority as i64);
+    let mut lowest: Vec<i64> = pds.iter().map(|pd| fixed(pd).unwrap_or(0)).collect();
+    let mut highest: Vec<i64> = pds
+        .iter()
+        .map(|pd| fixed(pd).unwrap_or(PD_MAX_PRIORITY as i64))
+        .collect();
+    for &pd_idx in &order {
+        for &higher_idx in &higher[pd_idx] {
+            lowest[higher_idx] = lowest[higher_idx].max(lowest[pd_idx] + 1);
+        }
+    }
+    for &pd_idx in order.iter().rev() {
+        for &higher_idx in &higher[pd_idx] {
+            highest[pd_idx] = highest[pd_idx].min(highest[higher_idx] - 1);
+        }
+    }
+
+    // Report a PD with an automatic priority if possible, as it is the one
+    // caught between the constraints of the others.
+    if let Some(pd_idx) = (0..pds.len())
+        .filter(|i| lowest[*i] > highest[*i])
+        .min_by_key(|i| !pds[*i].auto_priority)
+    {
+        return Err(format!(
+            "Error: priority constraints cannot be satisfied for protection domain '{}': it needs a priority of at least {} and at most {} @ {}",
+            pds[pd_idx].name,
+            lowest[pd_idx],
+            highest[pd_idx],
+            loc_string(xml_sdf, pds[pd_idx].text_pos)
+        ));
+    }
+
+    for (pd_idx, pd) in pds.iter_mut().enumerate() {
+        if pd.auto_priority {
+            pd.priority = lowest[pd_idx] as u8;
+        }
+    }
+
+    Ok(())
+}
+
+/// Kinds of communication between protection domains that an information-flow
+/// policy can forbid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PolicyFlow {
+    /// Any channel between the two PDs
+    Channel,
+    /// A memory region mapped by both PDs (or their virtual machines)
+    Memory,
+    /// A chain of protected procedure calls from one PD to the other
+    Ppc,
+    /// One PD being an ancestor of the other
+    Child,
+}
+
+impl PolicyFlow {
+    fn parse(s: &str) -> Option<PolicyFlow> {
+        match s {
+            "channel" => Some(PolicyFlow::Channel),
+            "memory" => Some(PolicyFlow::Memory),
+            "ppc" => Some(PolicyFlow::Ppc),
+            "child" => Some(PolicyFlow::Child),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            PolicyFlow::Channel => "channel",
+            PolicyFlow::Memory => "memory",
+            PolicyFlow::Ppc => "ppc",
+            PolicyFlow::Child => "child",
+        }
+    }
+
+    fn all() -> Vec<PolicyFlow> {
+        vec![
+            PolicyFlow::Channel,
+            PolicyFlow::Memory,
+            PolicyFlow::Ppc,
+            PolicyFlow::Child,
+        ]
+    }
+}
+
+/// A 'forbid' rule of the system's information-flow policy.
+#[derive(Debug)]
+pub struct PolicyRule {
+    /// Indices of the protection domains on either side of the rule
+    pub from: Vec<usize>,
+    pub to: Vec<usize>,
+    pub flows: Vec<PolicyFlow>,
+    /// PD or group names that the rule was written with
+    from_name: String,
+    to_name: String,
+    text_pos: roxmltree::TextPos,
+}
+
+impl PolicyRule {
+    fn from_xml(
+        xml_sdf: &XmlSystemDescription,
+        node: &roxmltree::Node,
+        pds: &[ProtectionDomain],
+        groups: &HashMap<String, Vec<usize>>,
+    ) -> Result<PolicyRule, String> {
+        check_attributes(xml_sdf, node, &["from", "to", "flows"])?;
+
+        let lookup = |attribute: &'static str| -> Result<Vec<usize>, String> {
+            let name = checked_lookup(xml_sdf, node, attribute)?;
+            if let Some(pd_idx) = pds.iter().position(|pd| pd.name == name) {
+                Ok(vec![pd_idx])
+            } else if let Some(members) = groups.get(name) {
+                Ok(members.clone())
+            } else {
+                Err(value_error(
+                    xml_sdf,
+                    node,
+                    format!("invalid PD or group name '{name}'"),
+                ))
+            }
+        };
+        let from = lookup("from")?;
+        let to = lookup("to")?;
+
+        let flows = match node.attribute("flows") {
+            Some(flows) => flows
+                .split(',')
+                .map(|flow| {
+                    PolicyFlow::parse(flow.trim()).ok_or_else(|| {
+                        value_error(
+                            xml_sdf,
+                            node,
+                            format!(
+                                "invalid flow '{}' (expected 'channel', 'memory', 'ppc' or 'child')",
+                                flow.trim()
+                            ),
+                        )
+                    })
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => PolicyFlow::all(),
+        };
+
+        Ok(PolicyRule {
+            from,
+            to,
+            flows,
+            from_name: checked_lookup(xml_sdf, node, "from")?.to_string(),
+            to_name: checked_lookup(xml_sdf, node, "to")?.to_string(),
+            text_pos: xml_sdf.doc.text_pos_at(node.range().start),
+        })
+    }
+}
+
+/// Parse the 'group' and 'forbid' elements of all 'policy' elements. Groups
+/// can be used in any rule, regardless of which 'policy' they are declared in.
+fn parse_policy(
+    xml_sdf: &XmlSystemDescription,
+    nodes: &[roxmltree::Node],
+    pds: &[ProtectionDomain],
+) -> Result<Vec<PolicyRule>, String> {
+    let mut groups: HashMap<String, Vec<usize>> = HashMap::new();
+    let mut rule_nodes = Vec::new();
+
+    for node in nodes {
+        check_attributes(xml_sdf, node, &[])?;
+        for child in node.children().filter(|child| child.is_element()) {
+            match child.tag_name().name() {
+                "group" => {
+                    check_attributes(xml_sdf, &child, &["name"])?;
+                    let name = checked_lookup(xml_sdf, &child, "name")?;
+                    if groups.contains_key(name) || pds.iter().any(|pd| pd.name == name) {
+                        return Err(value_error(
+                            xml_sdf,
+                            &child,
+                            format!("duplicate PD or group name '{name}'"),
+                        ));
+                    }
+
+                    let mut members = Vec::new();
+                    for member in child.children().filter(|member| member.is_element()) {
+                        if member.tag_name().name() != "member" {
+                            let pos = xml_sdf.doc.text_pos_at(member.range().start);
+                            return Err(format!(
+                                "Error: invalid XML element '{}': {}",
+                                member.tag_name().name(),
+                                loc_string(xml_sdf, pos)
+                            ));
+                        }
+                        check_attributes(xml_sdf, &member, &["pd"])?;
+                        let pd_name = checked_lookup(xml_sdf, &member, "pd")?;
+                        match pds.iter().position(|pd| pd.name == pd_name) {
+                            Some(pd_idx) => members.push(pd_idx),
+                            None => {
+                                return Err(value_error(
+                                    xml_sdf,
+                                    &member,
+                                    format!("invalid PD name '{pd_name}'"),
+                                ))
+                            }
+                        }
+                    }
+                    groups.insert(name.to_string(), members);
+                }
+                "forbid" => rule_nodes.push(child),
+                child_name => {
+                    let pos = xml_sdf.doc.text_pos_at(child.range().start);
+                    return Err(format!(
+                        "Error: invalid XML element '{}': {}",
+                        child_name,
+                        loc_string(xml_sdf, pos)
+                    ));
+                }
+            }
+        }
+    }
+
+    rule_nodes
+        .iter()
+        .map(|node| PolicyRule::from_xml(xml_sdf, node, pds, &groups))
+        .collect()
+}
+
+/// Check the actual communication between protection domains against the
+/// information-flow policy of the system. All violations are reported
+/// together, one per line.
+fn check_policy(
+    xml_sdf: &XmlSystemDescription,
+    pds: &[ProtectionDomain],
+    channels: &[Channel],
+    rules: &[PolicyRule],
+) -> Result<(), String> {
+    fn maps(pd: &ProtectionDomain) -> Vec<&String> {
+        let mut maps: Vec<&String> = pd.maps.iter().map(|map| &map.mr).collect();
+        if let Some(vm) = &pd.virtual_machine {
+            maps.extend(vm.maps.iter().map(|map| &map.mr));
+        }
+        maps
+    }
+    let is_ancestor = |ancestor: usize, mut pd_idx: usize| {
+        while let Some(parent) = pds[pd_idx].parent {
+            if parent == ancestor {
+                return true;
+            }
+            pd_idx = parent;
+        }
+        false
+    };
+    // Find a chain of PPCs from one PD to another, if there is one
+    let ppc_path = |from: usize, to: usize| -> Option<Vec<usize>> {
+        let mut previous: Vec<Option<usize>> = vec![None; pds.len()];
+        let mut queue = std::collections::VecDeque::from([from]);
+        while let Some(pd_idx) = queue.pop_front() {
+            if pd_idx == to {
+                let mut path = vec![to];
+                while let Some(prev) = previous[*path.last().unwrap()] {
+                    path.push(prev);
+                }
+                path.reverse();
+                return Some(path);
+            }
+            for cc in channels {
+                for (caller, callee) in [(&cc.end_a, &cc.end_b), (&cc.end_b, &cc.end_a)] {
+                    if caller.pd == pd_idx
+                        && caller.pp
+                        && callee.pd != from
+                        && previous[callee.pd].is_none()
+                    {
+                        previous[callee.pd] = Some(pd_idx);
+                        queue.push_back(callee.pd);
+                    }
+                }
+            }
+        }
+        None
+    };
+
+    let mut violations = Vec::new();
+    for rule in rules {
+        let flows: Vec<&str> = rule.flows.iter().map(|flow| flow.name()).collect();
+        for &from in &rule.from {
+            for &to in &rule.to {
+                if from == to {
+                    continue;
+                }
+                let (pd_from, pd_to) = (&pds[from], &pds[to]);
+                for flow in &rule.flows {
+                    let violation = match flow {
+                        PolicyFlow::Channel => channels
+                            .iter()
+                            .any(|cc| {
+                                (cc.end_a.pd, cc.end_b.pd) == (from, to)
+                                    || (cc.end_a.pd, cc.end_b.pd) == (to, from)
+                            })
+                            .then(|| {
+                                format!("channel between '{}' and '{}'", pd_from.name, pd_to.name)
+                            }),
+                        PolicyFlow::Memory => {
+                            let to_maps = maps(pd_to);
+                            maps(pd_from)
+                                .into_iter()
+                                .find(|mr| to_maps.contains(mr))
+                                .map(|mr| {
+                                    format!(
+                                        "memory region '{}' is mapped by both '{}' and '{}'",
+                                        mr, pd_from.name, pd_to.name
+                                    )
+                                })
+                        }
+                        PolicyFlow::Ppc => ppc_path(from, to).map(|path| {
+                            let path: Vec<&str> = path
+                                .iter()
+                                .map(|pd_idx| pds[*pd_idx].name.as_str())
+                                .collect();
+                            format!("protected procedure calls from {}", path.join(" -> "))
+                        }),
+                        PolicyFlow::Child => {
+                            if is_ancestor(from, to) {
+                                Some(format!(
+                                    "'{}' is a descendant of '{}'",
+                                    pd_to.name, pd_from.name
+                                ))
+                            } else if is_ancestor(to, from) {
+                                Some(format!(
+                                    "'{}' is a descendant of '{}'",
+                                    pd_from.name, pd_to.name
+                                ))
+                            } else {
+                                None
+                            }
+                        }
+                    };
+
+                    if let Some(violation) = violation {
+                        violations.push(format!(
+                            "Error: system violates its policy: {} is forbidden by the rule from '{}' to '{}' with flows '{}' @ {}",
+                            violation,
+                            rule.from_name,
+                            rule.to_name,
+                            flows.join(","),
+                            loc_string(xml_sdf, rule.text_pos)
+                        ));
+                    }
+                }
+            }
+        }
+    }
+
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(violations.join("\n"))
+    }
+}
+
 struct XmlSystemDescription<'a> {
     filename: &'a str,
     doc: &'a roxmltree::Document<'a>,
@@ -918,6 +1732,7 @@ pub struct SystemDescription {
     pub protection_domains: Vec<ProtectionDomain>,
     pub memory_regions: Vec<SysMemoryRegion>,
     pub channels: Vec<Channel>,
+    pub reserved_memory: Vec<SysReservedMemory>,
 }
 
 fn check_maps(
@@ -925,8 +1740,15 @@ fn check_maps(
     mrs: &[SysMemoryRegion],
     e: &dyn ExecutionContext,
     maps: &[SysMap],
+    reserved: &[(&str, u64, u64)],
 ) -> Result<(), String> {
     let mut checked_maps = Vec::with_capacity(maps.len());
+    // Guard regions (and anything else created by the tool) are reserved,
+    // nothing may be mapped in them
+    let mut guards: Vec<(String, u64, u64)> = reserved
+        .iter()
+        .map(|(what, start, end)| (what.to_string(), *start, *end))
+        .collect();
     for map in maps {
         let maybe_mr = mrs.iter().find(|mr| mr.name == map.mr);
         let pos = map.text_pos.unwrap();
@@ -939,6 +1761,15 @@ fn check_maps(
                     ));
                 }
 
+                if map.guard % mr.page_size as u64 != 0 {
+                    return Err(format!(
+                        "Error: guard must be a multiple of the page size of memory region '{}' (0x{:x}) on 'map' @ {}",
+                        mr.name,
+                        mr.page_size as u64,
+                        loc_string(xml_sdf, pos)
+                    ));
+                }
+
                 let map_start = map.vaddr;
                 let map_end = map.vaddr + mr.size;
                 for (name, start, end) in &checked_maps {
@@ -959,7 +1790,52 @@ fn check_maps(
                         );
                     }
                 }
+
+                for (owner, start, end) in &guards {
+                    if !(map_start >= *end || map_end <= *start) {
+                        return Err(format!(
+                            "Error: map for '{}' has virtual address range [0x{:x}..0x{:x}) which overlaps with {} [0x{:x}..0x{:x}) in {} '{}' @ {}",
+                            map.mr,
+                            map_start,
+                            map_end,
+                            owner,
+                            start,
+                            end,
+                            e.kind(),
+                            e.name(),
+                            loc_string(xml_sdf, pos)
+                        ));
+                    }
+                }
+
+                let map_guards = map.guard_regions(mr.size);
+                for (guard_start, guard_end) in &map_guards {
+                    for (name, start, end) in &checked_maps {
+                        if !(*guard_start >= *end || *guard_end <= *start) {
+                            return Err(format!(
+                                "Error: guard region [0x{:x}..0x{:x}) of map for '{}' overlaps with map for '{}' [0x{:x}..0x{:x}) in {} '{}' @ {}",
+                                guard_start,
+                                guard_end,
+                                map.mr,
+                                name,
+                                start,
+                                end,
+                                e.kind(),
+                                e.name(),
+                                loc_string(xml_sdf, pos)
+                            ));
+                        }
+                    }
+                }
+
                 checked_maps.push((&map.mr, map_start, map_end));
+                for (guard_start, guard_end) in map_guards {
+                    guards.push((
+                        format!("the guard region of map for '{}'", map.mr),
+                        guard_start,
+                        guard_end,
+                    ));
+                }
             }
             None => {
                 return Err(format!(
@@ -1146,6 +2022,7 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
     let mut root_pds = vec![];
     let mut mrs = vec![];
     let mut channels = vec![];
+    let mut reserved_memory: Vec<SysReservedMemory> = vec![];
 
     let system = doc
         .root()
@@ -1160,6 +2037,9 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
     // via an index in the list of PDs. This means that we have to parse all PDs first and
     // then parse the channels.
     let mut channel_nodes = Vec::new();
+    // Likewise the policy refers to PDs by name, so is checked once everything
+    // else has been parsed.
+    let mut policy_nodes = Vec::new();
 
     for child in system.children() {
         if !child.is_element() {
@@ -1172,7 +2052,25 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
                 root_pds.push(ProtectionDomain::from_xml(config, &xml_sdf, &child, false)?)
             }
             "channel" => channel_nodes.push(child),
+            "policy" => policy_nodes.push(child),
             "memory_region" => mrs.push(SysMemoryRegion::from_xml(config, &xml_sdf, &child)?),
+            "reserved_memory" => {
+                let reserved = SysReservedMemory::from_xml(config, &xml_sdf, &child)?;
+                if let Some(other) = reserved_memory
+                    .iter()
+                    .find(|r| r.base < reserved.end() && reserved.base < r.end())
+                {
+                    return Err(format!(
+                        "Error: reserved memory [0x{:x}..0x{:x}) overlaps with reserved memory [0x{:x}..0x{:x}) @ {}",
+                        reserved.base,
+                        reserved.end(),
+                        other.base,
+                        other.end(),
+                        loc_string(&xml_sdf, reserved.text_pos.unwrap())
+                    ));
+                }
+                reserved_memory.push(reserved);
+            }
             "virtual_machine" => {
                 let pos = xml_sdf.doc.text_pos_at(child.range().start);
                 return Err(format!(
@@ -1191,12 +2089,14 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
         }
     }
 
-    let pds = pd_flatten(&xml_sdf, root_pds)?;
+    let mut pds = pd_flatten(&xml_sdf, root_pds)?;
 
     for node in channel_nodes {
         channels.push(Channel::from_xml(&xml_sdf, &node, &pds)?);
     }
 
+    assign_priorities(&xml_sdf, &mut pds, &channels)?;
+
     // Now that we have parsed everything in the system description we can validate any
     // global properties (e.g no duplicate PD names etc).
 
@@ -1230,16 +2130,16 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
         }
     }
 
-    let mut vms = vec![];
+    let mut vm_names = vec![];
     for pd in &pds {
         if let Some(vm) = &pd.virtual_machine {
-            if vms.contains(&vm) {
+            if vm_names.contains(&&vm.name) {
                 return Err(format!(
                     "Error: duplicate virtual machine name '{}'.",
                     vm.name
                 ));
             }
-            vms.push(vm);
+            vm_names.push(&vm.name);
         }
     }
 
@@ -1311,9 +2211,16 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
 
     // Ensure that all maps are correct
     for pd in &pds {
-        check_maps(&xml_sdf, &mrs, pd, &pd.maps)?;
+        let mut reserved = Vec::new();
+        if let Some((start, end)) = pd.stack_guard(config) {
+            reserved.push(("the guard region of the stack", start, end));
+        }
+        if let Some((start, end)) = pd.heap(config) {
+            reserved.push(("the heap", start, end));
+        }
+        check_maps(&xml_sdf, &mrs, pd, &pd.maps, &reserved)?;
         if let Some(vm) = &pd.virtual_machine {
-            check_maps(&xml_sdf, &mrs, vm, &vm.maps)?;
+            check_maps(&xml_sdf, &mrs, vm, &vm.maps, &[])?;
         }
     }
 
@@ -1346,31 +2253,13 @@ pub fn parse(filename: &str, xml: &str, config: &Config) -> Result<SystemDescrip
         }
     }
 
-    // Check that all MRs are used
-    let mut all_maps = vec![];
-    for pd in &pds {
-        all_maps.extend(&pd.maps);
-        if let Some(vm) = &pd.virtual_machine {
-            all_maps.extend(&vm.maps);
-        }
-    }
-    for mr in &mrs {
-        let mut found = false;
-        for map in &all_maps {
-            if map.mr == mr.name {
-                found = true;
-                break;
-            }
-        }
-
-        if !found {
-            println!("WARNING: unused memory region '{}'", mr.name);
-        }
-    }
+    let policy = parse_policy(&xml_sdf, &policy_nodes, &pds)?;
+    check_policy(&xml_sdf, &pds, &channels, &policy)?;
 
     Ok(SystemDescription {
         protection_domains: pds,
         memory_regions: mrs,
         channels,
+        reserved_memory,
     })
 }
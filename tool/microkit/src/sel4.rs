@@ -7,6 +7,7 @@
 use crate::UntypedObject;
 use std::collections::HashMap;
 use std::io::{BufWriter, Write};
+use std::iter::zip;
 
 #[derive(Clone)]
 pub struct BootInfo {
@@ -80,6 +81,19 @@ impl Config {
         }
     }
 
+    /// The size of huge pages, if the kernel supports them in this
+    /// configuration, which is on AArch64 and on RISC-V with Sv39. Huge pages
+    /// are only used when a memory region asks for them explicitly, so they
+    /// are not in 'page_sizes'.
+    pub fn huge_page_size(&self) -> Option<u64> {
+        match self.arch {
+            Arch::Aarch64 => Some(0x4000_0000),
+            Arch::Riscv64 => match self.riscv_pt_levels.unwrap() {
+                RiscvVirtualMemory::Sv39 => Some(0x4000_0000),
+            },
+        }
+    }
+
     // Given the size of a memory region, returns the 'most optimal'
     // page size for the platform based on the alignment of the size.
     pub fn optimal_page_size(&self, size: u64) -> u64 {
@@ -120,6 +134,7 @@ impl Config {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Arch {
     Aarch64,
     Riscv64,
@@ -220,6 +235,31 @@ impl ObjectType {
         }
     }
 
+    /// The inverse of 'value', used when decoding raw invocations.
+    pub fn from_value(config: &Config, value: u64) -> Option<ObjectType> {
+        let mut object_types = vec![
+            ObjectType::Untyped,
+            ObjectType::Tcb,
+            ObjectType::Endpoint,
+            ObjectType::Notification,
+            ObjectType::CNode,
+            ObjectType::SchedContext,
+            ObjectType::Reply,
+            ObjectType::HugePage,
+            ObjectType::VSpace,
+            ObjectType::SmallPage,
+            ObjectType::LargePage,
+            ObjectType::PageTable,
+        ];
+        if config.arch == Arch::Aarch64 {
+            object_types.push(ObjectType::Vcpu);
+        }
+
+        object_types
+            .into_iter()
+            .find(|object_type| object_type.value(config) == value)
+    }
+
     /// The kernel associates each kernel object with an identifier, which
     /// also depends on the configuration of the kernel.
     /// When generating the raw invocation to be given to the initial task,
@@ -277,6 +317,7 @@ impl ObjectType {
 pub enum PageSize {
     Small = 0x1000,
     Large = 0x200_000,
+    Huge = 0x4000_0000,
 }
 
 impl From<u64> for PageSize {
@@ -284,6 +325,7 @@ impl From<u64> for PageSize {
         match item {
             0x1000 => PageSize::Small,
             0x200_000 => PageSize::Large,
+            0x4000_0000 => PageSize::Huge,
             _ => panic!("Unknown page size {:x}", item),
         }
     }
@@ -733,6 +775,196 @@ impl Invocation {
         }
     }
 
+    /// Number of bytes that 'add_raw_invocation' adds for this invocation.
+    pub fn raw_size(&self, config: &Config) -> u64 {
+        let (_, args, extra_caps) = self.args.clone().get_args(config);
+        // Message tag and service, followed by the extra caps and arguments
+        let mut words = 2 + extra_caps.len() + args.len();
+        if let Some((_, repeat)) = &self.repeat {
+            let (_, repeat_args, repeat_extra_caps) = repeat.clone().get_args(config);
+            words += 1 + repeat_extra_caps.len() + repeat_args.len();
+        }
+
+        (words * 8) as u64
+    }
+
+    /// Decode 'count' invocations from data written by 'add_raw_invocation'.
+    /// The raw labels are mapped back to invocations using the label mapping
+    /// of the kernel configuration.
+    pub fn decode(config: &Config, data: &[u8], count: usize) -> Result<Vec<Invocation>, String> {
+        let mut labels: HashMap<u64, &str> = HashMap::new();
+        if let Some(invocations_labels) = config.invocations_labels.as_object() {
+            for (name, value) in invocations_labels {
+                if let Some(value) = value.as_u64() {
+                    labels.insert(value, name);
+                }
+            }
+        }
+
+        let words: Vec<u64> = data
+            .chunks_exact(8)
+            .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+
+        let mut invocations = Vec::with_capacity(count);
+        let mut offset = 0;
+        for i in 0..count {
+            let tag = match words.get(offset) {
+                Some(tag) => *tag,
+                None => return Err(format!("invocation {} is missing", i)),
+            };
+            let iterations = (tag >> 32) + 1;
+            let label_raw = (tag & 0xffffffff) >> 12;
+            let extra_caps_len = ((tag >> 7) & 0x3) as usize;
+            let args_len = (tag & 0x7f) as usize;
+            let label = match labels.get(&label_raw) {
+                Some(label) => *label,
+                None => return Err(format!("invocation {} has unknown label {}", i, label_raw)),
+            };
+
+            let mut len = 2 + extra_caps_len + args_len;
+            if iterations > 1 {
+                len += 1 + extra_caps_len + args_len;
+            }
+            if offset + len > words.len() {
+                return Err(format!("invocation {} is truncated", i));
+            }
+
+            let service = words[offset + 1];
+            let extra_caps = &words[offset + 2..offset + 2 + extra_caps_len];
+            let args_start = offset + 2 + extra_caps_len;
+            let args = &words[args_start..args_start + args_len];
+            let invocation_args = InvocationArgs::decode(config, label, service, args, extra_caps)
+                .map_err(|err| format!("invocation {}: {}", i, err))?;
+
+            let repeat = if iterations > 1 {
+                let repeat_start = args_start + args_len;
+                let repeat_service = words[repeat_start];
+                let repeat_extra_caps = &words[repeat_start + 1..repeat_start + 1 + extra_caps_len];
+                let repeat_args_start = repeat_start + 1 + extra_caps_len;
+                let repeat_args = &words[repeat_args_start..repeat_args_start + args_len];
+                let repeat_invocation_args = InvocationArgs::decode(
+                    config,
+                    label,
+                    repeat_service,
+                    repeat_args,
+                    repeat_extra_caps,
+                )
+                .map_err(|err| format!("invocation {} repeat: {}", i, err))?;
+                Some((iterations as u32, repeat_invocation_args))
+            } else {
+                None
+            };
+
+            invocations.push(Invocation {
+                label: invocation_args.to_label(config),
+                label_raw: label_raw as u32,
+                args: invocation_args,
+                repeat,
+            });
+            offset += len;
+        }
+
+        Ok(invocations)
+    }
+
+    /// Name of the invocation's label, as used by the kernel configuration.
+    pub fn label_name(&self) -> String {
+        self.label.to_string()
+    }
+
+    /// The arguments of each time the invocation is performed, with 'repeat'
+    /// expanded.
+    pub fn expand(&self, config: &Config) -> Vec<InvocationArgs> {
+        let (count, step) = match &self.repeat {
+            Some((count, step)) => (*count, step),
+            None => return vec![self.args.clone()],
+        };
+
+        let label = self.label.to_string();
+        let (_, _, extra_caps) = self.args.clone().get_args(config);
+        let base_words = self.args.raw_words(config);
+        let step_words = step.raw_words(config);
+        (0..count as u64)
+            .map(|i| {
+                let words: Vec<u64> = zip(&base_words, &step_words)
+                    .map(|(base, step)| base.wrapping_add(step.wrapping_mul(i)))
+                    .collect();
+                let (service, words) = words.split_first().unwrap();
+                let (extra_caps, args) = words.split_at(extra_caps.len());
+                InvocationArgs::decode(config, &label, *service, args, extra_caps)
+                    .expect("Internal error: could not decode repeated invocation")
+            })
+            .collect()
+    }
+
+    /// Number of times the invocation is performed, taking 'repeat' into account.
+    pub fn count(&self) -> u32 {
+        match self.repeat {
+            Some((count, _)) => count,
+            None => 1,
+        }
+    }
+
+    /// Merge runs of consecutive invocations whose arguments form an
+    /// arithmetic progression into a single invocation using 'repeat'.
+    /// The invocations performed, and the order they are performed in,
+    /// is unchanged.
+    pub fn batch(config: &Config, invocations: Vec<Invocation>) -> Vec<Invocation> {
+        let mut batched: Vec<Invocation> = Vec::with_capacity(invocations.len());
+        for invocation in invocations {
+            if let Some(last) = batched.last_mut() {
+                if last.extend(config, &invocation) {
+                    continue;
+                }
+            }
+            batched.push(invocation);
+        }
+
+        batched
+    }
+
+    /// Extend this invocation with 'next' if 'next' continues the progression
+    /// of arguments. Returns whether the invocation was extended.
+    fn extend(&mut self, config: &Config, next: &Invocation) -> bool {
+        if self.label_raw != next.label_raw {
+            return false;
+        }
+        let count = self.count();
+        let next_count = next.count();
+        if count.checked_add(next_count).is_none() {
+            return false;
+        }
+
+        let step = match &self.repeat {
+            Some((_, step)) => step.clone(),
+            None => match self.args.step(&next.args) {
+                Some(step) => step,
+                None => return false,
+            },
+        };
+
+        let base_words = self.args.raw_words(config);
+        let step_words = step.raw_words(config);
+        let next_words = next.args.raw_words(config);
+        let continues =
+            zip(zip(&base_words, &step_words), &next_words).all(|((base, step), next)| {
+                base.wrapping_add(step.wrapping_mul(count as u64)) == *next
+            });
+        if !continues {
+            return false;
+        }
+        if let Some((_, next_step)) = &next.repeat {
+            if next_step.raw_words(config) != step_words {
+                return false;
+            }
+        }
+
+        self.repeat = Some((count + next_count, step));
+
+        true
+    }
+
     /// With how count is used when we convert the invocation, it is limited to a u32.
     pub fn repeat(&mut self, count: u32, repeat_args: InvocationArgs) {
         assert!(self.repeat.is_none());
@@ -770,16 +1002,19 @@ impl Invocation {
         format!("         {:<20} {}", field_name, value)
     }
 
+    fn cap_name(cap_lookup: &HashMap<u64, String>, cap: u64) -> &str {
+        match cap_lookup.get(&cap) {
+            Some(name) => name,
+            None => "None",
+        }
+    }
+
     fn fmt_field_cap(
         field_name: &'static str,
         cap: u64,
         cap_lookup: &HashMap<u64, String>,
     ) -> String {
-        let s = if let Some(name) = cap_lookup.get(&cap) {
-            name
-        } else {
-            "None"
-        };
+        let s = Invocation::cap_name(cap_lookup, cap);
         let field = format!("{} (cap)", field_name);
         format!("         {:<20} 0x{:016x} ({})", field, cap, s)
     }
@@ -822,7 +1057,7 @@ impl Invocation {
                 arg_strs.push(Invocation::fmt_field("node_depth", node_depth));
                 arg_strs.push(Invocation::fmt_field("node_offset", node_offset));
                 arg_strs.push(Invocation::fmt_field("num_objects", num_objects));
-                (untyped, &cap_lookup[&untyped])
+                (untyped, Invocation::cap_name(cap_lookup, untyped))
             }
             InvocationArgs::TcbSetSchedParams {
                 tcb,
@@ -845,7 +1080,7 @@ impl Invocation {
                     cap_lookup,
                 ));
                 arg_strs.push(Invocation::fmt_field_cap("fault_ep", fault_ep, cap_lookup));
-                (tcb, &cap_lookup[&tcb])
+                (tcb, Invocation::cap_name(cap_lookup, tcb))
             }
             InvocationArgs::TcbSetSpace {
                 tcb,
@@ -868,7 +1103,7 @@ impl Invocation {
                     cap_lookup,
                 ));
                 arg_strs.push(Invocation::fmt_field("vspace_root_data", vspace_root_data));
-                (tcb, &cap_lookup[&tcb])
+                (tcb, Invocation::cap_name(cap_lookup, tcb))
             }
             InvocationArgs::TcbSetIpcBuffer {
                 tcb,
@@ -881,9 +1116,9 @@ impl Invocation {
                     buffer_frame,
                     cap_lookup,
                 ));
-                (tcb, &cap_lookup[&tcb])
+                (tcb, Invocation::cap_name(cap_lookup, tcb))
             }
-            InvocationArgs::TcbResume { tcb } => (tcb, &cap_lookup[&tcb]),
+            InvocationArgs::TcbResume { tcb } => (tcb, Invocation::cap_name(cap_lookup, tcb)),
             InvocationArgs::TcbWriteRegisters {
                 tcb,
                 resume,
@@ -903,7 +1138,7 @@ impl Invocation {
                     arg_strs.push(format!("                              {}", s));
                 }
 
-                (tcb, &cap_lookup[&tcb])
+                (tcb, Invocation::cap_name(cap_lookup, tcb))
             }
             InvocationArgs::TcbBindNotification { tcb, notification } => {
                 arg_strs.push(Invocation::fmt_field_cap(
@@ -911,11 +1146,11 @@ impl Invocation {
                     notification,
                     cap_lookup,
                 ));
-                (tcb, &cap_lookup[&tcb])
+                (tcb, Invocation::cap_name(cap_lookup, tcb))
             }
             InvocationArgs::AsidPoolAssign { asid_pool, vspace } => {
                 arg_strs.push(Invocation::fmt_field_cap("vspace", vspace, cap_lookup));
-                (asid_pool, &cap_lookup[&asid_pool])
+                (asid_pool, Invocation::cap_name(cap_lookup, asid_pool))
             }
             InvocationArgs::IrqControlGetTrigger {
                 irq_control,
@@ -934,7 +1169,7 @@ impl Invocation {
                 ));
                 arg_strs.push(Invocation::fmt_field("dest_index", dest_index));
                 arg_strs.push(Invocation::fmt_field("dest_depth", dest_depth));
-                (irq_control, &cap_lookup[&irq_control])
+                (irq_control, Invocation::cap_name(cap_lookup, irq_control))
             }
             InvocationArgs::IrqHandlerSetNotification {
                 irq_handler,
@@ -945,7 +1180,7 @@ impl Invocation {
                     notification,
                     cap_lookup,
                 ));
-                (irq_handler, &cap_lookup[&irq_handler])
+                (irq_handler, Invocation::cap_name(cap_lookup, irq_handler))
             }
             InvocationArgs::PageTableMap {
                 page_table,
@@ -956,7 +1191,7 @@ impl Invocation {
                 arg_strs.push(Invocation::fmt_field_cap("vspace", vspace, cap_lookup));
                 arg_strs.push(Invocation::fmt_field_hex("vaddr", vaddr));
                 arg_strs.push(Invocation::fmt_field("attr", attr));
-                (page_table, &cap_lookup[&page_table])
+                (page_table, Invocation::cap_name(cap_lookup, page_table))
             }
             InvocationArgs::PageMap {
                 page,
@@ -969,7 +1204,7 @@ impl Invocation {
                 arg_strs.push(Invocation::fmt_field_hex("vaddr", vaddr));
                 arg_strs.push(Invocation::fmt_field("rights", rights));
                 arg_strs.push(Invocation::fmt_field("attr", attr));
-                (page, &cap_lookup[&page])
+                (page, Invocation::cap_name(cap_lookup, page))
             }
             InvocationArgs::CnodeCopy {
                 cnode,
@@ -986,7 +1221,7 @@ impl Invocation {
                 arg_strs.push(Invocation::fmt_field_cap("src_obj", src_obj, cap_lookup));
                 arg_strs.push(Invocation::fmt_field("src_depth", src_depth));
                 arg_strs.push(Invocation::fmt_field("rights", rights));
-                (cnode, &cap_lookup[&cnode])
+                (cnode, Invocation::cap_name(cap_lookup, cnode))
             }
             InvocationArgs::CnodeMint {
                 cnode,
@@ -1005,7 +1240,7 @@ impl Invocation {
                 arg_strs.push(Invocation::fmt_field("src_depth", src_depth));
                 arg_strs.push(Invocation::fmt_field("rights", rights));
                 arg_strs.push(Invocation::fmt_field("badge", badge));
-                (cnode, &cap_lookup[&cnode])
+                (cnode, Invocation::cap_name(cap_lookup, cnode))
             }
             InvocationArgs::SchedControlConfigureFlags {
                 sched_control,
@@ -1030,7 +1265,7 @@ impl Invocation {
             }
             InvocationArgs::ArmVcpuSetTcb { vcpu, tcb } => {
                 arg_strs.push(Invocation::fmt_field_cap("tcb", tcb, cap_lookup));
-                (vcpu, &cap_lookup[&vcpu])
+                (vcpu, Invocation::cap_name(cap_lookup, vcpu))
             }
         };
         _ = writeln!(
@@ -1104,6 +1339,541 @@ impl Invocation {
 }
 
 impl InvocationArgs {
+    /// The service, extra caps and arguments of the invocation, in the order
+    /// they are written out.
+    fn raw_words(&self, config: &Config) -> Vec<u64> {
+        let (service, args, extra_caps) = self.clone().get_args(config);
+        let mut words = Vec::with_capacity(1 + extra_caps.len() + args.len());
+        words.push(service);
+        words.extend(extra_caps);
+        words.extend(args);
+        words
+    }
+
+    /// Arguments that, when used with 'repeat', step from these arguments to
+    /// 'next'. Returns None if the invocations cannot be repeated, for example
+    /// if they have different object types.
+    fn step(&self, next: &InvocationArgs) -> Option<InvocationArgs> {
+        let d = |a: u64, b: u64| b.wrapping_sub(a);
+        let step = match (self, next) {
+            (
+                InvocationArgs::UntypedRetype {
+                    untyped,
+                    object_type,
+                    size_bits,
+                    root,
+                    node_index,
+                    node_depth,
+                    node_offset,
+                    num_objects,
+                },
+                InvocationArgs::UntypedRetype {
+                    untyped: next_untyped,
+                    object_type: next_object_type,
+                    size_bits: next_size_bits,
+                    root: next_root,
+                    node_index: next_node_index,
+                    node_depth: next_node_depth,
+                    node_offset: next_node_offset,
+                    num_objects: next_num_objects,
+                },
+            ) if object_type == next_object_type => InvocationArgs::UntypedRetype {
+                untyped: d(*untyped, *next_untyped),
+                // Untyped has an object type value of zero
+                object_type: ObjectType::Untyped,
+                size_bits: d(*size_bits, *next_size_bits),
+                root: d(*root, *next_root),
+                node_index: d(*node_index, *next_node_index),
+                node_depth: d(*node_depth, *next_node_depth),
+                node_offset: d(*node_offset, *next_node_offset),
+                num_objects: d(*num_objects, *next_num_objects),
+            },
+            (
+                InvocationArgs::TcbSetSchedParams {
+                    tcb,
+                    authority,
+                    mcp,
+                    priority,
+                    sched_context,
+                    fault_ep,
+                },
+                InvocationArgs::TcbSetSchedParams {
+                    tcb: next_tcb,
+                    authority: next_authority,
+                    mcp: next_mcp,
+                    priority: next_priority,
+                    sched_context: next_sched_context,
+                    fault_ep: next_fault_ep,
+                },
+            ) => InvocationArgs::TcbSetSchedParams {
+                tcb: d(*tcb, *next_tcb),
+                authority: d(*authority, *next_authority),
+                mcp: d(*mcp, *next_mcp),
+                priority: d(*priority, *next_priority),
+                sched_context: d(*sched_context, *next_sched_context),
+                fault_ep: d(*fault_ep, *next_fault_ep),
+            },
+            (
+                InvocationArgs::TcbSetSpace {
+                    tcb,
+                    fault_ep,
+                    cspace_root,
+                    cspace_root_data,
+                    vspace_root,
+                    vspace_root_data,
+                },
+                InvocationArgs::TcbSetSpace {
+                    tcb: next_tcb,
+                    fault_ep: next_fault_ep,
+                    cspace_root: next_cspace_root,
+                    cspace_root_data: next_cspace_root_data,
+                    vspace_root: next_vspace_root,
+                    vspace_root_data: next_vspace_root_data,
+                },
+            ) => InvocationArgs::TcbSetSpace {
+                tcb: d(*tcb, *next_tcb),
+                fault_ep: d(*fault_ep, *next_fault_ep),
+                cspace_root: d(*cspace_root, *next_cspace_root),
+                cspace_root_data: d(*cspace_root_data, *next_cspace_root_data),
+                vspace_root: d(*vspace_root, *next_vspace_root),
+                vspace_root_data: d(*vspace_root_data, *next_vspace_root_data),
+            },
+            (
+                InvocationArgs::TcbSetIpcBuffer {
+                    tcb,
+                    buffer,
+                    buffer_frame,
+                },
+                InvocationArgs::TcbSetIpcBuffer {
+                    tcb: next_tcb,
+                    buffer: next_buffer,
+                    buffer_frame: next_buffer_frame,
+                },
+            ) => InvocationArgs::TcbSetIpcBuffer {
+                tcb: d(*tcb, *next_tcb),
+                buffer: d(*buffer, *next_buffer),
+                buffer_frame: d(*buffer_frame, *next_buffer_frame),
+            },
+            (InvocationArgs::TcbResume { tcb }, InvocationArgs::TcbResume { tcb: next_tcb }) => {
+                InvocationArgs::TcbResume {
+                    tcb: d(*tcb, *next_tcb),
+                }
+            }
+            (
+                InvocationArgs::TcbBindNotification { tcb, notification },
+                InvocationArgs::TcbBindNotification {
+                    tcb: next_tcb,
+                    notification: next_notification,
+                },
+            ) => InvocationArgs::TcbBindNotification {
+                tcb: d(*tcb, *next_tcb),
+                notification: d(*notification, *next_notification),
+            },
+            (
+                InvocationArgs::AsidPoolAssign { asid_pool, vspace },
+                InvocationArgs::AsidPoolAssign {
+                    asid_pool: next_asid_pool,
+                    vspace: next_vspace,
+                },
+            ) => InvocationArgs::AsidPoolAssign {
+                asid_pool: d(*asid_pool, *next_asid_pool),
+                vspace: d(*vspace, *next_vspace),
+            },
+            (
+                InvocationArgs::IrqControlGetTrigger {
+                    irq_control,
+                    irq,
+                    trigger,
+                    dest_root,
+                    dest_index,
+                    dest_depth,
+                },
+                InvocationArgs::IrqControlGetTrigger {
+                    irq_control: next_irq_control,
+                    irq: next_irq,
+                    trigger: next_trigger,
+                    dest_root: next_dest_root,
+                    dest_index: next_dest_index,
+                    dest_depth: next_dest_depth,
+                },
+            ) if trigger == next_trigger => InvocationArgs::IrqControlGetTrigger {
+                irq_control: d(*irq_control, *next_irq_control),
+                irq: d(*irq, *next_irq),
+                // Level has a trigger value of zero
+                trigger: IrqTrigger::Level,
+                dest_root: d(*dest_root, *next_dest_root),
+                dest_index: d(*dest_index, *next_dest_index),
+                dest_depth: d(*dest_depth, *next_dest_depth),
+            },
+            (
+                InvocationArgs::IrqHandlerSetNotification {
+                    irq_handler,
+                    notification,
+                },
+                InvocationArgs::IrqHandlerSetNotification {
+                    irq_handler: next_irq_handler,
+                    notification: next_notification,
+                },
+            ) => InvocationArgs::IrqHandlerSetNotification {
+                irq_handler: d(*irq_handler, *next_irq_handler),
+                notification: d(*notification, *next_notification),
+            },
+            (
+                InvocationArgs::PageTableMap {
+                    page_table,
+                    vspace,
+                    vaddr,
+                    attr,
+                },
+                InvocationArgs::PageTableMap {
+                    page_table: next_page_table,
+                    vspace: next_vspace,
+                    vaddr: next_vaddr,
+                    attr: next_attr,
+                },
+            ) => InvocationArgs::PageTableMap {
+                page_table: d(*page_table, *next_page_table),
+                vspace: d(*vspace, *next_vspace),
+                vaddr: d(*vaddr, *next_vaddr),
+                attr: d(*attr, *next_attr),
+            },
+            (
+                InvocationArgs::PageMap {
+                    page,
+                    vspace,
+                    vaddr,
+                    rights,
+                    attr,
+                },
+                InvocationArgs::PageMap {
+                    page: next_page,
+                    vspace: next_vspace,
+                    vaddr: next_vaddr,
+                    rights: next_rights,
+                    attr: next_attr,
+                },
+            ) => InvocationArgs::PageMap {
+                page: d(*page, *next_page),
+                vspace: d(*vspace, *next_vspace),
+                vaddr: d(*vaddr, *next_vaddr),
+                rights: d(*rights, *next_rights),
+                attr: d(*attr, *next_attr),
+            },
+            (
+                InvocationArgs::CnodeCopy {
+                    cnode,
+                    dest_index,
+                    dest_depth,
+                    src_root,
+                    src_obj,
+                    src_depth,
+                    rights,
+                },
+                InvocationArgs::CnodeCopy {
+                    cnode: next_cnode,
+                    dest_index: next_dest_index,
+                    dest_depth: next_dest_depth,
+                    src_root: next_src_root,
+                    src_obj: next_src_obj,
+                    src_depth: next_src_depth,
+                    rights: next_rights,
+                },
+            ) => InvocationArgs::CnodeCopy {
+                cnode: d(*cnode, *next_cnode),
+                dest_index: d(*dest_index, *next_dest_index),
+                dest_depth: d(*dest_depth, *next_dest_depth),
+                src_root: d(*src_root, *next_src_root),
+                src_obj: d(*src_obj, *next_src_obj),
+                src_depth: d(*src_depth, *next_src_depth),
+                rights: d(*rights, *next_rights),
+            },
+            (
+                InvocationArgs::CnodeMint {
+                    cnode,
+                    dest_index,
+                    dest_depth,
+                    src_root,
+                    src_obj,
+                    src_depth,
+                    rights,
+                    badge,
+                },
+                InvocationArgs::CnodeMint {
+                    cnode: next_cnode,
+                    dest_index: next_dest_index,
+                    dest_depth: next_dest_depth,
+                    src_root: next_src_root,
+                    src_obj: next_src_obj,
+                    src_depth: next_src_depth,
+                    rights: next_rights,
+                    badge: next_badge,
+                },
+            ) => InvocationArgs::CnodeMint {
+                cnode: d(*cnode, *next_cnode),
+                dest_index: d(*dest_index, *next_dest_index),
+                dest_depth: d(*dest_depth, *next_dest_depth),
+                src_root: d(*src_root, *next_src_root),
+                src_obj: d(*src_obj, *next_src_obj),
+                src_depth: d(*src_depth, *next_src_depth),
+                rights: d(*rights, *next_rights),
+                badge: d(*badge, *next_badge),
+            },
+            (
+                InvocationArgs::SchedControlConfigureFlags {
+                    sched_control,
+                    sched_context,
+                    budget,
+                    period,
+                    extra_refills,
+                    badge,
+                    flags,
+                },
+                InvocationArgs::SchedControlConfigureFlags {
+                    sched_control: next_sched_control,
+                    sched_context: next_sched_context,
+                    budget: next_budget,
+                    period: next_period,
+                    extra_refills: next_extra_refills,
+                    badge: next_badge,
+                    flags: next_flags,
+                },
+            ) => InvocationArgs::SchedControlConfigureFlags {
+                sched_control: d(*sched_control, *next_sched_control),
+                sched_context: d(*sched_context, *next_sched_context),
+                budget: d(*budget, *next_budget),
+                period: d(*period, *next_period),
+                extra_refills: d(*extra_refills, *next_extra_refills),
+                badge: d(*badge, *next_badge),
+                flags: d(*flags, *next_flags),
+            },
+            (
+                InvocationArgs::ArmVcpuSetTcb { vcpu, tcb },
+                InvocationArgs::ArmVcpuSetTcb {
+                    vcpu: next_vcpu,
+                    tcb: next_tcb,
+                },
+            ) => InvocationArgs::ArmVcpuSetTcb {
+                vcpu: d(*vcpu, *next_vcpu),
+                tcb: d(*tcb, *next_tcb),
+            },
+            // Writing registers packs several arguments into a single word,
+            // so it cannot be repeated.
+            _ => return None,
+        };
+
+        Some(step)
+    }
+
+    /// Construct the arguments of an invocation from its label (as named in
+    /// the kernel's label mapping) and the raw words given by 'get_args'.
+    fn decode(
+        config: &Config,
+        label: &str,
+        service: u64,
+        args: &[u64],
+        extra_caps: &[u64],
+    ) -> Result<InvocationArgs, String> {
+        let expect = |num_args: usize, num_extra_caps: usize| {
+            if args.len() != num_args || extra_caps.len() != num_extra_caps {
+                Err(format!(
+                    "{} expects {} arguments and {} extra caps, found {} and {}",
+                    label,
+                    num_args,
+                    num_extra_caps,
+                    args.len(),
+                    extra_caps.len()
+                ))
+            } else {
+                Ok(())
+            }
+        };
+
+        let invocation_args = match (label, config.arch) {
+            ("UntypedRetype", _) => {
+                expect(6, 1)?;
+                let object_type = match ObjectType::from_value(config, args[0]) {
+                    Some(object_type) => object_type,
+                    None => return Err(format!("unknown object type {}", args[0])),
+                };
+                InvocationArgs::UntypedRetype {
+                    untyped: service,
+                    object_type,
+                    size_bits: args[1],
+                    root: extra_caps[0],
+                    node_index: args[2],
+                    node_depth: args[3],
+                    node_offset: args[4],
+                    num_objects: args[5],
+                }
+            }
+            ("TCBSetSchedParams", _) => {
+                expect(2, 3)?;
+                InvocationArgs::TcbSetSchedParams {
+                    tcb: service,
+                    authority: extra_caps[0],
+                    mcp: args[0],
+                    priority: args[1],
+                    sched_context: extra_caps[1],
+                    fault_ep: extra_caps[2],
+                }
+            }
+            ("TCBSetSpace", _) => {
+                expect(2, 3)?;
+                InvocationArgs::TcbSetSpace {
+                    tcb: service,
+                    fault_ep: extra_caps[0],
+                    cspace_root: extra_caps[1],
+                    cspace_root_data: args[0],
+                    vspace_root: extra_caps[2],
+                    vspace_root_data: args[1],
+                }
+            }
+            ("TCBSetIPCBuffer", _) => {
+                expect(1, 1)?;
+                InvocationArgs::TcbSetIpcBuffer {
+                    tcb: service,
+                    buffer: args[0],
+                    buffer_frame: extra_caps[0],
+                }
+            }
+            ("TCBResume", _) => {
+                expect(0, 0)?;
+                InvocationArgs::TcbResume { tcb: service }
+            }
+            ("TCBWriteRegisters", arch) => {
+                if args.len() < 2 || !extra_caps.is_empty() {
+                    return Err(format!("{} has malformed arguments", label));
+                }
+                let names = match arch {
+                    Arch::Aarch64 => Aarch64Regs::default().field_names(),
+                    Arch::Riscv64 => Riscv64Regs::default().field_names(),
+                };
+                let values = &args[2..];
+                if values.len() > names.len() {
+                    return Err(format!(
+                        "{} has {} registers, at most {} are supported",
+                        label,
+                        values.len(),
+                        names.len()
+                    ));
+                }
+                InvocationArgs::TcbWriteRegisters {
+                    tcb: service,
+                    resume: args[0] & 1 == 1,
+                    arch_flags: (args[0] >> 8) as u8,
+                    count: args[1],
+                    regs: zip(names, values)
+                        .map(|((name, _), value)| (name, *value))
+                        .collect(),
+                }
+            }
+            ("TCBBindNotification", _) => {
+                expect(0, 1)?;
+                InvocationArgs::TcbBindNotification {
+                    tcb: service,
+                    notification: extra_caps[0],
+                }
+            }
+            ("ARMASIDPoolAssign", Arch::Aarch64) | ("RISCVASIDPoolAssign", Arch::Riscv64) => {
+                expect(0, 1)?;
+                InvocationArgs::AsidPoolAssign {
+                    asid_pool: service,
+                    vspace: extra_caps[0],
+                }
+            }
+            ("ARMIRQIssueIRQHandlerTrigger", Arch::Aarch64)
+            | ("RISCVIRQIssueIRQHandlerTrigger", Arch::Riscv64) => {
+                expect(4, 1)?;
+                let trigger = match args[1] {
+                    0 => IrqTrigger::Level,
+                    1 => IrqTrigger::Edge,
+                    trigger => return Err(format!("unknown IRQ trigger {}", trigger)),
+                };
+                InvocationArgs::IrqControlGetTrigger {
+                    irq_control: service,
+                    irq: args[0],
+                    trigger,
+                    dest_root: extra_caps[0],
+                    dest_index: args[2],
+                    dest_depth: args[3],
+                }
+            }
+            ("IRQSetIRQHandler", _) => {
+                expect(0, 1)?;
+                InvocationArgs::IrqHandlerSetNotification {
+                    irq_handler: service,
+                    notification: extra_caps[0],
+                }
+            }
+            ("ARMPageTableMap", Arch::Aarch64) | ("RISCVPageTableMap", Arch::Riscv64) => {
+                expect(2, 1)?;
+                InvocationArgs::PageTableMap {
+                    page_table: service,
+                    vspace: extra_caps[0],
+                    vaddr: args[0],
+                    attr: args[1],
+                }
+            }
+            ("ARMPageMap", Arch::Aarch64) | ("RISCVPageMap", Arch::Riscv64) => {
+                expect(3, 1)?;
+                InvocationArgs::PageMap {
+                    page: service,
+                    vspace: extra_caps[0],
+                    vaddr: args[0],
+                    rights: args[1],
+                    attr: args[2],
+                }
+            }
+            ("CNodeCopy", _) => {
+                expect(5, 1)?;
+                InvocationArgs::CnodeCopy {
+                    cnode: service,
+                    dest_index: args[0],
+                    dest_depth: args[1],
+                    src_root: extra_caps[0],
+                    src_obj: args[2],
+                    src_depth: args[3],
+                    rights: args[4],
+                }
+            }
+            ("CNodeMint", _) => {
+                expect(6, 1)?;
+                InvocationArgs::CnodeMint {
+                    cnode: service,
+                    dest_index: args[0],
+                    dest_depth: args[1],
+                    src_root: extra_caps[0],
+                    src_obj: args[2],
+                    src_depth: args[3],
+                    rights: args[4],
+                    badge: args[5],
+                }
+            }
+            ("SchedControlConfigureFlags", _) => {
+                expect(5, 1)?;
+                InvocationArgs::SchedControlConfigureFlags {
+                    sched_control: service,
+                    sched_context: extra_caps[0],
+                    budget: args[0],
+                    period: args[1],
+                    extra_refills: args[2],
+                    badge: args[3],
+                    flags: args[4],
+                }
+            }
+            ("ARMVCPUSetTCB", Arch::Aarch64) => {
+                expect(0, 1)?;
+                InvocationArgs::ArmVcpuSetTcb {
+                    vcpu: service,
+                    tcb: extra_caps[0],
+                }
+            }
+            _ => return Err(format!("unsupported invocation '{}'", label)),
+        };
+
+        Ok(invocation_args)
+    }
+
     fn to_label(&self, config: &Config) -> InvocationLabel {
         match self {
             InvocationArgs::UntypedRetype { .. } => InvocationLabel::UntypedRetype,
@@ -1401,3 +2171,336 @@ pub enum InvocationArgs {
         tcb: u64,
     },
 }
+
+/// Fixtures shared by the unit tests of the different modules
+#[cfg(test)]
+pub(crate) mod test_util {
+    use super::*;
+    use serde_json::json;
+
+    pub(crate) fn config() -> Config {
+        Config {
+            arch: Arch::Aarch64,
+            word_size: 64,
+            minimum_page_size: 4096,
+            paddr_user_device_top: 1 << 40,
+            kernel_frame_size: 1 << 12,
+            init_cnode_bits: 12,
+            cap_address_bits: 64,
+            fan_out_limit: 256,
+            hypervisor: true,
+            benchmark: false,
+            fpu: true,
+            arm_pa_size_bits: Some(40),
+            arm_smc: None,
+            riscv_pt_levels: None,
+            invocations_labels: json!({
+                "UntypedRetype": 1,
+                "TCBSetSchedParams": 2,
+                "TCBSetSpace": 3,
+                "TCBSetIPCBuffer": 4,
+                "TCBResume": 5,
+                "TCBWriteRegisters": 6,
+                "TCBBindNotification": 7,
+                "ARMASIDPoolAssign": 8,
+                "ARMIRQIssueIRQHandlerTrigger": 9,
+                "IRQSetIRQHandler": 10,
+                "ARMPageTableMap": 11,
+                "ARMPageMap": 12,
+                "CNodeCopy": 13,
+                "CNodeMint": 14,
+                "SchedControlConfigureFlags": 15,
+                "ARMVCPUSetTCB": 16,
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::test_util::config;
+    use super::*;
+
+    fn set_notification(config: &Config, irq_handler: u64, notification: u64) -> Invocation {
+        Invocation::new(
+            config,
+            InvocationArgs::IrqHandlerSetNotification {
+                irq_handler,
+                notification,
+            },
+        )
+    }
+
+    fn raw_data(config: &Config, invocations: &[Invocation]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for invocation in invocations {
+            invocation.add_raw_invocation(config, &mut data);
+        }
+        data
+    }
+
+    #[test]
+    fn test_batch_progression() {
+        let config = config();
+        let invocations = vec![
+            set_notification(&config, 10, 20),
+            set_notification(&config, 11, 22),
+            set_notification(&config, 12, 24),
+            // Breaks the progression
+            set_notification(&config, 14, 26),
+            set_notification(&config, 15, 26),
+        ];
+        let batched = Invocation::batch(&config, invocations);
+
+        assert_eq!(batched.len(), 2);
+        assert_eq!(batched[0].count(), 3);
+        assert_eq!(batched[1].count(), 2);
+
+        let mut expected = set_notification(&config, 10, 20);
+        expected.repeat(
+            3,
+            InvocationArgs::IrqHandlerSetNotification {
+                irq_handler: 1,
+                notification: 2,
+            },
+        );
+        assert_eq!(
+            raw_data(&config, &batched[..1]),
+            raw_data(&config, &[expected])
+        );
+    }
+
+    #[test]
+    fn test_batch_with_repeat() {
+        let config = config();
+        let page_map = |page, vaddr| {
+            Invocation::new(
+                &config,
+                InvocationArgs::PageMap {
+                    page,
+                    vspace: 5,
+                    vaddr,
+                    rights: 3,
+                    attr: 0,
+                },
+            )
+        };
+        let step = InvocationArgs::PageMap {
+            page: 1,
+            vspace: 0,
+            vaddr: 0x1000,
+            rights: 0,
+            attr: 0,
+        };
+        let mut first = page_map(100, 0x10000);
+        first.repeat(4, step.clone());
+        let mut second = page_map(104, 0x14000);
+        second.repeat(2, step);
+        let third = page_map(106, 0x16000);
+
+        let batched = Invocation::batch(&config, vec![first, second, third]);
+        assert_eq!(batched.len(), 1);
+        assert_eq!(batched[0].count(), 7);
+    }
+
+    #[test]
+    fn test_batch_different_labels() {
+        let config = config();
+        let invocations = vec![
+            set_notification(&config, 10, 20),
+            Invocation::new(
+                &config,
+                InvocationArgs::PageMap {
+                    page: 11,
+                    vspace: 20,
+                    vaddr: 0,
+                    rights: 0,
+                    attr: 0,
+                },
+            ),
+            set_notification(&config, 12, 20),
+        ];
+        assert_eq!(Invocation::batch(&config, invocations).len(), 3);
+    }
+
+    #[test]
+    fn test_raw_size() {
+        let config = config();
+        let mut invocation = set_notification(&config, 10, 20);
+        invocation.repeat(
+            3,
+            InvocationArgs::IrqHandlerSetNotification {
+                irq_handler: 1,
+                notification: 0,
+            },
+        );
+        let invocations = [invocation, set_notification(&config, 10, 20)];
+        for invocation in &invocations {
+            assert_eq!(
+                invocation.raw_size(&config),
+                raw_data(&config, std::slice::from_ref(invocation)).len() as u64
+            );
+        }
+    }
+
+    #[test]
+    fn test_decode_round_trip() {
+        let config = config();
+        let mut retype = Invocation::new(
+            &config,
+            InvocationArgs::UntypedRetype {
+                untyped: 10,
+                object_type: ObjectType::SmallPage,
+                size_bits: 0,
+                root: 2,
+                node_index: 0,
+                node_depth: 0,
+                node_offset: 100,
+                num_objects: 4,
+            },
+        );
+        retype.repeat(
+            3,
+            InvocationArgs::UntypedRetype {
+                untyped: 1,
+                object_type: ObjectType::Untyped,
+                size_bits: 0,
+                root: 0,
+                node_index: 0,
+                node_depth: 0,
+                node_offset: 4,
+                num_objects: 0,
+            },
+        );
+        let all_args = vec![
+            InvocationArgs::TcbSetSchedParams {
+                tcb: 1,
+                authority: 2,
+                mcp: 3,
+                priority: 4,
+                sched_context: 5,
+                fault_ep: 6,
+            },
+            InvocationArgs::TcbSetSpace {
+                tcb: 1,
+                fault_ep: 2,
+                cspace_root: 3,
+                cspace_root_data: 4,
+                vspace_root: 5,
+                vspace_root_data: 6,
+            },
+            InvocationArgs::TcbSetIpcBuffer {
+                tcb: 1,
+                buffer: 0x1000,
+                buffer_frame: 3,
+            },
+            InvocationArgs::TcbResume { tcb: 1 },
+            InvocationArgs::TcbWriteRegisters {
+                tcb: 1,
+                resume: true,
+                arch_flags: 2,
+                count: Aarch64Regs::LEN as u64,
+                regs: Aarch64Regs {
+                    pc: 0x200000,
+                    sp: 0x10000000,
+                    ..Default::default()
+                }
+                .field_names(),
+            },
+            InvocationArgs::TcbBindNotification {
+                tcb: 1,
+                notification: 2,
+            },
+            InvocationArgs::AsidPoolAssign {
+                asid_pool: 1,
+                vspace: 2,
+            },
+            InvocationArgs::IrqControlGetTrigger {
+                irq_control: 1,
+                irq: 33,
+                trigger: IrqTrigger::Edge,
+                dest_root: 2,
+                dest_index: 3,
+                dest_depth: 64,
+            },
+            InvocationArgs::IrqHandlerSetNotification {
+                irq_handler: 1,
+                notification: 2,
+            },
+            InvocationArgs::PageTableMap {
+                page_table: 1,
+                vspace: 2,
+                vaddr: 0x200000,
+                attr: 3,
+            },
+            InvocationArgs::PageMap {
+                page: 1,
+                vspace: 2,
+                vaddr: 0x1000,
+                rights: 3,
+                attr: 4,
+            },
+            InvocationArgs::CnodeCopy {
+                cnode: 1,
+                dest_index: 2,
+                dest_depth: 3,
+                src_root: 4,
+                src_obj: 5,
+                src_depth: 6,
+                rights: 7,
+            },
+            InvocationArgs::CnodeMint {
+                cnode: 1,
+                dest_index: 2,
+                dest_depth: 3,
+                src_root: 4,
+                src_obj: 5,
+                src_depth: 6,
+                rights: 7,
+                badge: 8,
+            },
+            InvocationArgs::SchedControlConfigureFlags {
+                sched_control: 1,
+                sched_context: 2,
+                budget: 3,
+                period: 4,
+                extra_refills: 5,
+                badge: 6,
+                flags: 7,
+            },
+            InvocationArgs::ArmVcpuSetTcb { vcpu: 1, tcb: 2 },
+        ];
+        let mut invocations = vec![retype];
+        for args in all_args {
+            invocations.push(Invocation::new(&config, args));
+        }
+
+        let data = raw_data(&config, &invocations);
+        let decoded = Invocation::decode(&config, &data, invocations.len()).unwrap();
+        assert_eq!(decoded.len(), invocations.len());
+        assert_eq!(decoded[0].count(), 3);
+        assert_eq!(raw_data(&config, &decoded), data);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let config = config();
+        let data = raw_data(&config, &[set_notification(&config, 1, 2)]);
+
+        assert_eq!(
+            Invocation::decode(&config, &data, 2).err().unwrap(),
+            "invocation 1 is missing"
+        );
+        assert_eq!(
+            Invocation::decode(&config, &data[..16], 1).err().unwrap(),
+            "invocation 0 is truncated"
+        );
+
+        let mut unknown = data.clone();
+        unknown[2] = 0xff;
+        assert!(Invocation::decode(&config, &unknown, 1)
+            .err()
+            .unwrap()
+            .starts_with("invocation 0 has unknown label"));
+    }
+}
@@ -0,0 +1,1438 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+// A model of the seL4 kernel objects that the monitor creates at boot.
+//
+// The simulator starts from the state the kernel hands to the initial task,
+// as described by the emulated boot info, and performs the bootstrap and
+// system invocations in order. Any invocation that the kernel would reject
+// is reported, so that a bad sequence of invocations is caught when building
+// the image rather than by the monitor failing on hardware.
+//
+// Only the kernel behaviour that the tool relies on is modelled. Checks that
+// depend on platform details the tool does not know about (such as the number
+// of IRQs) or on the kernel's internal data structures (such as the number of
+// refills that fit in a scheduling context) are not performed.
+
+use crate::sel4::{
+    Arch, BootInfo, Config, Invocation, InvocationArgs, IrqTrigger, ObjectType, Rights,
+};
+use crate::util::{mask, round_up};
+use crate::MemoryRegion;
+use std::collections::{HashMap, HashSet};
+
+// Slots of the caps the kernel gives to the initial task, see
+// 'seL4_RootCNodeCapSlots'.
+const INIT_TCB_CAP: u64 = 1;
+const INIT_CNODE_CAP: u64 = 2;
+const INIT_VSPACE_CAP: u64 = 3;
+const IRQ_CONTROL_CAP: u64 = 4;
+const ASID_CONTROL_CAP: u64 = 5;
+const INIT_ASID_POOL_CAP: u64 = 6;
+const BOOT_INFO_FRAME_CAP: u64 = 9;
+const INIT_IPC_BUFFER_CAP: u64 = 10;
+const DOMAIN_CAP: u64 = 11;
+const INIT_SC_CAP: u64 = 14;
+const SMC_CAP: u64 = 15;
+
+const SLOT_BITS: u64 = 5; // seL4_SlotBits
+const MAX_PRIO: u64 = 255; // seL4_MaxPrio
+const IPC_BUFFER_SIZE_BITS: u64 = 10; // seL4_IPCBufferSizeBits
+const ASID_POOL_SIZE: u64 = 1 << 9; // BIT(asidLowBits)
+const MIN_UNTYPED_BITS: u64 = 4; // seL4_MinUntypedBits
+const MIN_SCHED_CONTEXT_BITS: u64 = 7; // seL4_MinSchedContextBits
+pub(crate) const PT_INDEX_BITS: u64 = 9;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum CapType {
+    Null,
+    Object(ObjectType),
+    IrqControl,
+    IrqHandler(u64),
+    AsidControl,
+    AsidPool,
+    SchedControl,
+    Domain,
+    Smc,
+}
+
+#[derive(Copy, Clone)]
+pub(crate) struct Cap {
+    pub(crate) cap_type: CapType,
+    /// Index of the object the cap refers to in 'Simulator::objects'
+    pub(crate) object: usize,
+    pub(crate) rights: u64,
+    pub(crate) badge: u64,
+    /// Only used by CNode caps
+    pub(crate) guard: u64,
+    pub(crate) guard_size: u64,
+    /// Whether a frame or page table cap is mapped, or a VSpace cap
+    /// has been assigned an ASID.
+    pub(crate) mapped: bool,
+}
+
+impl Cap {
+    const NULL: Cap = Cap {
+        cap_type: CapType::Null,
+        object: 0,
+        rights: 0,
+        badge: 0,
+        guard: 0,
+        guard_size: 0,
+        mapped: false,
+    };
+
+    fn new(cap_type: CapType, object: usize) -> Cap {
+        Cap {
+            cap_type,
+            object,
+            rights: Rights::All as u64,
+            ..Cap::NULL
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self.cap_type {
+            CapType::Null => "a null cap".to_string(),
+            CapType::Object(object_type) => format!("a {:?} cap", object_type),
+            cap_type => format!("an {:?} cap", cap_type),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Tcb {
+    pub(crate) mcp: u64,
+    pub(crate) priority: u64,
+    pub(crate) sched_context: Option<usize>,
+    pub(crate) bound_notification: Option<usize>,
+    pub(crate) fault_ep: Option<Cap>,
+    pub(crate) cspace: Option<Cap>,
+    pub(crate) vspace: Option<Cap>,
+    /// Virtual address and frame cap of the IPC buffer
+    pub(crate) ipc_buffer: Option<(u64, Cap)>,
+    pub(crate) registers: Vec<(&'static str, u64)>,
+}
+
+#[derive(Copy, Clone)]
+pub(crate) struct Mapping {
+    pub(crate) frame: Cap,
+    pub(crate) rights: u64,
+    pub(crate) attr: u64,
+}
+
+pub(crate) enum Object {
+    Untyped {
+        region: MemoryRegion,
+        is_device: bool,
+        watermark: u64,
+        has_children: bool,
+    },
+    CNode {
+        size_bits: u64,
+        slots: HashMap<u64, Cap>,
+    },
+    Tcb(Box<Tcb>),
+    Notification {
+        bound: bool,
+    },
+    SchedContext {
+        tcb: Option<usize>,
+        budget: u64,
+        period: u64,
+        badge: u64,
+    },
+    /// A VSpace, or on RISC-V any page table since a page table can be
+    /// used as the root of a VSpace. Entries are keyed by the number of
+    /// bits of virtual address they cover and the index of the entry
+    /// at that level.
+    Paging {
+        page_tables: HashMap<(u64, u64), usize>,
+        pages: HashMap<(u64, u64), Mapping>,
+    },
+    Frame {
+        is_device: bool,
+    },
+    AsidPool {
+        used: u64,
+    },
+    IrqHandler {
+        irq: u64,
+        trigger: IrqTrigger,
+        notification: Option<Cap>,
+    },
+    Other,
+}
+
+impl Object {
+    fn sched_context() -> Object {
+        Object::SchedContext {
+            tcb: None,
+            budget: 0,
+            period: 0,
+            badge: 0,
+        }
+    }
+}
+
+type Slot = (usize, u64);
+
+pub struct Simulator<'a> {
+    config: &'a Config,
+    pub(crate) objects: Vec<Object>,
+    /// All invocations are performed by the monitor, which is the initial task
+    monitor_tcb: usize,
+    cspace_root: Cap,
+    irqs: HashSet<u64>,
+}
+
+impl<'a> Simulator<'a> {
+    /// Create the objects and caps that the kernel gives to the initial task.
+    pub fn new(
+        config: &'a Config,
+        boot_info: &BootInfo,
+        initial_task_virt_region: MemoryRegion,
+    ) -> Simulator<'a> {
+        let mut simulator = Simulator {
+            config,
+            objects: Vec::new(),
+            monitor_tcb: 0,
+            cspace_root: Cap::NULL,
+            irqs: HashSet::new(),
+        };
+
+        let cnode = simulator.add_object(Object::CNode {
+            size_bits: config.init_cnode_bits,
+            slots: HashMap::new(),
+        });
+        let mut cnode_cap = Cap::new(CapType::Object(ObjectType::CNode), cnode);
+        cnode_cap.guard_size = config.cap_address_bits - config.init_cnode_bits;
+        simulator.cspace_root = cnode_cap;
+
+        let sched_context = simulator.add_object(Object::sched_context());
+        simulator.monitor_tcb = simulator.add_object(Object::Tcb(Box::new(Tcb {
+            mcp: MAX_PRIO,
+            priority: MAX_PRIO,
+            sched_context: Some(sched_context),
+            ..Default::default()
+        })));
+        if let Object::SchedContext { tcb, .. } = &mut simulator.objects[sched_context] {
+            *tcb = Some(simulator.monitor_tcb);
+        }
+
+        // The kernel creates the page tables and maps the pages of the
+        // initial task's image.
+        let mut page_tables = HashMap::new();
+        let mut pages = HashMap::new();
+        let region = initial_task_virt_region;
+        let mut bits = simulator.root_bits();
+        while bits > config.minimum_page_size.ilog2() as u64 {
+            for index in (region.base >> bits)..=((region.end - 1) >> bits) {
+                page_tables.insert((bits, index), simulator.add_object(Object::Other));
+            }
+            bits -= PT_INDEX_BITS;
+        }
+        for index in (region.base >> bits)..=((region.end - 1) >> bits) {
+            let frame = simulator.add_object(Object::Frame { is_device: false });
+            let mapping = Mapping {
+                frame: Cap::new(CapType::Object(ObjectType::SmallPage), frame),
+                rights: Rights::All as u64,
+                attr: 0,
+            };
+            pages.insert((bits, index), mapping);
+        }
+        let vspace = simulator.add_object(Object::Paging { page_tables, pages });
+        let mut vspace_cap = Cap::new(CapType::Object(simulator.vspace_type()), vspace);
+        vspace_cap.mapped = true;
+
+        let asid_pool = simulator.add_object(Object::AsidPool { used: 1 });
+
+        let mut caps = vec![
+            (
+                INIT_TCB_CAP,
+                Cap::new(CapType::Object(ObjectType::Tcb), simulator.monitor_tcb),
+            ),
+            (INIT_CNODE_CAP, cnode_cap),
+            (INIT_VSPACE_CAP, vspace_cap),
+            (INIT_ASID_POOL_CAP, Cap::new(CapType::AsidPool, asid_pool)),
+            (
+                INIT_SC_CAP,
+                Cap::new(CapType::Object(ObjectType::SchedContext), sched_context),
+            ),
+        ];
+        let mut others = vec![
+            (IRQ_CONTROL_CAP, CapType::IrqControl),
+            (ASID_CONTROL_CAP, CapType::AsidControl),
+            (DOMAIN_CAP, CapType::Domain),
+            (boot_info.sched_control_cap, CapType::SchedControl),
+        ];
+        if config.arm_smc == Some(true) {
+            others.push((SMC_CAP, CapType::Smc));
+        }
+        for (slot, cap_type) in others {
+            let object = simulator.add_object(Object::Other);
+            caps.push((slot, Cap::new(cap_type, object)));
+        }
+
+        let paging_caps =
+            boot_info.fixed_cap_count..boot_info.fixed_cap_count + boot_info.paging_cap_count;
+        let page_caps = boot_info.sched_control_cap + 1
+            ..boot_info.sched_control_cap + 1 + boot_info.page_cap_count;
+        let frames = [BOOT_INFO_FRAME_CAP, INIT_IPC_BUFFER_CAP]
+            .into_iter()
+            .map(|slot| (slot, ObjectType::SmallPage))
+            .chain(paging_caps.map(|slot| (slot, ObjectType::PageTable)))
+            .chain(page_caps.map(|slot| (slot, ObjectType::SmallPage)));
+        for (slot, object_type) in frames {
+            let object = simulator.add_object(match object_type {
+                ObjectType::SmallPage => Object::Frame { is_device: false },
+                _ => Object::Other,
+            });
+            let mut cap = Cap::new(CapType::Object(object_type), object);
+            cap.mapped = true;
+            caps.push((slot, cap));
+        }
+
+        for ut in &boot_info.untyped_objects {
+            let object = simulator.add_object(Object::Untyped {
+                region: ut.region,
+                is_device: ut.is_device,
+                watermark: 0,
+                has_children: false,
+            });
+            caps.push((
+                ut.cap,
+                Cap::new(CapType::Object(ObjectType::Untyped), object),
+            ));
+        }
+
+        for (slot, cap) in caps {
+            simulator.set_cap((cnode, slot), cap);
+        }
+
+        simulator
+    }
+
+    /// Perform the invocations in order, stopping at the first invocation
+    /// that the kernel would reject.
+    pub fn run(&mut self, name: &str, invocations: &[Invocation]) -> Result<(), String> {
+        for (i, invocation) in invocations.iter().enumerate() {
+            for (j, args) in invocation.expand(self.config).iter().enumerate() {
+                self.execute(args).map_err(|err| {
+                    let iteration = match invocation.count() {
+                        1 => String::new(),
+                        _ => format!(", iteration {}", j),
+                    };
+                    format!(
+                        "{} invocation {} ({}{}) would fail: {}",
+                        name,
+                        i,
+                        invocation.label_name(),
+                        iteration,
+                        err
+                    )
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Perform a single invocation on behalf of the monitor.
+    pub fn execute(&mut self, args: &InvocationArgs) -> Result<(), String> {
+        match *args {
+            InvocationArgs::UntypedRetype {
+                untyped,
+                object_type,
+                size_bits,
+                root,
+                node_index,
+                node_depth,
+                node_offset,
+                num_objects,
+            } => self.untyped_retype(
+                untyped,
+                object_type,
+                size_bits,
+                root,
+                node_index,
+                node_depth,
+                node_offset,
+                num_objects,
+            ),
+            InvocationArgs::TcbSetSchedParams {
+                tcb,
+                authority,
+                mcp,
+                priority,
+                sched_context,
+                fault_ep,
+            } => self.tcb_set_sched_params(tcb, authority, mcp, priority, sched_context, fault_ep),
+            InvocationArgs::TcbSetSpace {
+                tcb,
+                fault_ep,
+                cspace_root,
+                cspace_root_data,
+                vspace_root,
+                vspace_root_data: _,
+            } => self.tcb_set_space(tcb, fault_ep, cspace_root, cspace_root_data, vspace_root),
+            InvocationArgs::TcbSetIpcBuffer {
+                tcb,
+                buffer,
+                buffer_frame,
+            } => {
+                let tcb = self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+                if buffer & mask(IPC_BUFFER_SIZE_BITS) != 0 {
+                    return Err(format!(
+                        "IPC buffer address 0x{:x} is not aligned to 0x{:x}",
+                        buffer,
+                        1 << IPC_BUFFER_SIZE_BITS
+                    ));
+                }
+                if buffer != 0 {
+                    let frame = self.lookup_cap(buffer_frame)?;
+                    if self.frame_size_bits(&frame).is_none() {
+                        return Err(format!(
+                            "IPC buffer is {} rather than a frame cap",
+                            frame.describe()
+                        ));
+                    }
+                    if let Object::Frame { is_device: true } = self.objects[frame.object] {
+                        return Err("IPC buffer is a device frame".to_string());
+                    }
+                    self.tcb_mut(tcb.object).ipc_buffer = Some((buffer, frame));
+                }
+                Ok(())
+            }
+            InvocationArgs::TcbResume { tcb } => {
+                self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+                Ok(())
+            }
+            InvocationArgs::TcbWriteRegisters {
+                tcb,
+                count,
+                ref regs,
+                ..
+            } => {
+                let tcb = self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+                if tcb.object == self.monitor_tcb {
+                    return Err("cannot write the registers of the monitor".to_string());
+                }
+                if count > regs.len() as u64 {
+                    return Err(format!(
+                        "{} registers are written but only {} are given",
+                        count,
+                        regs.len()
+                    ));
+                }
+                self.tcb_mut(tcb.object).registers = regs[..count as usize].to_vec();
+                Ok(())
+            }
+            InvocationArgs::TcbBindNotification { tcb, notification } => {
+                let tcb = self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+                let ntfn = self.expect(notification, CapType::Object(ObjectType::Notification))?;
+                if ntfn.rights & Rights::Read as u64 == 0 {
+                    return Err("notification cap cannot receive".to_string());
+                }
+                if self.tcb_mut(tcb.object).bound_notification.is_some() {
+                    return Err("TCB is already bound to a notification".to_string());
+                }
+                if let Object::Notification { bound: true } = self.objects[ntfn.object] {
+                    return Err("notification is already bound to a TCB".to_string());
+                }
+                self.tcb_mut(tcb.object).bound_notification = Some(ntfn.object);
+                self.objects[ntfn.object] = Object::Notification { bound: true };
+                Ok(())
+            }
+            InvocationArgs::AsidPoolAssign { asid_pool, vspace } => {
+                let pool = self.expect(asid_pool, CapType::AsidPool)?;
+                let slot = self.lookup(vspace)?;
+                let mut cap = self.cap(slot);
+                if cap.cap_type != CapType::Object(self.vspace_type()) {
+                    return Err(format!("{} is not a VSpace cap", cap.describe()));
+                }
+                if cap.mapped {
+                    return Err("VSpace already has an ASID".to_string());
+                }
+                let Object::AsidPool { used } = &mut self.objects[pool.object] else {
+                    unreachable!()
+                };
+                if *used >= ASID_POOL_SIZE {
+                    return Err("ASID pool is full".to_string());
+                }
+                *used += 1;
+                cap.mapped = true;
+                self.set_cap(slot, cap);
+                Ok(())
+            }
+            InvocationArgs::IrqControlGetTrigger {
+                irq_control,
+                irq,
+                trigger,
+                dest_root,
+                dest_index,
+                dest_depth,
+            } => {
+                self.expect(irq_control, CapType::IrqControl)?;
+                if self.irqs.contains(&irq) {
+                    return Err(format!("IRQ {} already has a handler", irq));
+                }
+                let root = self.expect(dest_root, CapType::Object(ObjectType::CNode))?;
+                let dest = self.lookup_empty(&root, dest_index, dest_depth)?;
+                let object = self.add_object(Object::IrqHandler {
+                    irq,
+                    trigger,
+                    notification: None,
+                });
+                self.set_cap(dest, Cap::new(CapType::IrqHandler(irq), object));
+                self.irqs.insert(irq);
+                Ok(())
+            }
+            InvocationArgs::IrqHandlerSetNotification {
+                irq_handler,
+                notification,
+            } => {
+                let handler = self.lookup_cap(irq_handler)?;
+                if !matches!(handler.cap_type, CapType::IrqHandler(_)) {
+                    return Err(format!("{} is not an IRQ handler cap", handler.describe()));
+                }
+                let ntfn = self.expect(notification, CapType::Object(ObjectType::Notification))?;
+                if ntfn.rights & Rights::Write as u64 == 0 {
+                    return Err("notification cap cannot send".to_string());
+                }
+                if let Object::IrqHandler { notification, .. } = &mut self.objects[handler.object] {
+                    *notification = Some(ntfn);
+                }
+                Ok(())
+            }
+            InvocationArgs::PageTableMap {
+                page_table,
+                vspace,
+                vaddr,
+                ..
+            } => self.page_table_map(page_table, vspace, vaddr),
+            InvocationArgs::PageMap {
+                page,
+                vspace,
+                vaddr,
+                rights,
+                attr,
+            } => self.page_map(page, vspace, vaddr, rights, attr),
+            InvocationArgs::CnodeCopy {
+                cnode,
+                dest_index,
+                dest_depth,
+                src_root,
+                src_obj,
+                src_depth,
+                rights,
+            } => self.cnode_copy(
+                cnode, dest_index, dest_depth, src_root, src_obj, src_depth, rights, None,
+            ),
+            InvocationArgs::CnodeMint {
+                cnode,
+                dest_index,
+                dest_depth,
+                src_root,
+                src_obj,
+                src_depth,
+                rights,
+                badge,
+            } => self.cnode_copy(
+                cnode,
+                dest_index,
+                dest_depth,
+                src_root,
+                src_obj,
+                src_depth,
+                rights,
+                Some(badge),
+            ),
+            InvocationArgs::SchedControlConfigureFlags {
+                sched_control,
+                sched_context,
+                budget,
+                period,
+                badge,
+                ..
+            } => {
+                self.expect(sched_control, CapType::SchedControl)?;
+                let sc = self.expect(sched_context, CapType::Object(ObjectType::SchedContext))?;
+                if budget > period {
+                    return Err(format!(
+                        "budget {} is greater than the period {}",
+                        budget, period
+                    ));
+                }
+                if let Object::SchedContext {
+                    budget: sc_budget,
+                    period: sc_period,
+                    badge: sc_badge,
+                    ..
+                } = &mut self.objects[sc.object]
+                {
+                    *sc_budget = budget;
+                    *sc_period = period;
+                    *sc_badge = badge;
+                }
+                Ok(())
+            }
+            InvocationArgs::ArmVcpuSetTcb { vcpu, tcb } => {
+                self.expect(vcpu, CapType::Object(ObjectType::Vcpu))?;
+                self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+                Ok(())
+            }
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn untyped_retype(
+        &mut self,
+        untyped: u64,
+        object_type: ObjectType,
+        size_bits: u64,
+        root: u64,
+        node_index: u64,
+        node_depth: u64,
+        node_offset: u64,
+        num_objects: u64,
+    ) -> Result<(), String> {
+        let ut = self.expect(untyped, CapType::Object(ObjectType::Untyped))?;
+        let object_size_bits = match object_type.fixed_size_bits(self.config) {
+            Some(bits) => bits,
+            None => {
+                let (min_size_bits, object_size_bits) = match object_type {
+                    ObjectType::Untyped => (MIN_UNTYPED_BITS, size_bits),
+                    ObjectType::CNode => (1, size_bits + SLOT_BITS),
+                    ObjectType::SchedContext => (MIN_SCHED_CONTEXT_BITS, size_bits),
+                    _ => unreachable!(),
+                };
+                if size_bits < min_size_bits {
+                    return Err(format!(
+                        "size bits {} is less than the minimum of {} for {:?}",
+                        size_bits, min_size_bits, object_type
+                    ));
+                }
+                object_size_bits
+            }
+        };
+        if num_objects == 0 || num_objects > self.config.fan_out_limit {
+            return Err(format!(
+                "number of objects {} is not between 1 and the fan out limit {}",
+                num_objects, self.config.fan_out_limit
+            ));
+        }
+
+        let root = self.expect(root, CapType::Object(ObjectType::CNode))?;
+        let cnode = match node_depth {
+            0 => root,
+            _ => {
+                let slot = self.lookup_exact(&root, node_index, node_depth)?;
+                let cap = self.cap(slot);
+                if cap.cap_type != CapType::Object(ObjectType::CNode) {
+                    return Err(format!(
+                        "destination is {} rather than a CNode cap",
+                        cap.describe()
+                    ));
+                }
+                cap
+            }
+        };
+        let cnode_size = 1 << self.cnode_bits(cnode.object);
+        if node_offset >= cnode_size || num_objects > cnode_size - node_offset {
+            return Err(format!(
+                "slots {}..{} are outside of the destination CNode of {} slots",
+                node_offset,
+                node_offset.saturating_add(num_objects),
+                cnode_size
+            ));
+        }
+        for slot in node_offset..node_offset + num_objects {
+            if self.cap((cnode.object, slot)).cap_type != CapType::Null {
+                return Err(format!("destination slot {} is occupied", slot));
+            }
+        }
+
+        let Object::Untyped {
+            region,
+            is_device,
+            watermark,
+            has_children,
+        } = &mut self.objects[ut.object]
+        else {
+            unreachable!()
+        };
+        let is_frame = matches!(
+            object_type,
+            ObjectType::SmallPage | ObjectType::LargePage | ObjectType::HugePage
+        );
+        if *is_device && !is_frame && object_type != ObjectType::Untyped {
+            return Err(format!(
+                "device untyped cannot be retyped into {:?} objects",
+                object_type
+            ));
+        }
+        let is_device = *is_device;
+        // Memory is only reused once all the children of an untyped are deleted
+        let free = match *has_children {
+            true => *watermark,
+            false => 0,
+        };
+        let object_size = 1 << object_size_bits;
+        let base = round_up(region.base + free, object_size);
+        let end = num_objects
+            .checked_mul(object_size)
+            .and_then(|size| base.checked_add(size));
+        let end = match end {
+            Some(end) if object_size_bits < 64 && end <= region.end => end,
+            _ => {
+                return Err(format!(
+                    "not enough memory for {} {:?} objects in untyped [0x{:x}..0x{:x}) with 0x{:x} bytes used",
+                    num_objects, object_type, region.base, region.end, free
+                ))
+            }
+        };
+        *watermark = end - region.base;
+        *has_children = true;
+
+        for i in 0..num_objects {
+            let paddr = base + i * object_size;
+            let object = match object_type {
+                ObjectType::Untyped => Object::Untyped {
+                    region: MemoryRegion::new(paddr, paddr + object_size),
+                    is_device,
+                    watermark: 0,
+                    has_children: false,
+                },
+                ObjectType::CNode => Object::CNode {
+                    size_bits,
+                    slots: HashMap::new(),
+                },
+                ObjectType::Tcb => Object::Tcb(Box::default()),
+                ObjectType::Notification => Object::Notification { bound: false },
+                ObjectType::SchedContext => Object::sched_context(),
+                ObjectType::SmallPage | ObjectType::LargePage | ObjectType::HugePage => {
+                    Object::Frame { is_device }
+                }
+                ObjectType::VSpace => Object::Paging {
+                    page_tables: HashMap::new(),
+                    pages: HashMap::new(),
+                },
+                ObjectType::PageTable if self.config.arch == Arch::Riscv64 => Object::Paging {
+                    page_tables: HashMap::new(),
+                    pages: HashMap::new(),
+                },
+                ObjectType::Endpoint
+                | ObjectType::Reply
+                | ObjectType::PageTable
+                | ObjectType::Vcpu => Object::Other,
+            };
+            // On RISC-V a VSpace is a page table
+            let cap_type = match object_type {
+                ObjectType::VSpace | ObjectType::PageTable => self.vspace_type_of(object_type),
+                _ => object_type,
+            };
+            let object = self.add_object(object);
+            self.set_cap(
+                (cnode.object, node_offset + i),
+                Cap::new(CapType::Object(cap_type), object),
+            );
+        }
+
+        Ok(())
+    }
+
+    fn tcb_set_sched_params(
+        &mut self,
+        tcb: u64,
+        authority: u64,
+        mcp: u64,
+        priority: u64,
+        sched_context: u64,
+        fault_ep: u64,
+    ) -> Result<(), String> {
+        let tcb = self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+        let authority = self.expect(authority, CapType::Object(ObjectType::Tcb))?;
+        let authority_mcp = self.tcb_mut(authority.object).mcp;
+        if mcp > authority_mcp || priority > authority_mcp {
+            return Err(format!(
+                "priority {} and MCP {} must not exceed the MCP {} of the authority",
+                priority, mcp, authority_mcp
+            ));
+        }
+        let sc = self.lookup_cap(sched_context)?;
+        let sc = match sc.cap_type {
+            CapType::Null => None,
+            CapType::Object(ObjectType::SchedContext) => Some(sc.object),
+            _ => {
+                return Err(format!(
+                    "scheduling context is {} rather than a SchedContext cap",
+                    sc.describe()
+                ))
+            }
+        };
+        let fault_ep = self.check_fault_handler(fault_ep)?;
+        if let Some(sc) = sc {
+            let tcb_sc = self.tcb_mut(tcb.object).sched_context;
+            if tcb_sc.is_some_and(|tcb_sc| tcb_sc != sc) {
+                return Err("TCB already has a scheduling context".to_string());
+            }
+            let Object::SchedContext { tcb: sc_tcb, .. } = &mut self.objects[sc] else {
+                unreachable!()
+            };
+            if sc_tcb.is_some_and(|sc_tcb| sc_tcb != tcb.object) {
+                return Err("scheduling context is already bound to a TCB".to_string());
+            }
+            *sc_tcb = Some(tcb.object);
+        }
+        let tcb = self.tcb_mut(tcb.object);
+        tcb.mcp = mcp;
+        tcb.priority = priority;
+        tcb.sched_context = sc;
+        tcb.fault_ep = fault_ep;
+
+        Ok(())
+    }
+
+    fn tcb_set_space(
+        &mut self,
+        tcb: u64,
+        fault_ep: u64,
+        cspace_root: u64,
+        cspace_root_data: u64,
+        vspace_root: u64,
+    ) -> Result<(), String> {
+        let tcb = self.expect(tcb, CapType::Object(ObjectType::Tcb))?;
+        let fault_ep = self.check_fault_handler(fault_ep)?;
+        let mut cspace = self.lookup_cap(cspace_root)?;
+        if cspace_root_data != 0 {
+            cspace = self
+                .update_cap_data(cspace, cspace_root_data)
+                .unwrap_or(Cap::NULL);
+        }
+        if cspace.cap_type != CapType::Object(ObjectType::CNode) {
+            return Err(format!(
+                "CSpace root is {} rather than a valid CNode cap",
+                cspace.describe()
+            ));
+        }
+        self.vspace(vspace_root)?;
+        if tcb.object == self.monitor_tcb {
+            self.cspace_root = cspace;
+        }
+        let vspace = self.lookup_cap(vspace_root)?;
+        let tcb = self.tcb_mut(tcb.object);
+        tcb.fault_ep = fault_ep;
+        tcb.cspace = Some(cspace);
+        tcb.vspace = Some(vspace);
+
+        Ok(())
+    }
+
+    fn page_table_map(&mut self, page_table: u64, vspace: u64, vaddr: u64) -> Result<(), String> {
+        let slot = self.lookup(page_table)?;
+        let mut cap = self.cap(slot);
+        if cap.cap_type != CapType::Object(ObjectType::PageTable) {
+            return Err(format!("{} is not a PageTable cap", cap.describe()));
+        }
+        if cap.mapped {
+            return Err("page table is already mapped".to_string());
+        }
+        let vspace = self.vspace(vspace)?;
+        if vaddr >= self.config.user_top() {
+            return Err(format!(
+                "vaddr 0x{:x} is not below the top of user memory 0x{:x}",
+                vaddr,
+                self.config.user_top()
+            ));
+        }
+        let bits = self.lookup_pt_slot(vspace, vaddr);
+        let Object::Paging { page_tables, pages } = &mut self.objects[vspace] else {
+            unreachable!()
+        };
+        if bits == self.config.minimum_page_size.ilog2() as u64 {
+            return Err(format!(
+                "a page table is already mapped at vaddr 0x{:x}",
+                vaddr
+            ));
+        }
+        if pages.contains_key(&(bits, vaddr >> bits)) {
+            return Err(format!("a page is already mapped at vaddr 0x{:x}", vaddr));
+        }
+        page_tables.insert((bits, vaddr >> bits), cap.object);
+        cap.mapped = true;
+        self.set_cap(slot, cap);
+
+        Ok(())
+    }
+
+    fn page_map(
+        &mut self,
+        page: u64,
+        vspace: u64,
+        vaddr: u64,
+        rights: u64,
+        attr: u64,
+    ) -> Result<(), String> {
+        let slot = self.lookup(page)?;
+        let mut cap = self.cap(slot);
+        let size_bits = match self.frame_size_bits(&cap) {
+            Some(size_bits) => size_bits,
+            None => return Err(format!("{} is not a frame cap", cap.describe())),
+        };
+        if cap.mapped {
+            return Err("frame cap is already mapped".to_string());
+        }
+        let vspace = self.vspace(vspace)?;
+        if vaddr & mask(size_bits) != 0 {
+            return Err(format!(
+                "vaddr 0x{:x} is not aligned to the page size 0x{:x}",
+                vaddr,
+                1u64 << size_bits
+            ));
+        }
+        if vaddr + (1 << size_bits) > self.config.user_top() {
+            return Err(format!(
+                "page at vaddr 0x{:x} is not below the top of user memory 0x{:x}",
+                vaddr,
+                self.config.user_top()
+            ));
+        }
+        let bits = self.lookup_pt_slot(vspace, vaddr);
+        if bits > size_bits {
+            return Err(format!("missing page table for vaddr 0x{:x}", vaddr));
+        }
+        if bits < size_bits {
+            return Err(format!(
+                "a page table is already mapped at vaddr 0x{:x}",
+                vaddr
+            ));
+        }
+        let Object::Paging { pages, .. } = &mut self.objects[vspace] else {
+            unreachable!()
+        };
+        let mapping = Mapping {
+            frame: cap,
+            rights: rights & cap.rights,
+            attr,
+        };
+        if pages.insert((bits, vaddr >> bits), mapping).is_some() {
+            return Err(format!("a page is already mapped at vaddr 0x{:x}", vaddr));
+        }
+        cap.mapped = true;
+        self.set_cap(slot, cap);
+
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cnode_copy(
+        &mut self,
+        cnode: u64,
+        dest_index: u64,
+        dest_depth: u64,
+        src_root: u64,
+        src_obj: u64,
+        src_depth: u64,
+        rights: u64,
+        badge: Option<u64>,
+    ) -> Result<(), String> {
+        let dest_root = self.expect(cnode, CapType::Object(ObjectType::CNode))?;
+        let dest = self.lookup_empty(&dest_root, dest_index, dest_depth)?;
+        let src_root = self.expect(src_root, CapType::Object(ObjectType::CNode))?;
+        let src = self.lookup_exact(&src_root, src_obj, src_depth)?;
+        let mut cap = self.cap(src);
+        if cap.cap_type == CapType::Null {
+            return Err(format!("source slot 0x{:x} is empty", src_obj));
+        }
+        cap.rights &= rights;
+        if let Some(badge) = badge {
+            cap = match self.update_cap_data(cap, badge) {
+                Some(cap) => cap,
+                None => {
+                    return Err(format!(
+                        "cannot mint {} with badge 0x{:x}",
+                        cap.describe(),
+                        badge
+                    ))
+                }
+            };
+        }
+        // Deriving a cap
+        match cap.cap_type {
+            CapType::Object(ObjectType::Untyped) => {
+                if let Object::Untyped {
+                    has_children: true, ..
+                } = self.objects[cap.object]
+                {
+                    return Err("cannot copy an untyped cap with children".to_string());
+                }
+            }
+            CapType::IrqControl => return Err("cannot copy the IRQ control cap".to_string()),
+            CapType::Object(ObjectType::PageTable) | CapType::Object(ObjectType::VSpace) => {
+                if !cap.mapped {
+                    return Err(format!("cannot copy {} that is not mapped", cap.describe()));
+                }
+            }
+            _ => {
+                if self.frame_size_bits(&cap).is_some() {
+                    cap.mapped = false;
+                }
+            }
+        }
+        self.set_cap(dest, cap);
+
+        Ok(())
+    }
+
+    /// Equivalent of 'updateCapData' in the kernel, returns None where the
+    /// kernel would return a null cap.
+    fn update_cap_data(&self, mut cap: Cap, data: u64) -> Option<Cap> {
+        match cap.cap_type {
+            CapType::Object(ObjectType::Endpoint) | CapType::Object(ObjectType::Notification) => {
+                if cap.badge != 0 {
+                    return None;
+                }
+                cap.badge = data;
+            }
+            CapType::Object(ObjectType::CNode) => {
+                let guard_size = data & 0x3f;
+                if guard_size + self.cnode_bits(cap.object) > self.config.cap_address_bits {
+                    return None;
+                }
+                cap.guard_size = guard_size;
+                cap.guard = (data >> 6) & field_mask(guard_size);
+            }
+            _ => {}
+        }
+
+        Some(cap)
+    }
+
+    /// Check that a cap can be used as a fault handler, returning the cap
+    /// or None if there is no fault handler.
+    fn check_fault_handler(&self, fault_ep: u64) -> Result<Option<Cap>, String> {
+        let cap = self.lookup_cap(fault_ep)?;
+        let grant = Rights::Grant as u64 | Rights::GrantReply as u64;
+        match cap.cap_type {
+            CapType::Null => Ok(None),
+            CapType::Object(ObjectType::Endpoint)
+                if cap.rights & Rights::Write as u64 != 0 && cap.rights & grant != 0 =>
+            {
+                Ok(Some(cap))
+            }
+            _ => Err(format!(
+                "fault endpoint is {} rather than an endpoint cap that can send and grant",
+                cap.describe()
+            )),
+        }
+    }
+
+    /// Look up a VSpace cap that has been assigned an ASID, returning the VSpace.
+    fn vspace(&self, vspace: u64) -> Result<usize, String> {
+        let cap = self.lookup_cap(vspace)?;
+        if cap.cap_type != CapType::Object(self.vspace_type()) {
+            return Err(format!("{} is not a VSpace cap", cap.describe()));
+        }
+        if !cap.mapped {
+            return Err("VSpace has not been assigned an ASID".to_string());
+        }
+
+        Ok(cap.object)
+    }
+
+    /// Find the deepest level of page table that covers 'vaddr', returning
+    /// the number of bits of virtual address covered by an entry at that level.
+    fn lookup_pt_slot(&self, vspace: usize, vaddr: u64) -> u64 {
+        let Object::Paging { page_tables, .. } = &self.objects[vspace] else {
+            unreachable!()
+        };
+        let min_bits = self.config.minimum_page_size.ilog2() as u64;
+        let mut bits = self.root_bits();
+        while bits > min_bits && page_tables.contains_key(&(bits, vaddr >> bits)) {
+            bits -= PT_INDEX_BITS;
+        }
+
+        bits
+    }
+
+    /// Number of bits of virtual address covered by an entry of the VSpace root
+    pub(crate) fn root_bits(&self) -> u64 {
+        let levels = match self.config.arch {
+            Arch::Aarch64 => match (self.config.hypervisor, self.config.arm_pa_size_bits) {
+                (true, Some(40)) => 3,
+                _ => 4,
+            },
+            Arch::Riscv64 => self.config.riscv_pt_levels.unwrap().levels() as u64,
+        };
+
+        self.config.minimum_page_size.ilog2() as u64 + PT_INDEX_BITS * (levels - 1)
+    }
+
+    fn vspace_type(&self) -> ObjectType {
+        self.vspace_type_of(ObjectType::VSpace)
+    }
+
+    fn vspace_type_of(&self, object_type: ObjectType) -> ObjectType {
+        match self.config.arch {
+            Arch::Riscv64 => ObjectType::PageTable,
+            _ => object_type,
+        }
+    }
+
+    fn frame_size_bits(&self, cap: &Cap) -> Option<u64> {
+        match cap.cap_type {
+            CapType::Object(
+                object_type
+                @ (ObjectType::SmallPage | ObjectType::LargePage | ObjectType::HugePage),
+            ) => object_type.fixed_size_bits(self.config),
+            _ => None,
+        }
+    }
+
+    fn tcb_mut(&mut self, tcb: usize) -> &mut Tcb {
+        match &mut self.objects[tcb] {
+            Object::Tcb(tcb) => tcb,
+            _ => unreachable!(),
+        }
+    }
+
+    fn add_object(&mut self, object: Object) -> usize {
+        self.objects.push(object);
+        self.objects.len() - 1
+    }
+
+    fn cnode_bits(&self, cnode: usize) -> u64 {
+        match &self.objects[cnode] {
+            Object::CNode { size_bits, .. } => *size_bits,
+            _ => unreachable!(),
+        }
+    }
+
+    fn cap(&self, (cnode, index): Slot) -> Cap {
+        match &self.objects[cnode] {
+            Object::CNode { slots, .. } => slots.get(&index).copied().unwrap_or(Cap::NULL),
+            _ => unreachable!(),
+        }
+    }
+
+    fn set_cap(&mut self, (cnode, index): Slot, cap: Cap) {
+        match &mut self.objects[cnode] {
+            Object::CNode { slots, .. } => {
+                slots.insert(index, cap);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Resolve 'depth' bits of the cap address 'addr' starting at the CNode
+    /// cap 'root', following the kernel's 'resolveAddressBits'. Returns the slot
+    /// found and the number of bits that were not resolved.
+    fn resolve(&self, root: &Cap, addr: u64, depth: u64) -> Result<(Slot, u64), String> {
+        let mut cap = *root;
+        let mut bits = depth;
+        loop {
+            if cap.cap_type != CapType::Object(ObjectType::CNode) {
+                return Err(format!(
+                    "cap address 0x{:x} is looked up in {} rather than a CNode cap",
+                    addr,
+                    cap.describe()
+                ));
+            }
+            let radix = self.cnode_bits(cap.object);
+            let level_bits = radix + cap.guard_size;
+            if level_bits > bits {
+                return Err(format!(
+                    "cap address 0x{:x} has {} bits left to resolve but the CNode needs {}",
+                    addr, bits, level_bits
+                ));
+            }
+            let guard = field(addr, bits - cap.guard_size, cap.guard_size);
+            if guard != cap.guard {
+                return Err(format!(
+                    "cap address 0x{:x} does not match the guard 0x{:x} of the CNode",
+                    addr, cap.guard
+                ));
+            }
+            let slot = (cap.object, field(addr, bits - level_bits, radix));
+            bits -= level_bits;
+            let next = self.cap(slot);
+            if bits == 0 || next.cap_type != CapType::Object(ObjectType::CNode) {
+                return Ok((slot, bits));
+            }
+            cap = next;
+        }
+    }
+
+    /// Look up a cap address in the CSpace of the monitor.
+    fn lookup(&self, addr: u64) -> Result<Slot, String> {
+        let (slot, _) = self.resolve(&self.cspace_root, addr, self.config.cap_address_bits)?;
+        Ok(slot)
+    }
+
+    fn lookup_cap(&self, addr: u64) -> Result<Cap, String> {
+        Ok(self.cap(self.lookup(addr)?))
+    }
+
+    /// The object referred to by a cap address in the CSpace of the monitor.
+    pub(crate) fn object_at(&self, addr: u64) -> Option<usize> {
+        match self.lookup_cap(addr) {
+            Ok(cap) if cap.cap_type != CapType::Null => Some(cap.object),
+            _ => None,
+        }
+    }
+
+    /// Look up a cap address in the CSpace of the monitor and check the type of the cap.
+    fn expect(&self, addr: u64, cap_type: CapType) -> Result<Cap, String> {
+        let cap = self.lookup_cap(addr)?;
+        if cap.cap_type != cap_type {
+            return Err(format!(
+                "cap 0x{:x} is {} rather than {}",
+                addr,
+                cap.describe(),
+                Cap::new(cap_type, 0).describe()
+            ));
+        }
+
+        Ok(cap)
+    }
+
+    /// Look up a slot for a CNode operation, where the cap address must
+    /// resolve to a slot using exactly 'depth' bits.
+    fn lookup_exact(&self, root: &Cap, index: u64, depth: u64) -> Result<Slot, String> {
+        if depth < 1 || depth > self.config.cap_address_bits {
+            return Err(format!("depth {} is invalid", depth));
+        }
+        let (slot, bits) = self.resolve(root, index, depth)?;
+        if bits != 0 {
+            return Err(format!(
+                "cap address 0x{:x} does not resolve to a slot at depth {}",
+                index, depth
+            ));
+        }
+
+        Ok(slot)
+    }
+
+    fn lookup_empty(&self, root: &Cap, index: u64, depth: u64) -> Result<Slot, String> {
+        let slot = self.lookup_exact(root, index, depth)?;
+        if self.cap(slot).cap_type != CapType::Null {
+            return Err(format!("destination slot 0x{:x} is occupied", index));
+        }
+
+        Ok(slot)
+    }
+}
+
+fn field_mask(bits: u64) -> u64 {
+    match bits {
+        0 => 0,
+        _ => u64::MAX >> (64 - bits),
+    }
+}
+
+/// The 'bits' bits of 'value' starting at bit 'shift'.
+fn field(value: u64, shift: u64, bits: u64) -> u64 {
+    value.checked_shr(shift as u32).unwrap_or(0) & field_mask(bits)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::sel4::test_util::config;
+    use crate::UntypedObject;
+
+    const ROOT_CNODE: u64 = 25;
+
+    fn boot_info() -> BootInfo {
+        BootInfo {
+            fixed_cap_count: 16,
+            sched_control_cap: 18,
+            paging_cap_count: 2,
+            page_cap_count: 4,
+            untyped_objects: vec![
+                UntypedObject::new(23, MemoryRegion::new(0x4000_0000, 0x4010_0000), false),
+                UntypedObject::new(24, MemoryRegion::new(0x900_0000, 0x900_1000), true),
+            ],
+            first_available_cap: 25,
+        }
+    }
+
+    pub(crate) fn simulator(config: &Config) -> Simulator<'_> {
+        Simulator::new(
+            config,
+            &boot_info(),
+            MemoryRegion::new(0x8a00_0000, 0x8a00_4000),
+        )
+    }
+
+    pub(crate) fn retype(
+        untyped: u64,
+        object_type: ObjectType,
+        size_bits: u64,
+        slot: u64,
+    ) -> InvocationArgs {
+        InvocationArgs::UntypedRetype {
+            untyped,
+            object_type,
+            size_bits,
+            root: INIT_CNODE_CAP,
+            node_index: 0,
+            node_depth: 0,
+            node_offset: slot,
+            num_objects: 1,
+        }
+    }
+
+    pub(crate) fn page_map(page: u64, vspace: u64, vaddr: u64) -> InvocationArgs {
+        InvocationArgs::PageMap {
+            page,
+            vspace,
+            vaddr,
+            rights: Rights::Read as u64,
+            attr: 0,
+        }
+    }
+
+    #[test]
+    fn test_retype_checks_slots_and_memory() {
+        let config = config();
+        let mut simulator = simulator(&config);
+
+        assert!(simulator
+            .execute(&retype(23, ObjectType::Tcb, 0, 25))
+            .is_ok());
+        let err = simulator
+            .execute(&retype(23, ObjectType::Tcb, 0, 25))
+            .unwrap_err();
+        assert!(err.contains("occupied"), "{}", err);
+
+        // The TCB moved the watermark, so a 1MiB untyped no longer fits
+        let err = simulator
+            .execute(&retype(23, ObjectType::Untyped, 20, 26))
+            .unwrap_err();
+        assert!(err.contains("not enough memory"), "{}", err);
+
+        let err = simulator
+            .execute(&retype(24, ObjectType::Tcb, 0, 26))
+            .unwrap_err();
+        assert!(err.contains("device untyped"), "{}", err);
+        assert!(simulator
+            .execute(&retype(24, ObjectType::SmallPage, 0, 26))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_page_map_needs_asid_and_page_tables() {
+        let config = config();
+        let mut simulator = simulator(&config);
+
+        for (slot, object_type) in [
+            (25, ObjectType::VSpace),
+            (26, ObjectType::PageTable),
+            (27, ObjectType::PageTable),
+            (28, ObjectType::SmallPage),
+            (29, ObjectType::SmallPage),
+        ] {
+            simulator
+                .execute(&retype(23, object_type, 0, slot))
+                .unwrap();
+        }
+
+        let err = simulator.execute(&page_map(28, 25, 0x1000)).unwrap_err();
+        assert!(err.contains("ASID"), "{}", err);
+        let assign = InvocationArgs::AsidPoolAssign {
+            asid_pool: INIT_ASID_POOL_CAP,
+            vspace: 25,
+        };
+        simulator.execute(&assign).unwrap();
+
+        let err = simulator.execute(&page_map(28, 25, 0x1000)).unwrap_err();
+        assert!(err.contains("missing page table"), "{}", err);
+        for page_table in [26, 27] {
+            let map = InvocationArgs::PageTableMap {
+                page_table,
+                vspace: 25,
+                vaddr: 0,
+                attr: 0,
+            };
+            simulator.execute(&map).unwrap();
+        }
+        simulator.execute(&page_map(28, 25, 0x1000)).unwrap();
+
+        let err = simulator.execute(&page_map(28, 25, 0x2000)).unwrap_err();
+        assert!(err.contains("already mapped"), "{}", err);
+        let err = simulator.execute(&page_map(29, 25, 0x1000)).unwrap_err();
+        assert!(err.contains("already mapped"), "{}", err);
+        let err = simulator.execute(&page_map(29, 25, 0x1800)).unwrap_err();
+        assert!(err.contains("not aligned"), "{}", err);
+    }
+
+    #[test]
+    fn test_cspace_guards() {
+        let config = config();
+        let mut simulator = simulator(&config);
+        let system_cnode_bits = 4;
+        let system_cap_address_mask = 1 << 63;
+
+        // Set up the CSpace of the monitor in the same way as the bootstrap invocations
+        let mint = |dest_index: u64, src_obj: u64, badge: u64| InvocationArgs::CnodeMint {
+            cnode: ROOT_CNODE,
+            dest_index,
+            dest_depth: 1,
+            src_root: INIT_CNODE_CAP,
+            src_obj,
+            src_depth: 64,
+            rights: Rights::All as u64,
+            badge,
+        };
+        simulator
+            .execute(&retype(23, ObjectType::CNode, 1, ROOT_CNODE))
+            .unwrap();
+        simulator
+            .execute(&mint(0, INIT_CNODE_CAP, 64 - 1 - 12))
+            .unwrap();
+        let set_space = InvocationArgs::TcbSetSpace {
+            tcb: INIT_TCB_CAP,
+            fault_ep: 0,
+            cspace_root: ROOT_CNODE,
+            cspace_root_data: 0,
+            vspace_root: INIT_VSPACE_CAP,
+            vspace_root_data: 0,
+        };
+        simulator.execute(&set_space).unwrap();
+        simulator
+            .execute(&retype(23, ObjectType::CNode, system_cnode_bits, 26))
+            .unwrap();
+        simulator
+            .execute(&mint(1, 26, 64 - 1 - system_cnode_bits))
+            .unwrap();
+
+        let retype_tcb = InvocationArgs::UntypedRetype {
+            untyped: 23,
+            object_type: ObjectType::Tcb,
+            size_bits: 0,
+            root: ROOT_CNODE,
+            node_index: 1,
+            node_depth: 1,
+            node_offset: 0,
+            num_objects: 1,
+        };
+        simulator.execute(&retype_tcb).unwrap();
+        let resume = |tcb: u64| InvocationArgs::TcbResume { tcb };
+        simulator.execute(&resume(system_cap_address_mask)).unwrap();
+
+        let err = simulator
+            .execute(&resume(system_cap_address_mask | 1))
+            .unwrap_err();
+        assert!(err.contains("null cap"), "{}", err);
+        // Bits that are not covered by the CNodes must match the guard
+        let err = simulator
+            .execute(&resume(system_cap_address_mask | 1 << 40))
+            .unwrap_err();
+        assert!(err.contains("guard"), "{}", err);
+    }
+}
@@ -0,0 +1,724 @@
+//
+// Copyright 2024, UNSW
+//
+// SPDX-License-Identifier: BSD-2-Clause
+//
+
+//! Build whole systems with the tool against a minimal SDK that is created
+//! by the tests. The kernel, monitor, loader and program images only contain
+//! the segments and symbols that the tool looks at.
+
+use microkit_tool::loader::LoaderImage;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+const BOARD: &str = "qemu_virt_aarch64";
+const CONFIG: &str = "debug";
+
+const KERNEL_VADDR: u64 = 0xffffff8040000000;
+const KERNEL_PADDR: u64 = 0x40000000;
+const MONITOR_VADDR: u64 = 0x8a000000;
+const LOADER_VADDR: u64 = 0x70000000;
+const PD_VADDR: u64 = 0x200000;
+/// Where the 0x3000 byte heap of 'client' in sys_build.system is placed
+const HEAP_VADDR: u64 = 0xffffffc000;
+
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const KERNEL_CONFIG: &str = r#"{
+    "SEL4_ARCH": "aarch64",
+    "ARM_HYPERVISOR_SUPPORT": true,
+    "ARM_PA_SIZE_BITS_40": true,
+    "ARM_PA_SIZE_BITS_44": false,
+    "ALLOW_SMC_CALLS": false,
+    "WORD_SIZE": "64",
+    "PADDR_USER_DEVICE_TOP": "1099511627776",
+    "ROOT_CNODE_SIZE_BITS": "12",
+    "RETYPE_FAN_OUT_LIMIT": "256",
+    "HAVE_FPU": true
+}"#;
+
+/// In the order of the kernel's invocation labels for AArch64 with the
+/// hypervisor enabled.
+const INVOCATION_LABELS: &[&str] = &[
+    "UntypedRetype",
+    "TCBReadRegisters",
+    "TCBWriteRegisters",
+    "TCBCopyRegisters",
+    "TCBConfigure",
+    "TCBSetPriority",
+    "TCBSetMCPriority",
+    "TCBSetSchedParams",
+    "TCBSetTimeoutEndpoint",
+    "TCBSetIPCBuffer",
+    "TCBSetSpace",
+    "TCBSuspend",
+    "TCBResume",
+    "TCBBindNotification",
+    "TCBUnbindNotification",
+    "TCBSetTLSBase",
+    "CNodeRevoke",
+    "CNodeDelete",
+    "CNodeCancelBadgedSends",
+    "CNodeCopy",
+    "CNodeMint",
+    "CNodeMove",
+    "CNodeMutate",
+    "CNodeRotate",
+    "IRQIssueIRQHandler",
+    "IRQAckIRQ",
+    "IRQSetIRQHandler",
+    "IRQClearIRQHandler",
+    "DomainSetSet",
+    "SchedControlConfigureFlags",
+    "SchedContextBind",
+    "SchedContextUnbind",
+    "SchedContextUnbindObject",
+    "SchedContextConsume",
+    "SchedContextYieldTo",
+    "ARMVSpaceCleanData",
+    "ARMVSpaceInvalidateData",
+    "ARMVSpaceCleanInvalidateData",
+    "ARMVSpaceUnifyInstruction",
+    "ARMSMCCall",
+    "ARMPageTableMap",
+    "ARMPageTableUnmap",
+    "ARMPageMap",
+    "ARMPageUnmap",
+    "ARMPageCleanData",
+    "ARMPageInvalidateData",
+    "ARMPageCleanInvalidateData",
+    "ARMPageUnifyInstruction",
+    "ARMPageGetAddress",
+    "ARMASIDControlMakePool",
+    "ARMASIDPoolAssign",
+    "ARMVCPUSetTCB",
+    "ARMVCPUInjectIRQ",
+    "ARMVCPUReadReg",
+    "ARMVCPUWriteReg",
+    "ARMVCPUAckVppi",
+    "ARMIRQIssueIRQHandlerTrigger",
+];
+
+struct Segment {
+    vaddr: u64,
+    paddr: u64,
+    flags: u32,
+    size: u64,
+}
+
+/// Write a little-endian ELF64 file with the given loadable segments (the
+/// contents of which are all zero) and symbols.
+fn write_elf(path: &Path, entry: u64, segments: &[Segment], symbols: &[(&str, u64, u64)]) {
+    const EHDR_SIZE: u64 = 64;
+    const PHDR_SIZE: u64 = 56;
+    const SHDR_SIZE: u64 = 64;
+    const SYM_SIZE: u64 = 24;
+
+    let phoff = EHDR_SIZE;
+    let mut offset = phoff + PHDR_SIZE * segments.len() as u64;
+    let mut segment_offsets = Vec::new();
+    for segment in segments {
+        segment_offsets.push(offset);
+        offset += segment.size;
+    }
+
+    let mut strtab = vec![0];
+    let mut symtab = vec![0; SYM_SIZE as usize];
+    for (name, value, size) in symbols {
+        symtab.extend((strtab.len() as u32).to_le_bytes());
+        symtab.push(0x11); // global object
+        symtab.push(0);
+        symtab.extend(1_u16.to_le_bytes());
+        symtab.extend(value.to_le_bytes());
+        symtab.extend(size.to_le_bytes());
+        strtab.extend(name.as_bytes());
+        strtab.push(0);
+    }
+    let symtab_offset = offset;
+    let strtab_offset = symtab_offset + symtab.len() as u64;
+    let shoff = strtab_offset + strtab.len() as u64;
+
+    let mut elf = Vec::new();
+    elf.extend(b"\x7fELF");
+    elf.extend([2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+    elf.extend(2_u16.to_le_bytes()); // executable
+    elf.extend(183_u16.to_le_bytes()); // AArch64
+    elf.extend(1_u32.to_le_bytes());
+    elf.extend(entry.to_le_bytes());
+    elf.extend(phoff.to_le_bytes());
+    elf.extend(shoff.to_le_bytes());
+    elf.extend(0_u32.to_le_bytes());
+    elf.extend((EHDR_SIZE as u16).to_le_bytes());
+    elf.extend((PHDR_SIZE as u16).to_le_bytes());
+    elf.extend((segments.len() as u16).to_le_bytes());
+    elf.extend((SHDR_SIZE as u16).to_le_bytes());
+    elf.extend(3_u16.to_le_bytes());
+    elf.extend(2_u16.to_le_bytes());
+
+    for (segment, offset) in segments.iter().zip(&segment_offsets) {
+        elf.extend(1_u32.to_le_bytes()); // PT_LOAD
+        elf.extend(segment.flags.to_le_bytes());
+        for field in [
+            *offset,
+            segment.vaddr,
+            segment.paddr,
+            segment.size,
+            segment.size,
+            0x1000,
+        ] {
+            elf.extend(field.to_le_bytes());
+        }
+    }
+    for segment in segments {
+        elf.extend(vec![0; segment.size as usize]);
+    }
+    elf.extend(&symtab);
+    elf.extend(&strtab);
+
+    // Null section, symbol table and its string table
+    elf.extend([0; SHDR_SIZE as usize]);
+    for (type_, offset, size, link, entsize) in [
+        (2_u32, symtab_offset, symtab.len() as u64, 2_u32, SYM_SIZE),
+        (3, strtab_offset, strtab.len() as u64, 0, 0),
+    ] {
+        elf.extend(0_u32.to_le_bytes());
+        elf.extend(type_.to_le_bytes());
+        for field in [0, 0, offset, size] {
+            elf.extend(u64::to_le_bytes(field));
+        }
+        elf.extend(link.to_le_bytes());
+        elf.extend(0_u32.to_le_bytes());
+        elf.extend(8_u64.to_le_bytes());
+        elf.extend(entsize.to_le_bytes());
+    }
+
+    fs::write(path, elf).unwrap();
+}
+
+/// Patch the contents of a symbol in an ELF file written by 'write_elf'.
+fn patch_elf(path: &Path, segment_vaddr: u64, vaddr: u64, data: &[u8]) {
+    let mut elf = fs::read(path).unwrap();
+    let phoff = u64::from_le_bytes(elf[32..40].try_into().unwrap()) as usize;
+    let segment_offset = u64::from_le_bytes(elf[phoff + 8..phoff + 16].try_into().unwrap());
+    let offset = (segment_offset + vaddr - segment_vaddr) as usize;
+    elf[offset..offset + data.len()].copy_from_slice(data);
+    fs::write(path, elf).unwrap();
+}
+
+fn write_kernel(path: &Path) {
+    let device_frames = KERNEL_VADDR + 0x1000;
+    let avail_p_regs = KERNEL_VADDR + 0x2000;
+    write_elf(
+        path,
+        KERNEL_VADDR,
+        &[Segment {
+            vaddr: KERNEL_VADDR,
+            paddr: KERNEL_PADDR,
+            flags: PF_R | PF_W | PF_X,
+            size: 0x30000,
+        }],
+        &[
+            ("kernel_device_frames", device_frames, 2 * 24),
+            ("avail_p_regs", avail_p_regs, 16),
+            ("ki_boot_end", KERNEL_VADDR + 0x10000, 0),
+            ("ki_end", KERNEL_VADDR + 0x30000, 0),
+        ],
+    );
+
+    // The GIC is used by the kernel, the UART is available to user-level
+    let mut frames = Vec::new();
+    for (paddr, pptr, user_accessible) in [
+        (0x8000000_u64, 0xffffffffc0000000_u64, 0_u32),
+        (0x9000000, 0xffffffffc0001000, 1),
+    ] {
+        frames.extend(paddr.to_le_bytes());
+        frames.extend(pptr.to_le_bytes());
+        frames.extend(1_u32.to_le_bytes());
+        frames.extend(user_accessible.to_le_bytes());
+    }
+    patch_elf(path, KERNEL_VADDR, device_frames, &frames);
+
+    let mut regions = Vec::new();
+    regions.extend(0x40000000_u64.to_le_bytes());
+    regions.extend(0x80000000_u64.to_le_bytes());
+    patch_elf(path, KERNEL_VADDR, avail_p_regs, &regions);
+}
+
+fn write_monitor(path: &Path) {
+    let symbols = [
+        ("untyped_info", 0x1000, 16 + 256 * 24),
+        ("bootstrap_invocation_count", 0x3000, 8),
+        ("bootstrap_invocation_data", 0x4000, 0x20000),
+        ("system_invocation_count", 0x24000, 8),
+        ("fault_ep", 0x24008, 8),
+        ("reply", 0x24010, 8),
+        ("tcbs", 0x25000, 64 * 8),
+        ("scheduling_contexts", 0x25200, 64 * 8),
+        ("notification_caps", 0x25400, 64 * 8),
+        ("pd_names", 0x26000, 64 * 64),
+    ]
+    .map(|(name, offset, size)| (name, MONITOR_VADDR + offset, size));
+    write_elf(
+        path,
+        MONITOR_VADDR,
+        &[Segment {
+            vaddr: MONITOR_VADDR,
+            paddr: MONITOR_VADDR,
+            flags: PF_R | PF_W | PF_X,
+            size: 0x27000,
+        }],
+        &symbols,
+    );
+}
+
+fn write_loader(path: &Path) {
+    let symbols = [
+        "boot_lvl0_lower",
+        "boot_lvl0_upper",
+        "boot_lvl1_lower",
+        "boot_lvl1_upper",
+        "boot_lvl2_upper",
+    ];
+    let symbols: Vec<_> = symbols
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (*name, LOADER_VADDR + 0x1000 * (i as u64 + 1), 0x1000))
+        .collect();
+    write_elf(
+        path,
+        LOADER_VADDR,
+        &[Segment {
+            vaddr: LOADER_VADDR,
+            paddr: LOADER_VADDR,
+            flags: PF_R | PF_W | PF_X,
+            size: 0x1000 * (symbols.len() as u64 + 1),
+        }],
+        &symbols,
+    );
+}
+
+fn write_program_image(path: &Path) {
+    write_program_image_with_data(path, PD_VADDR + 0x1000, 0x1000);
+}
+
+/// Program image with a data segment of 'data_size' bytes at 'data', which
+/// holds the symbols patched by the tool.
+fn write_program_image_with_data(path: &Path, data: u64, data_size: u64) {
+    write_elf(
+        path,
+        PD_VADDR,
+        &[
+            Segment {
+                vaddr: PD_VADDR,
+                paddr: PD_VADDR,
+                flags: PF_R | PF_X,
+                size: 0x1000,
+            },
+            Segment {
+                vaddr: data,
+                paddr: data,
+                flags: PF_R | PF_W,
+                size: data_size,
+            },
+        ],
+        &[
+            ("microkit_name", data, 64),
+            ("microkit_passive", data + 64, 1),
+            ("shared_vaddr", data + 72, 8),
+            ("microkit_heap_start", data + 80, 8),
+            ("microkit_heap_size", data + 88, 8),
+            ("__sel4_ipc_buffer_obj", data + data_size, 0),
+        ],
+    );
+}
+
+/// Contents of the 'firmware' memory region in sys_build.system
+fn firmware() -> Vec<u8> {
+    (0..0x100).map(|i| i as u8).collect()
+}
+
+/// Create an SDK for a single board and configuration in a new directory
+fn create_sdk(test_name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("microkit-{}-{}", test_name, std::process::id()));
+    if dir.exists() {
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    let config_dir = dir.join("board").join(BOARD).join(CONFIG);
+    fs::create_dir_all(config_dir.join("elf")).unwrap();
+    fs::create_dir_all(config_dir.join("include/kernel")).unwrap();
+
+    write_kernel(&config_dir.join("elf/sel4.elf"));
+    write_monitor(&config_dir.join("elf/monitor.elf"));
+    write_loader(&config_dir.join("elf/loader.elf"));
+    write_program_image(&dir.join("pd.elf"));
+    fs::write(dir.join("firmware.bin"), firmware()).unwrap();
+
+    fs::write(
+        config_dir.join("include/kernel/gen_config.json"),
+        KERNEL_CONFIG,
+    )
+    .unwrap();
+    let labels: Vec<String> = INVOCATION_LABELS
+        .iter()
+        .enumerate()
+        .map(|(i, label)| format!("\"{}\": {}", label, i + 1))
+        .collect();
+    fs::write(
+        config_dir.join("invocations_all.json"),
+        format!("{{{}}}", labels.join(", ")),
+    )
+    .unwrap();
+
+    dir
+}
+
+fn build(sdk: &Path, system: &str, extra_args: &[&str]) -> Output {
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("tests/sdf/");
+    path.push(system);
+
+    Command::new(env!("CARGO_BIN_EXE_microkit"))
+        .arg(path)
+        .args(["--board", BOARD, "--config", CONFIG])
+        .arg("-o")
+        .arg(sdk.join("loader.img"))
+        .arg("-r")
+        .arg(sdk.join("report.txt"))
+        .arg("--search-path")
+        .arg(sdk)
+        .args(extra_args)
+        .env("MICROKIT_SDK", sdk)
+        .output()
+        .unwrap()
+}
+
+fn check_success(output: &Output) {
+    if !output.status.success() {
+        panic!(
+            "Build failed:\n{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+}
+
+#[test]
+fn test_build_and_simulate() {
+    let sdk = create_sdk("build_and_simulate");
+    let spec = sdk.join("spec.cdl");
+    let output = build(
+        &sdk,
+        "sys_build.system",
+        &["--capdl", spec.to_str().unwrap()],
+    );
+    check_success(&output);
+
+    // The spec is only written once the invocations have been simulated
+    let spec = fs::read_to_string(spec).unwrap();
+    for object in ["tcb_pd_client", "tcb_pd_server", "irq_33"] {
+        assert!(spec.contains(object), "'{}' is not in the spec", object);
+    }
+    assert!(sdk.join("loader.img").exists());
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_build_without_verify() {
+    let sdk = create_sdk("build_without_verify");
+    let output = build(&sdk, "sys_build.system", &["--no-verify"]);
+    check_success(&output);
+    assert!(sdk.join("loader.img").exists());
+
+    // Sections of the report that would be empty are left out
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    for section in [
+        "# Guard Regions",
+        "# Contiguous Memory Regions",
+        "# Reserved Memory",
+    ] {
+        assert!(!report.contains(section), "'{}' is in the report", section);
+    }
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_heap() {
+    let sdk = create_sdk("heap");
+    let output = build(&sdk, "sys_build.system", &[]);
+    check_success(&output);
+
+    // The heap of 'client' is mapped directly below its stack
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    let lines: Vec<&str> = report.lines().collect();
+    let map = lines
+        .iter()
+        .position(|line| line.ends_with("(Page(4 KiB): MR=HEAP:client #0 (derived))"))
+        .expect("heap of 'client' is not mapped");
+    assert!(lines[map + 1].ends_with("(VSpace: PD=client)"));
+    assert!(lines[map + 2].ends_with(&format!("vaddr                0x{:x}", HEAP_VADDR)));
+    assert!(!report.contains("MR=HEAP:server"));
+
+    // The heap symbols are only written for the protection domain with a heap
+    let image_data = fs::read(sdk.join("loader.img")).unwrap();
+    let image = LoaderImage::parse(&image_data).unwrap();
+    let pd_data = |name: &str| -> &[u8] {
+        let name = format!("{}\0", name);
+        image
+            .regions
+            .iter()
+            .find(|(_, data)| data.starts_with(name.as_bytes()))
+            .map(|(_, data)| *data)
+            .unwrap()
+    };
+    let read_u64 = |data: &[u8], offset: usize| {
+        u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    };
+    let client = pd_data("client");
+    assert_eq!(read_u64(client, 80), HEAP_VADDR);
+    assert_eq!(read_u64(client, 88), 0x3000);
+    let server = pd_data("server");
+    assert_eq!(read_u64(server, 80), 0);
+    assert_eq!(read_u64(server, 88), 0);
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_memory_region_source() {
+    let sdk = create_sdk("memory_region_source");
+    let output = build(&sdk, "sys_build.system", &[]);
+    check_success(&output);
+
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    let line = report
+        .lines()
+        .find(|line| line.contains("<Region name=MR firmware "))
+        .expect("'firmware' is not a loader region");
+    let addr = line
+        .split("addr=0x")
+        .nth(1)
+        .unwrap()
+        .split(' ')
+        .next()
+        .unwrap();
+    let addr = u64::from_str_radix(addr, 16).unwrap();
+
+    // The region is placed in the reserved region, with only the contents of
+    // the source in the image and the rest zeroed by the loader
+    let image_data = fs::read(sdk.join("loader.img")).unwrap();
+    let image = LoaderImage::parse(&image_data).unwrap();
+    assert!(addr >= image.extra_device_addr_p);
+    assert!(addr + 0x3000 <= image.extra_device_addr_p + image.extra_device_size);
+    assert_eq!(image.region(addr + 0x1800), Some(firmware().as_slice()));
+    assert_eq!(
+        image.zero_regions,
+        vec![(addr, 0x1800), (addr + 0x1900, 0x1700)]
+    );
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_memory_region_source_too_large() {
+    let sdk = create_sdk("memory_region_source_too_large");
+    fs::write(sdk.join("firmware.bin"), vec![0; 0x1801]).unwrap();
+    let output = build(&sdk, "sys_build.system", &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(
+        "source 'firmware.bin' (0x1801 bytes) does not fit at offset 0x1800 of memory region 'firmware' (0x3000 bytes)"
+    ));
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_fixed_memory_region() {
+    let sdk = create_sdk("fixed_memory_region");
+    let output = build(&sdk, "sys_build.system", &[]);
+    check_success(&output);
+
+    // 'fixed' is in the same untyped as the pages allocated before it, the
+    // objects allocated after it continue from the end of it
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    let phys_addr = |object: &str| -> &str {
+        let line = report
+            .lines()
+            .find(|line| line.trim_start().starts_with(object))
+            .unwrap_or_else(|| panic!("'{}' is not in the report", object));
+        line.split("phys_addr=").nth(1).unwrap()
+    };
+    assert_eq!(phys_addr("Page(4 KiB): MR=fixed @"), "4000e000");
+    assert_eq!(phys_addr("TCB: PD=client"), "4000f000");
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_board_reserved_memory() {
+    let sdk = create_sdk("board_reserved_memory");
+    let path = sdk
+        .join("board")
+        .join(BOARD)
+        .join(CONFIG)
+        .join("reserved_memory.json");
+
+    fs::write(&path, r#"[{"base": "2130710528", "size": "4096"}]"#).unwrap();
+    let output = build(&sdk, "sys_build_reserved.system", &[]);
+    check_success(&output);
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    assert!(report.contains("[0x7f000000..0x7f001000) (system description)"));
+    assert!(report.contains("[0x7f001000..0x7f002000) (board)"));
+
+    fs::write(&path, r#"[{"base": "2130706432", "size": "8192"}]"#).unwrap();
+    let output = build(&sdk, "sys_build_reserved.system", &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("reserved memory [0x7f000000..0x7f001000) overlaps with reserved memory [0x7f000000..0x7f002000) of the board @ "));
+
+    fs::write(&path, r#"[{"base": 2130710528, "size": "4096"}]"#).unwrap();
+    let output = build(&sdk, "sys_build_reserved.system", &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!(
+        "entry 0 of board reserved memory '{}' does not have a 'base' that is a decimal string",
+        path.display()
+    )));
+
+    fs::write(&path, "[").unwrap();
+    let output = build(&sdk, "sys_build_reserved.system", &[]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!(
+        "could not parse board reserved memory '{}'",
+        path.display()
+    )));
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_large_pages() {
+    let sdk = create_sdk("large_pages");
+    // The data segment covers [0x3ff000..0x601000), so only its 2MiB aligned
+    // middle can be mapped with a large page
+    write_program_image_with_data(&sdk.join("large.elf"), 0x3ff000, 0x202000);
+    let output = build(&sdk, "sys_build_large.system", &[]);
+    check_success(&output);
+
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    for object in [
+        "Page(4 KiB): MR=ELF:large-1 @ ",
+        "Page(2 MiB): MR=ELF:large-1+0x1000 @ ",
+        "Page(4 KiB): MR=ELF:large-1+0x201000 @ ",
+    ] {
+        assert!(report.contains(object), "'{}' is not in the report", object);
+    }
+
+    // Each part of the segment is a separate region in the image
+    let image_data = fs::read(sdk.join("loader.img")).unwrap();
+    let image = LoaderImage::parse(&image_data).unwrap();
+    let sizes: Vec<usize> = image.regions.iter().map(|(_, data)| data.len()).collect();
+    assert!(sizes.contains(&0x200000));
+    let head = image
+        .regions
+        .iter()
+        .find(|(_, data)| data.starts_with(b"large\0"))
+        .unwrap();
+    assert_eq!(head.1.len(), 0x1000);
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_cap_audit() {
+    let sdk = create_sdk("cap_audit");
+    let text = sdk.join("caps.txt");
+    let json = sdk.join("caps.json");
+    let output = build(
+        &sdk,
+        "sys_build.system",
+        &["--cap-audit", text.to_str().unwrap()],
+    );
+    check_success(&output);
+    let output = build(
+        &sdk,
+        "sys_build.system",
+        &[
+            "--cap-audit",
+            json.to_str().unwrap(),
+            "--cap-audit-format",
+            "json",
+        ],
+    );
+    check_success(&output);
+
+    let text = fs::read_to_string(text).unwrap();
+    for entry in [
+        "# Protection domain 'client'\n\n    slot   1 (INPUT_CAP_IDX): Notification: PD=client\n",
+        "    slot  75 (BASE_OUTPUT_ENDPOINT_CAP + 1): EP: PD=server\n        rights: RWGP  badge: 0x8000000000000001\n        ppc: PPC into PD 'server' on channel 1\n",
+        "    slot 138 (BASE_IRQ_CAP + 0): IRQ Handler: irq=33\n        rights: RWGP  badge: 0x0\n        irq: acknowledge IRQ 33 on channel 0\n",
+        "# Protection domain 'server'\n\n",
+        "    slot   5 (MONITOR_EP_CAP_IDX): EP: Monitor Fault\n",
+        "    slot  11 (BASE_OUTPUT_NOTIFICATION_CAP + 1): Notification: PD=client\n        rights: RWGP  badge: 0x2\n        channel: notify PD 'client' on channel 1\n",
+    ] {
+        assert!(text.contains(entry), "'{}' is not in the audit", entry);
+    }
+
+    let json: serde_json::Value = serde_json::from_str(&fs::read_to_string(json).unwrap()).unwrap();
+    let pds = json["protection_domains"].as_array().unwrap();
+    let names: Vec<&str> = pds.iter().map(|pd| pd["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["client", "server"]);
+    let kinds = |pd: &serde_json::Value| -> Vec<String> {
+        pd["caps"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cap| cap["kind"].as_str().unwrap().to_string())
+            .collect()
+    };
+    assert_eq!(
+        kinds(&pds[0]),
+        ["input", "vspace", "reply", "channel", "ppc", "irq"]
+    );
+    assert_eq!(
+        kinds(&pds[1]),
+        ["input", "vspace", "reply", "monitor", "channel"]
+    );
+    let ppc = &pds[0]["caps"][4];
+    assert_eq!(ppc["slot"], 75);
+    assert_eq!(ppc["slot_name"], "BASE_OUTPUT_ENDPOINT_CAP + 1");
+    assert_eq!(ppc["object"], "EP: PD=server");
+    assert_eq!(ppc["rights"], "RWGP");
+    assert_eq!(ppc["badge"], 0x8000000000000001_u64);
+    assert_eq!(ppc["reason"], "PPC into PD 'server' on channel 1");
+
+    fs::remove_dir_all(sdk).unwrap();
+}
+
+#[test]
+fn test_harden_shared_segments() {
+    let sdk = create_sdk("harden_shared_segments");
+    // Both PDs use the same program image, so its code segment is shared
+    let output = build(&sdk, "sys_build_harden.system", &[]);
+    check_success(&output);
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    assert!(report.contains("MR=ELF:first-0 @ "));
+    assert!(!report.contains("MR=ELF:second-0 @ "));
+
+    // unless hardening, as executable memory must not be shared
+    let output = build(&sdk, "sys_build_harden.system", &["--harden"]);
+    check_success(&output);
+    let report = fs::read_to_string(sdk.join("report.txt")).unwrap();
+    assert!(report.contains("MR=ELF:first-0 @ "));
+    assert!(report.contains("MR=ELF:second-0 @ "));
+
+    fs::remove_dir_all(sdk).unwrap();
+}
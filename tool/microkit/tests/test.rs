@@ -84,6 +84,28 @@ mod memory_region {
         )
     }
 
+    #[test]
+    fn test_huge_page_not_default() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/sdf/mr_huge_size_large_aligned.system");
+        let sdf = std::fs::read_to_string(path).unwrap();
+        let system = sdf::parse(
+            "mr_huge_size_large_aligned.system",
+            &sdf,
+            &DEFAULT_KERNEL_CONFIG,
+        )
+        .unwrap();
+        assert_eq!(system.memory_regions[0].page_size, sel4::PageSize::Large);
+    }
+
+    #[test]
+    fn test_huge_page_addr_not_aligned() {
+        check_error(
+            "mr_huge_page_addr_not_aligned.system",
+            "Error: phys_addr is not aligned to the page size on element 'memory_region'",
+        )
+    }
+
     #[test]
     fn test_missing_size() {
         check_missing("mr_missing_size.system", "size", "memory_region")
@@ -109,6 +131,62 @@ mod memory_region {
             "Error: memory region 'mr2' physical address range [0x9001000..0x9002000) overlaps with another memory region 'mr1' [0x9000000..0x9002000) @ ",
         )
     }
+
+    #[test]
+    fn test_source_with_phys_addr() {
+        check_error(
+            "mr_source_with_phys_addr.system",
+            "Error: source cannot be used with phys_addr on element 'memory_region': ",
+        )
+    }
+
+    #[test]
+    fn test_offset_without_source() {
+        check_error(
+            "mr_offset_without_source.system",
+            "Error: offset can only be used with source on element 'memory_region': ",
+        )
+    }
+
+    #[test]
+    fn test_source_offset_too_large() {
+        check_error(
+            "mr_source_offset_too_large.system",
+            "Error: offset (0x1000) must be less than the size (0x1000) on element 'memory_region': ",
+        )
+    }
+
+    #[test]
+    fn test_align_without_contiguous() {
+        check_error(
+            "mr_align_without_contiguous.system",
+            "Error: align can only be used with contiguous=\"true\" on element 'memory_region': ",
+        )
+    }
+
+    #[test]
+    fn test_contiguous_with_phys_addr() {
+        check_error(
+            "mr_contiguous_with_phys_addr.system",
+            "Error: contiguous cannot be used with phys_addr on element 'memory_region': ",
+        )
+    }
+
+    #[test]
+    fn test_contiguous_invalid_align() {
+        check_error(
+            "mr_contiguous_invalid_align.system",
+            "Error: align (0x1800) must be a power of two and at least the page size (0x1000) on element 'memory_region': ",
+        )
+    }
+
+    #[test]
+    fn test_contiguous_range_too_small() {
+        check_error(
+            "mr_contiguous_range_too_small.system",
+            "Error: range from phys_min to phys_max [0x60000000..0x60001000) cannot fit the size (0x2000) on element 'memory_region': ",
+        )
+    }
 }
 
 #[cfg(test)]
@@ -277,6 +355,62 @@ mod protection_domain {
         )
     }
 
+    #[test]
+    fn test_unaligned_stack_guard_size() {
+        check_error(
+            "pd_unaligned_stack_guard_size.system",
+            "Error: stack guard size must be aligned to the smallest page size",
+        )
+    }
+
+    #[test]
+    fn test_map_in_stack_guard() {
+        check_error(
+            "pd_map_in_stack_guard.system",
+            "Error: map for 'mr1' has virtual address range [0xffffffc000..0xffffffe000) which overlaps with the guard region of the stack [0xffffffd000..0xfffffff000) in protection domain 'hello' @",
+        )
+    }
+
+    #[test]
+    fn test_map_in_guard() {
+        check_error(
+            "pd_map_in_guard.system",
+            "Error: map for 'mr2' has virtual address range [0x1001000..0x1002000) which overlaps with the guard region of map for 'mr1' [0x1001000..0x1002000) in protection domain 'hello' @",
+        )
+    }
+
+    #[test]
+    fn test_map_guard_overlaps() {
+        check_error(
+            "pd_map_guard_overlaps.system",
+            "Error: guard region [0x1000000..0x1002000) of map for 'mr2' overlaps with map for 'mr1' [0x1000000..0x1001000) in protection domain 'hello' @",
+        )
+    }
+
+    #[test]
+    fn test_map_unaligned_guard() {
+        check_error(
+            "pd_map_unaligned_guard.system",
+            "Error: guard must be a multiple of the page size of memory region 'mr1' (0x1000) on 'map' @",
+        )
+    }
+
+    #[test]
+    fn test_unaligned_heap_size() {
+        check_error(
+            "pd_unaligned_heap_size.system",
+            "Error: heap size must be aligned to the smallest page size",
+        )
+    }
+
+    #[test]
+    fn test_map_in_heap() {
+        check_error(
+            "pd_map_in_heap.system",
+            "Error: map for 'mr1' has virtual address range [0xfffffec000..0xfffffee000) which overlaps with the heap [0xfffffed000..0xfffffff000) in protection domain 'hello' @",
+        )
+    }
+
     #[test]
     fn test_overlapping_maps() {
         check_error(
@@ -284,6 +418,53 @@ mod protection_domain {
             "Error: map for 'mr2' has virtual address range [0x1000000..0x1001000) which overlaps with map for 'mr1' [0x1000000..0x1001000) in protection domain 'hello' @"
         )
     }
+
+    #[test]
+    fn test_auto_priority() {
+        let mut path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("tests/sdf/pd_priority_auto.system");
+        let sdf = std::fs::read_to_string(path).unwrap();
+        let system = sdf::parse("pd_priority_auto.system", &sdf, &DEFAULT_KERNEL_CONFIG).unwrap();
+        let priorities: Vec<(&str, u8)> = system
+            .protection_domains
+            .iter()
+            .map(|pd| (pd.name.as_str(), pd.priority))
+            .collect();
+        assert_eq!(
+            priorities,
+            vec![
+                ("client", 0),
+                ("server", 1),
+                ("driver", 2),
+                ("logger", 1),
+                ("timer", 50)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_priority_cycle() {
+        check_error(
+            "pd_priority_cycle.system",
+            "Error: priority constraints form a cycle, each protection domain must have a lower priority than the next: client -> server -> client",
+        )
+    }
+
+    #[test]
+    fn test_priority_unsatisfiable() {
+        check_error(
+            "pd_priority_unsatisfiable.system",
+            "Error: priority constraints cannot be satisfied for protection domain 'server': it needs a priority of at least 11 and at most 10 @ pd_priority_unsatisfiable.system:11:5",
+        )
+    }
+
+    #[test]
+    fn test_above_invalid_pd() {
+        check_error(
+            "pd_above_invalid_pd.system",
+            "Error: invalid PD name 'client' in 'above' of protection domain 'server' @ pd_above_invalid_pd.system:8:5",
+        )
+    }
 }
 
 #[cfg(test)]
@@ -541,4 +722,99 @@ mod system {
             "Error: too many protection domains (64) defined. Maximum is 63.",
         )
     }
+
+    #[test]
+    fn test_reserved_memory_not_aligned() {
+        check_error(
+            "sys_reserved_memory_not_aligned.system",
+            "Error: base is not aligned to the page size on element 'reserved_memory': ",
+        )
+    }
+
+    #[test]
+    fn test_reserved_memory_zero_size() {
+        check_error(
+            "sys_reserved_memory_zero_size.system",
+            "Error: size must be a non-zero multiple of the page size on element 'reserved_memory': ",
+        )
+    }
+
+    #[test]
+    fn test_reserved_memory_overlap() {
+        check_error(
+            "sys_reserved_memory_overlap.system",
+            "Error: reserved memory [0x400ff000..0x40101000) overlaps with reserved memory [0x40000000..0x40100000) @ ",
+        )
+    }
+}
+
+#[cfg(test)]
+mod policy {
+    use super::*;
+
+    #[test]
+    fn test_forbidden_channel() {
+        check_error(
+            "policy_channel.system",
+            "Error: system violates its policy: channel between 'crypto' and 'network' is forbidden by the rule from 'crypto' to 'network' with flows 'channel' @ policy_channel.system:21:9",
+        )
+    }
+
+    #[test]
+    fn test_forbidden_shared_memory() {
+        check_error(
+            "policy_memory.system",
+            "Error: system violates its policy: memory region 'shared' is mapped by both 'crypto' and 'network' is forbidden by the rule from 'trusted' to 'network' with flows 'memory' @ policy_memory.system:23:9",
+        )
+    }
+
+    #[test]
+    fn test_forbidden_ppc_path() {
+        check_error(
+            "policy_ppc_path.system",
+            "Error: system violates its policy: protected procedure calls from network -> driver -> crypto is forbidden by the rule from 'network' to 'crypto' with flows 'ppc' @ policy_ppc_path.system:28:9",
+        )
+    }
+
+    #[test]
+    fn test_forbidden_child() {
+        check_error(
+            "policy_child.system",
+            "Error: system violates its policy: 'network' is a descendant of 'crypto' is forbidden by the rule from 'network' to 'crypto' with flows 'child' @ policy_child.system:19:9",
+        )
+    }
+
+    #[test]
+    fn test_multiple_violations() {
+        check_error(
+            "policy_multiple.system",
+            "Error: system violates its policy: channel between 'crypto' and 'network' is forbidden by the rule from 'crypto' to 'network' with flows 'channel,memory,ppc,child' @ policy_multiple.system:25:9\n\
+             Error: system violates its policy: memory region 'shared' is mapped by both 'crypto' and 'network' is forbidden by the rule from 'crypto' to 'network' with flows 'channel,memory,ppc,child' @ policy_multiple.system:25:9\n\
+             Error: system violates its policy: memory region 'shared' is mapped by both 'network' and 'crypto' is forbidden by the rule from 'network' to 'crypto' with flows 'memory' @ policy_multiple.system:26:9",
+        )
+    }
+
+    #[test]
+    fn test_invalid_flow() {
+        check_error(
+            "policy_invalid_flow.system",
+            "Error: invalid flow 'irq' (expected 'channel', 'memory', 'ppc' or 'child') on element 'forbid'",
+        )
+    }
+
+    #[test]
+    fn test_invalid_pd() {
+        check_error(
+            "policy_invalid_pd.system",
+            "Error: invalid PD or group name 'network' on element 'forbid'",
+        )
+    }
+
+    #[test]
+    fn test_duplicate_group_name() {
+        check_error(
+            "policy_duplicate_group.system",
+            "Error: duplicate PD or group name 'crypto' on element 'group'",
+        )
+    }
 }